@@ -0,0 +1,361 @@
+//! Column profiles for `analyze`: type, empty values, distinct values,
+//! range and examples, gathered in one pass over the whole input
+
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::json::Value;
+use crate::parse_datetime;
+use crate::schema::{self, ColumnType};
+
+/// Distinct values counted exactly up to this many, estimated past it
+const EXACT_DISTINCT: usize = 10_000;
+/// Examples kept per column
+const EXAMPLES: usize = 3;
+/// Bits of the hash choosing a HyperLogLog register: 4096 registers, for
+/// about 1.6% error
+const HLL_BITS: u32 = 12;
+
+pub struct Profile {
+    pub name: String,
+    /// Type as declared, or widened over every value
+    pub ty: Option<ColumnType>,
+    pub declared: bool,
+    pub empty: usize,
+    pub nonempty: usize,
+    exact: Option<HashSet<String>>,
+    registers: Vec<u8>,
+    numbers: Option<(f64, f64)>,
+    /// Range of the values while all of them are dates
+    dates: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    all_dates: bool,
+    pub examples: Vec<String>,
+}
+
+impl Profile {
+    pub fn new(name: &str, declared: Option<ColumnType>) -> Self {
+        Self {
+            name: name.to_string(),
+            ty: declared,
+            declared: declared.is_some(),
+            empty: 0,
+            nonempty: 0,
+            exact: Some(HashSet::new()),
+            registers: vec![0; 1 << HLL_BITS],
+            numbers: None,
+            dates: None,
+            all_dates: true,
+            examples: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, value: &str) {
+        if value.is_empty() {
+            self.empty += 1;
+            return;
+        }
+        self.nonempty += 1;
+        if !self.declared {
+            let detected = ColumnType::detect(value);
+            self.ty = Some(self.ty.map_or(detected, |t| t.widen(detected)));
+        }
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - HLL_BITS)) as usize;
+        let rank = ((hash << HLL_BITS) | 1 << (HLL_BITS - 1)).leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+        if let Some(exact) = &mut self.exact
+            && !exact.contains(value)
+        {
+            if exact.len() == EXACT_DISTINCT {
+                self.exact = None;
+            } else {
+                exact.insert(value.to_string());
+            }
+        }
+        if self.examples.len() < EXAMPLES && !self.examples.iter().any(|e| e == value) {
+            self.examples.push(value.to_string());
+        }
+        let number = match self.ty {
+            Some(ColumnType::Duration(unit)) => unit.of(value),
+            _ => f64::from_str(value).ok().filter(|f| f.is_finite()),
+        };
+        if let Some(n) = number {
+            let (min, max) = self.numbers.get_or_insert((n, n));
+            (*min, *max) = (min.min(n), max.max(n));
+        }
+        if self.all_dates {
+            match parse_datetime(value) {
+                Some(dt) => {
+                    let (min, max) = self.dates.get_or_insert((dt, dt));
+                    (*min, *max) = ((*min).min(dt), (*max).max(dt));
+                }
+                None => self.all_dates = false,
+            }
+        }
+    }
+
+    /// Distinct non-empty values, and whether that is an exact count
+    pub fn distinct(&self) -> (usize, bool) {
+        if let Some(exact) = &self.exact {
+            return (exact.len(), true);
+        }
+        let m = self.registers.len() as f64;
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let estimate = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        let estimate = match estimate <= 2.5 * m && zeros > 0 {
+            // few values: count the registers still empty instead
+            true => m * (m / zeros as f64).ln(),
+            false => estimate,
+        };
+        (estimate.round() as usize, false)
+    }
+
+    /// Smallest and largest value: numbers for numeric columns, dates for
+    /// columns of nothing but dates
+    pub fn range(&self) -> Option<(String, String)> {
+        if self.numeric()
+            && let Some((min, max)) = self.numbers
+        {
+            return Some((min.to_string(), max.to_string()));
+        }
+        let date = |dt: DateTime<Utc>| dt.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        match self.dates {
+            Some((min, max)) if self.all_dates => Some((date(min), date(max))),
+            _ => None,
+        }
+    }
+
+    fn numeric(&self) -> bool {
+        matches!(
+            self.ty,
+            Some(
+                ColumnType::Long
+                    | ColumnType::UnsignedLong
+                    | ColumnType::Double
+                    | ColumnType::Decimal
+                    | ColumnType::Duration(_)
+            )
+        )
+    }
+
+    pub fn to_json(&self, rows: usize) -> Value {
+        let (distinct, exact) = self.distinct();
+        let (min, max) = match self.range() {
+            Some((min, max)) if self.numeric() => (Value::Number(min), Value::Number(max)),
+            Some((min, max)) => (Value::String(min), Value::String(max)),
+            None => (Value::Null, Value::Null),
+        };
+        let number = |n: f64| Value::Number(n.to_string());
+        Value::Object(vec![
+            ("name".to_string(), Value::String(self.name.clone())),
+            (
+                "type".to_string(),
+                self.ty
+                    .map_or(Value::Null, |t| Value::String(t.to_string())),
+            ),
+            ("declared".to_string(), Value::Bool(self.declared)),
+            ("empty".to_string(), number(self.empty as f64)),
+            ("empty_rate".to_string(), number(rate(self.empty, rows))),
+            ("distinct".to_string(), number(distinct as f64)),
+            ("distinct_exact".to_string(), Value::Bool(exact)),
+            ("min".to_string(), min),
+            ("max".to_string(), max),
+            (
+                "examples".to_string(),
+                Value::Array(self.examples.iter().cloned().map(Value::String).collect()),
+            ),
+        ])
+    }
+}
+
+/// Distinct keywords past which a column is flagged, when most of its
+/// values are distinct too
+const HIGH_CARDINALITY: usize = 1000;
+
+/// Field types a cardinality aggregation works on
+pub const AGGREGATABLE: &[&str] = &[
+    "keyword",
+    "long",
+    "integer",
+    "short",
+    "byte",
+    "double",
+    "float",
+    "half_float",
+    "scaled_float",
+    "unsigned_long",
+    "date",
+    "date_nanos",
+    "ip",
+    "boolean",
+];
+
+/// A column next to the field of the destination index it would go to
+pub struct Check {
+    pub name: String,
+    /// Types the index maps the field as, none for a new field
+    pub mapped: Vec<String>,
+    /// Why the index can't take the column as it is written
+    pub conflicts: Vec<String>,
+    /// Distinct values of the field already in the index
+    pub index_distinct: Option<u64>,
+    /// Keywords nearly all different, each a new term for the index
+    pub high_cardinality: bool,
+}
+
+/// How the profiled columns fit an index with `mapping`, which is `None`
+/// when the index doesn't exist yet; `distinct` has the cardinality of its
+/// fields
+pub fn compare(
+    profiles: &[Profile],
+    mapping: Option<&Value>,
+    distinct: &HashMap<String, u64>,
+) -> Vec<Check> {
+    let fields = mapping.map(schema::field_types).unwrap_or_default();
+    profiles
+        .iter()
+        .map(|p| {
+            let values = p.nonempty;
+            let (count, _) = p.distinct();
+            Check {
+                name: p.name.clone(),
+                mapped: fields
+                    .iter()
+                    .filter(|(f, _)| *f == p.name)
+                    .map(|(_, t)| t.clone())
+                    .collect(),
+                conflicts: mapping.map_or_else(Vec::new, |m| {
+                    schema::mapping_conflicts(m, [(p.name.as_str(), p.ty)])
+                }),
+                index_distinct: distinct.get(&p.name).copied(),
+                high_cardinality: matches!(p.ty, Some(ColumnType::Keyword))
+                    && count >= HIGH_CARDINALITY
+                    && count * 2 >= values,
+            }
+        })
+        .collect()
+}
+
+impl Check {
+    pub fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("name".to_string(), Value::String(self.name.clone())),
+            ("new".to_string(), Value::Bool(self.mapped.is_empty())),
+            (
+                "mapped_as".to_string(),
+                Value::Array(self.mapped.iter().cloned().map(Value::String).collect()),
+            ),
+            (
+                "conflicts".to_string(),
+                Value::Array(self.conflicts.iter().cloned().map(Value::String).collect()),
+            ),
+            (
+                "index_distinct".to_string(),
+                self.index_distinct
+                    .map_or(Value::Null, |n| Value::Number(n.to_string())),
+            ),
+            (
+                "high_cardinality".to_string(),
+                Value::Bool(self.high_cardinality),
+            ),
+        ])
+    }
+}
+
+/// The findings of `compare` as lines to print; nothing for columns that
+/// fit
+pub fn findings(checks: &[Check], profiles: &[Profile]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let new: Vec<&str> = checks
+        .iter()
+        .filter(|c| c.mapped.is_empty())
+        .map(|c| c.name.as_str())
+        .collect();
+    if !new.is_empty() {
+        lines.push(format!("new fields: {}", new.join(", ")));
+    }
+    for (check, profile) in checks.iter().zip(profiles) {
+        lines.extend(check.conflicts.iter().cloned());
+        if check.high_cardinality {
+            let (count, exact) = profile.distinct();
+            let mut line = format!(
+                "column {}: {}{} distinct keywords in {} values",
+                check.name,
+                if exact { "" } else { "~" },
+                count,
+                profile.nonempty
+            );
+            if let Some(n) = check.index_distinct {
+                line.push_str(&format!(", next to ~{} in the index", n));
+            }
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Share of `rows`, in 0 to 1
+pub fn rate(n: usize, rows: usize) -> f64 {
+    match rows {
+        0 => 0.0,
+        _ => n as f64 / rows as f64,
+    }
+}
+
+/// The profiles as an aligned table, one column per line
+pub fn table(profiles: &[Profile], rows: usize) -> String {
+    let cut = |s: &str| match s.char_indices().nth(24) {
+        Some((i, _)) => format!("{}...", &s[..i]),
+        None => s.to_string(),
+    };
+    let mut lines = vec![
+        [
+            "COLUMN", "TYPE", "EMPTY", "DISTINCT", "MIN", "MAX", "EXAMPLES",
+        ]
+        .map(String::from),
+    ];
+    for p in profiles {
+        let ty = match (p.ty, p.declared) {
+            (Some(ty), true) => format!("{} (declared)", ty),
+            (Some(ty), false) => ty.to_string(),
+            (None, _) => "-".to_string(),
+        };
+        let (distinct, exact) = p.distinct();
+        let (min, max) = p.range().unwrap_or_else(|| ("-".into(), "-".into()));
+        let examples: Vec<String> = p.examples.iter().map(|e| cut(e)).collect();
+        lines.push([
+            p.name.clone(),
+            ty,
+            format!("{:.1}%", rate(p.empty, rows) * 100.0),
+            format!("{}{}", if exact { "" } else { "~" }, distinct),
+            cut(&min),
+            cut(&max),
+            examples.join(" | "),
+        ]);
+    }
+    let mut widths = [0; 7];
+    for line in &lines {
+        for (w, cell) in widths.iter_mut().zip(line) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for line in &lines {
+        let mut text = String::new();
+        for (i, (cell, w)) in line.iter().zip(widths).enumerate() {
+            match i {
+                6 => text.push_str(cell),
+                _ => text.push_str(&format!("{:<w$}  ", cell, w = w)),
+            }
+        }
+        out.push_str(text.trim_end());
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,1346 @@
+//! Command line: subcommands and their options, parsed with clap
+
+use std::ffi::OsString;
+use std::time::Duration;
+
+use clap::builder::RangedU64ValueParser;
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+
+use crate::export::OutputFormat;
+use crate::hook::HookErrorPolicy;
+use crate::schema::ColumnType;
+use crate::{
+    ClusterStatus, DedupeKeep, DuplicateHeaderPolicy, EsLookup, ExistingIndexPolicy, HashAlgorithm,
+    InvalidUtf8Policy, Mask, MergeStrategy, RaggedPolicy, RolloverConditions, expr, is_time_value,
+    tz,
+};
+
+#[derive(Parser)]
+#[command(
+    name = "elastic_importer",
+    version,
+    about = "Import CSV, fixed-width, XML, log and NDJSON files into Elasticsearch",
+    after_help = "Without a subcommand, the arguments are those of import."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Import a file into an index
+    // clap would list the index group ahead of FILE
+    #[command(
+        override_usage = "elastic_importer import [OPTIONS] <FILE> <INDEX_NAME|--index <NAME>|--index-column <COL>>"
+    )]
+    Import(Box<ImportArgs>),
+    /// Write the documents of an index to a CSV or NDJSON file
+    Export(ExportArgs),
+    /// Copy the documents of an index into another, possibly on another
+    /// cluster, with the document options of import applied on the way
+    Reindex(Box<ReindexArgs>),
+    /// Delete the documents of an index that match a query, after showing
+    /// how many there are
+    Delete(DeleteArgs),
+    /// Print the health, document count, size and field count of an index
+    Inspect(InspectArgs),
+    /// Read and type-check a file without sending anything, reporting every
+    /// row that doesn't fit
+    Validate(InputArgs),
+    /// Scan a file and print for each column its type, how many values
+    /// are empty, about how many are distinct, their range and examples
+    Analyze(Box<AnalyzeArgs>),
+    /// Print the index mapping made from a file's column types
+    Mapping(InputArgs),
+    /// Print a completion script for a shell
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+/// `elastic_importer FILE INDEX ...` stays a shorthand for `import`: when
+/// the first argument isn't a subcommand or a top-level flag, `import` is
+/// put in front of the rest.
+pub fn default_to_import(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut args: Vec<OsString> = args.into_iter().collect();
+    let known = [
+        "import",
+        "export",
+        "reindex",
+        "delete",
+        "inspect",
+        "validate",
+        "analyze",
+        "mapping",
+        "completions",
+        "help",
+        "-h",
+        "--help",
+        "-V",
+        "--version",
+    ];
+    match args.get(1).and_then(|a| a.to_str()) {
+        Some(first) if known.contains(&first) => {}
+        None => {}
+        Some(_) => args.insert(1, "import".into()),
+    }
+    args
+}
+
+/// Input file formats
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Comma-separated values with a header line
+    Csv,
+    /// Fixed-width columns, see --widths
+    Fixed,
+    /// Repeated elements of an XML document, see --record-path
+    Xml,
+    /// Lines split by a regex, see --pattern
+    Log,
+    /// Web server access logs, see --preset
+    Accesslog,
+    /// One JSON object per line
+    Ndjson,
+    /// RFC 3164/5424 lines split into timestamp, host, facility, severity,
+    /// appname, procid, msgid, structured_data and message
+    Syslog,
+    /// A _bulk request body, action lines each with the document line after
+    /// it, sent as it is; FILE may be - for stdin
+    Bulk,
+}
+
+/// Reading and typing the input file
+#[derive(clap::Args)]
+pub struct InputArgs {
+    /// File to read, or - for stdin
+    pub file: String,
+
+    #[command(flatten)]
+    pub format: FormatArgs,
+
+    #[command(flatten)]
+    pub types: TypeArgs,
+
+    /// Load a WebAssembly plugin that reads the input file as NDJSON or
+    /// transforms every document after --script; see src/plugin.rs for its
+    /// ABI
+    #[arg(long, value_name = "FILE.wasm")]
+    pub plugin: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[command(next_help_heading = "Input format")]
+pub struct FormatArgs {
+    /// Input format
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    pub format: Format,
+
+    /// For --format xml, the elements that are records; their attributes
+    /// and child elements become fields, nested ones named like
+    /// address.city
+    #[arg(long, value_name = "/A/B/C")]
+    pub record_path: Option<String>,
+
+    /// For --format log, a regex whose named groups (?P<field>...) become
+    /// fields; %{NAME} inserts a grok pattern such as TIMESTAMP_ISO8601,
+    /// LOGLEVEL or COMBINEDAPACHELOG, %{NAME:field} captures it
+    #[arg(long)]
+    pub pattern: Option<String>,
+
+    /// For --format accesslog, the web server log layout: nginx,
+    /// apache-combined or apache-common; yields client_ip, user,
+    /// timestamp, verb, path, http_version, status, bytes, referrer and
+    /// user_agent
+    #[arg(long, value_name = "NAME")]
+    pub preset: Option<String>,
+
+    /// Column widths in characters for --format fixed, optionally named as
+    /// name:W; with names there is no header line
+    #[arg(long, value_name = "W[,W]", conflicts_with = "widths_file")]
+    pub widths: Option<String>,
+
+    /// Read the widths from FILE, one column per line as "name width"
+    #[arg(long, value_name = "FILE")]
+    pub widths_file: Option<String>,
+
+    /// Padding trimmed from fixed-width fields
+    #[arg(long, value_name = "CHAR", default_value_t = ' ')]
+    pub pad_char: char,
+
+    /// For --format ndjson, turn nested objects into dot-joined fields like
+    /// address.city
+    #[arg(long)]
+    pub flatten: bool,
+
+    /// With --flatten, join at most N levels of keys and keep deeper
+    /// objects as they are
+    #[arg(long, value_name = "N", requires = "flatten", value_parser = positive())]
+    pub max_depth: Option<usize>,
+
+    /// Skip lines starting with CHAR
+    #[arg(long, value_name = "CHAR", value_parser = ascii_char)]
+    pub comment: Option<u8>,
+
+    /// What to do with records whose field count differs from the header;
+    /// pad only fixes short records and truncate only long ones
+    #[arg(long, value_enum, default_value_t = RaggedPolicy::Error)]
+    pub on_ragged: RaggedPolicy,
+
+    /// What to do with bytes of the input that aren't valid UTF-8
+    #[arg(long, value_enum, default_value_t = InvalidUtf8Policy::Error)]
+    pub invalid_utf8: InvalidUtf8Policy,
+
+    /// Reject repeated column names or rename them name_2, name_3, ...
+    #[arg(long, value_enum, default_value_t = DuplicateHeaderPolicy::Error)]
+    pub on_duplicate_header: DuplicateHeaderPolicy,
+
+    /// Lowercase column names and replace spaces and special characters
+    /// with underscores
+    #[arg(long)]
+    pub sanitize_headers: bool,
+
+    /// Guess the CSV delimiter (, ; tab or |), quote character, line
+    /// endings and whether there is a header line from the first 16 KB,
+    /// and print what was decided; without a header columns are named
+    /// field_1, field_2, ...
+    #[arg(long)]
+    pub sniff: bool,
+
+    /// Memory-map the input file and parse it in place instead of reading
+    /// it into memory; the file must not change during the import
+    #[arg(long)]
+    pub mmap: bool,
+}
+
+#[derive(clap::Args)]
+#[command(next_help_heading = "Values and types")]
+pub struct TypeArgs {
+    /// Strip thousands separators, spaces and currency symbols from these
+    /// columns before typing them: $1,299.00 -> 1299
+    #[arg(long, value_name = "COL[,COL]", value_delimiter = ',')]
+    pub numeric_cleanup: Vec<String>,
+
+    /// Turn sizes and counts with unit suffixes in these columns into
+    /// plain numbers: 1.5GB -> 1610612736 (KB, MB, GB, TB and PB counting
+    /// in 1024s, as KiB and the like do), 2.3k -> 2300 (k, M, G and T in
+    /// 1000s); other values are left as they are
+    #[arg(long, value_name = "COL[,COL]", value_delimiter = ',')]
+    pub expand_units: Vec<String>,
+
+    /// Remove control characters other than newline and tab, such as NUL
+    /// and 0x1A, from all values
+    #[arg(long)]
+    pub strip_control_chars: bool,
+
+    /// Use VALUE for empty cells in COL; can be repeated
+    #[arg(long = "default", value_name = "COL=VALUE", value_parser = key_value)]
+    pub defaults: Vec<(String, String)>,
+
+    /// Add a field computed from each row, e.g. total='price * quantity';
+    /// can be repeated
+    ///
+    /// Expressions use column names (`quoted` if they contain spaces),
+    /// numbers, 'strings', + - * / %, == != < <= > >=, && || ! and the
+    /// functions concat, coalesce, if(cond, a, b), upper, lower, trim,
+    /// length, substr(s, start[, len]), replace(s, from, to), round(x[,
+    /// digits]), floor, ceil, abs, min and max. Empty cells are null, and
+    /// arithmetic on null or non-numeric values gives an empty result.
+    #[arg(long, value_name = "NAME=EXPRESSION", value_parser = computed)]
+    pub compute: Vec<(String, expr::Expr)>,
+
+    /// Enrich the rows from FILE, a small CSV read into memory: each row
+    /// gets the --lookup-fields of the FILE row with the same --lookup-key
+    /// value, or empty ones without a match
+    #[arg(long, value_name = "FILE", requires_all = ["lookup_key", "lookup_fields"])]
+    pub lookup: Option<String>,
+
+    /// Column of both the input and the --lookup file to match rows on
+    #[arg(long, value_name = "COL", requires = "lookup")]
+    pub lookup_key: Option<String>,
+
+    /// Columns of the --lookup file to add to the rows
+    #[arg(
+        long,
+        value_name = "COL[,COL]",
+        value_delimiter = ',',
+        requires = "lookup"
+    )]
+    pub lookup_fields: Vec<String>,
+
+    /// Add the continent, country, region, city and location (a
+    /// geo_point) of the IP address in COL as geoip.* fields, looked up
+    /// in --geoip-db without the cluster's geoip processor
+    #[arg(long, value_name = "COL", requires = "geoip_db")]
+    pub geoip: Option<String>,
+
+    /// MaxMind DB file for --geoip, e.g. GeoLite2-City.mmdb
+    #[arg(long, value_name = "FILE", requires = "geoip")]
+    pub geoip_db: Option<String>,
+
+    /// Mask the values of COL before they are sent: partial, redact or
+    /// hash; can be repeated
+    ///
+    /// partial stars out all but the first character of an email's local
+    /// part, or all but the last four letters and digits of other values;
+    /// redact writes [REDACTED]; hash writes the SHA-256, which keeps equal
+    /// values equal but is easy to reverse for short or guessable values.
+    /// Masks apply to --compute fields too, after they are computed.
+    #[arg(long = "mask", value_name = "COL=MASK", value_parser = mask)]
+    pub masks: Vec<(String, Mask)>,
+
+    /// Cut the values of COL, or without COL= those of every column, down
+    /// to SIZE bytes at a character boundary, e.g. description=32766 to
+    /// stay under the keyword limit; can be repeated
+    #[arg(long = "truncate-field", value_name = "[COL=]SIZE", value_parser = truncation)]
+    pub truncate_fields: Vec<(Option<String>, usize)>,
+
+    /// Read date-times without an offset in COL as local times of ZONE
+    /// and send them as UTC, e.g. created_at=America/New_York; ZONE is an
+    /// IANA name, UTC or an offset like +05:30; can be repeated
+    #[arg(long = "tz-column", value_name = "COL=ZONE", value_parser = tz_column)]
+    pub tz_columns: Vec<(String, tz::Zone)>,
+
+    /// Time zone of date-times without an offset in the columns not given
+    /// a --tz-column
+    #[arg(long, value_name = "ZONE", value_parser = tz::Zone::parse)]
+    pub input_tz: Option<tz::Zone>,
+
+    /// Replace the values of these columns with pseudonyms keyed by
+    /// --anonymize-key; the same value and key always give the same
+    /// pseudonym, so the columns still join across imports
+    #[arg(long, value_name = "COL[,COL]", value_delimiter = ',')]
+    pub anonymize: Vec<String>,
+
+    /// Secret key of --anonymize pseudonyms; $ANONYMIZE_KEY if not given
+    #[arg(long, value_name = "SECRET", requires = "anonymize")]
+    pub anonymize_key: Option<String>,
+
+    /// Decide one type per column from N sample rows, widening conflicts
+    /// (long+double -> double, other mixes -> string); 0 types each value
+    /// on its own
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    pub infer_sample: usize,
+
+    /// Sample rows at random from the whole input instead of taking the
+    /// first N
+    #[arg(long)]
+    pub infer_random: bool,
+
+    /// Declared column types, e.g. {"price": "double", "sku": "keyword"}
+    ///
+    /// Types are boolean, long, unsigned_long, double, keyword, decimal
+    /// (kept exact, as a string), date (written as ISO 8601), object
+    /// (JSON written as it is), dense_vector (a JSON array or ;-separated
+    /// numbers, optionally with dims and similarity as in
+    /// dense_vector(384,cosine)), duration (1h30m, 00:02:35, 450ms or
+    /// PT1H30M written as a number of milliseconds, or of the unit in
+    /// duration(s); ns, us, ms, s, m, h or d), geo_point ("lat,lon"),
+    /// geo_shape (WKT written as GeoJSON) and ip, the last two also
+    /// detected; integers beyond a long are strings unless declared
+    /// unsigned_long.
+    #[arg(long, value_name = "FILE")]
+    pub schema: Option<String>,
+
+    /// Declare the type of one column, as in --schema; can be repeated
+    #[arg(long = "type", value_name = "COL=TYPE", value_parser = declared_type)]
+    pub types: Vec<(String, ColumnType)>,
+
+    /// Skip and report rows whose values don't fit the type declared in
+    /// --schema or --type instead of indexing them as strings
+    #[arg(long)]
+    pub strict: bool,
+}
+
+/// Importing into an index
+#[derive(clap::Args)]
+#[command(group(
+    ArgGroup::new("target")
+        .required(true)
+        .args(["index_name", "index", "index_column"])
+))]
+pub struct ImportArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Index to write to
+    ///
+    /// The name may contain {now/d} style date math or {column:%Y.%m}
+    /// placeholders formatting a date column, e.g.
+    /// "events-{created_at:%Y.%m}".
+    pub index_name: Option<String>,
+
+    /// Target index, instead of INDEX_NAME
+    #[arg(long, value_name = "NAME", help_heading = "Documents")]
+    pub index: Option<String>,
+
+    /// Route each row to the index named by COL
+    #[arg(long, value_name = "COL", help_heading = "Documents")]
+    pub index_column: Option<String>,
+
+    /// Prefix for --index-column index names
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        requires = "index_column",
+        help_heading = "Documents"
+    )]
+    pub index_prefix: Option<String>,
+
+    /// Make consecutive rows with the same COL value one document, see
+    /// --nest
+    #[arg(
+        long,
+        value_name = "COL",
+        requires = "nest",
+        conflicts_with_all = ["sample", "sample_rows"],
+        help_heading = "Documents"
+    )]
+    pub group_by: Option<String>,
+
+    /// With --group-by, gather columns of the group's rows into an array
+    /// of objects, e.g. items:product,qty,price; can be repeated
+    #[arg(
+        long,
+        value_name = "NAME:A,B",
+        value_parser = nest,
+        requires = "group_by",
+        help_heading = "Documents"
+    )]
+    pub nest: Vec<(String, Vec<String>)>,
+
+    /// Make all rows with the same COL value one document, wherever they
+    /// are in the input; the documents are sent once it has been read
+    #[arg(
+        long,
+        value_name = "COL",
+        conflicts_with_all = ["group_by", "sample", "sample_rows"],
+        help_heading = "Documents"
+    )]
+    pub merge_by: Option<String>,
+
+    /// How --merge-by combines the other columns: keep the last non-empty
+    /// value, or gather the non-empty values into an array
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = MergeStrategy::Last,
+        requires = "merge_by",
+        help_heading = "Documents"
+    )]
+    pub merge_strategy: MergeStrategy,
+
+    /// Read the file named in COL into a base64 "data" field for the
+    /// attachment ingest pipeline; relative paths are from the input
+    /// file's directory
+    #[arg(long, value_name = "COL", help_heading = "Documents")]
+    pub attach_column: Option<String>,
+
+    /// Ingest pipeline for --attach-column, created with an attachment
+    /// processor if it doesn't exist
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "attachment",
+        requires = "attach_column",
+        help_heading = "Documents"
+    )]
+    pub attach_pipeline: String,
+
+    #[command(flatten)]
+    pub documents: DocumentArgs,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    #[command(flatten)]
+    pub bulk: BulkArgs,
+
+    /// Record progress in FILE after each batch and resume from it when
+    /// rerun. Ctrl-C or SIGTERM stops after the batches being sent, so the
+    /// checkpoint is current; a second one quits at once
+    #[arg(
+        long,
+        value_name = "FILE",
+        help_heading = "Bulk requests",
+        conflicts_with_all = ["sample", "sample_rows", "bench", "preview", "output", "recreate", "truncate",
+            "rollback_on_failure", "staged", "merge_by"]
+    )]
+    pub checkpoint: Option<String>,
+
+    /// Skip rows whose --id-column value is already in the index, looked
+    /// up a batch at a time; resumes an import without a checkpoint file
+    #[arg(
+        long,
+        requires = "id_column",
+        help_heading = "Bulk requests",
+        conflicts_with_all = ["bench", "preview", "output", "recreate", "truncate", "rollback_on_failure",
+            "staged"]
+    )]
+    pub resume_from_index: bool,
+
+    #[command(flatten)]
+    pub setup: SetupArgs,
+}
+
+/// Copying documents from one index to another
+#[derive(clap::Args)]
+pub struct ReindexArgs {
+    /// Index, alias or pattern to read
+    pub src: String,
+
+    /// Index to write to; may contain placeholders as for import
+    pub dst: String,
+
+    /// Only copy the documents matching the query of this search body
+    #[arg(long, value_name = "FILE")]
+    pub query: Option<String>,
+
+    /// Read the source index in N slices side by side
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = positive())]
+    pub slices: usize,
+
+    #[command(flatten)]
+    pub types: TypeArgs,
+
+    /// Load a WebAssembly plugin that transforms every document after
+    /// --script
+    #[arg(long, value_name = "FILE.wasm")]
+    pub plugin: Option<String>,
+
+    #[command(flatten)]
+    pub documents: DocumentArgs,
+
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
+    #[command(flatten)]
+    pub bulk: BulkArgs,
+
+    #[command(flatten)]
+    pub setup: SetupArgs,
+}
+
+#[derive(clap::Args)]
+#[command(next_help_heading = "Documents")]
+pub struct DocumentArgs {
+    /// Use the value of COL as the document _id
+    #[arg(long, value_name = "COL", conflicts_with = "id_template")]
+    pub id_column: Option<String>,
+
+    /// Build the _id from several columns, e.g. "{country}-{order_id}"
+    #[arg(long, value_name = "TEMPLATE")]
+    pub id_template: Option<String>,
+
+    /// Use a hash of the row content as the _id
+    #[arg(long, value_enum, conflicts_with_all = ["id_column", "id_template"])]
+    pub id_hash: Option<HashAlgorithm>,
+
+    /// Only hash these columns
+    #[arg(long, value_name = "A,B", value_delimiter = ',', requires = "id_hash")]
+    pub id_hash_columns: Vec<String>,
+
+    /// Add the ingestion time (UTC, ISO 8601) to every document as NAME
+    #[arg(long, value_name = "NAME")]
+    pub timestamp_field: Option<String>,
+
+    /// Identify this import as ID in log lines and as the X-Opaque-Id of
+    /// its requests (unless --opaque-id is given); one is made up if not
+    #[arg(long, value_name = "ID", value_parser = header_value)]
+    pub run_id: Option<String>,
+
+    /// Add the run ID to every document as NAME
+    #[arg(long, value_name = "NAME")]
+    pub run_id_field: Option<String>,
+
+    /// Add fields of documents in another index to each document with the
+    /// same key value, looked up with one search per batch, e.g.
+    /// index=users,key=user_id,fields=email,plan; the key must be a keyword
+    /// or numeric field of that index
+    #[arg(
+        long,
+        value_name = "index=INDEX,key=FIELD,fields=F[,F]",
+        conflicts_with_all = ["bench", "preview", "output"]
+    )]
+    pub es_lookup: Option<EsLookup>,
+
+    /// Write dotted column names like address.city as nested objects
+    #[arg(long)]
+    pub unflatten: bool,
+
+    /// Run this Rhai script on every document, which it sees as the map
+    /// `doc`: change it, set it to () to drop it, or end in an array of
+    /// maps to split it; `_id` and `_index` keys set the document metadata
+    #[arg(long, value_name = "FILE")]
+    pub script: Option<String>,
+
+    /// Import a random P percent of the rows
+    #[arg(long, value_name = "P%", value_parser = percent, conflicts_with = "sample_rows")]
+    pub sample: Option<f64>,
+
+    /// Import N rows picked at random, kept in input order
+    #[arg(long, value_name = "N", value_parser = positive())]
+    pub sample_rows: Option<usize>,
+
+    /// Drop rows with the same values in these columns as an earlier row,
+    /// or with --keep last as a later one
+    #[arg(
+        long,
+        value_name = "COL[,COL]",
+        value_delimiter = ',',
+        conflicts_with = "sample_rows"
+    )]
+    pub dedupe_by: Vec<String>,
+
+    /// Which row of those sharing a --dedupe-by key to import
+    #[arg(long, value_enum, default_value_t = DedupeKeep::First, requires = "dedupe_by")]
+    pub keep: DedupeKeep,
+}
+
+#[derive(clap::Args)]
+#[command(next_help_heading = "Connection")]
+pub struct ConnectionArgs {
+    /// Elasticsearch URL, http:// or https://; without a port, http uses
+    /// 9200 and https 443
+    #[arg(long, value_name = "URL", default_value = "http://localhost:9200")]
+    pub host: String,
+
+    /// Basic auth user, with --pass, --pass-file, --credential-profile or
+    /// $ELASTIC_PASSWORD; without any the password is taken from a ~/.netrc
+    /// entry for the host or else asked for on the terminal
+    ///
+    /// With neither --user nor --api-key the login too may come from
+    /// ~/.netrc ($NETRC), as with curl.
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Basic auth password
+    #[arg(long, requires = "user")]
+    pub pass: Option<String>,
+
+    /// Read the --user password from FILE, such as a mounted Docker or
+    /// Kubernetes secret [default: $ELASTIC_PASSWORD_FILE]
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "user",
+        conflicts_with_all = ["pass", "credential_profile"]
+    )]
+    pub pass_file: Option<String>,
+
+    /// Read the --user password from the OS keyring entry PROFILE instead
+    /// of --pass, which shows up in ps output and shell history; without
+    /// --user the entry holds an API key
+    ///
+    /// Store it with `secret-tool store --label=elastic_importer service
+    /// elastic_importer profile PROFILE` (Secret Service, on Linux),
+    /// `security add-generic-password -s elastic_importer -a PROFILE -w`
+    /// (macOS Keychain) or `cmdkey /generic:elastic_importer:PROFILE
+    /// /user:PROFILE /pass` (Windows Credential Manager).
+    #[arg(
+        long,
+        value_name = "PROFILE",
+        conflicts_with_all = ["pass", "api_key", "api_key_file", "aws_sigv4"]
+    )]
+    pub credential_profile: Option<String>,
+
+    /// API key to authenticate with, as base64 id:key [default:
+    /// $ELASTIC_API_KEY]
+    #[arg(long, value_name = "KEY", conflicts_with_all = ["user", "aws_sigv4"])]
+    pub api_key: Option<String>,
+
+    /// Read the --api-key from FILE [default: $ELASTIC_API_KEY_FILE]
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["user", "aws_sigv4", "api_key"]
+    )]
+    pub api_key_file: Option<String>,
+
+    /// Sign requests with AWS SigV4 for Amazon OpenSearch Service, using
+    /// credentials from the environment, ~/.aws/credentials or the
+    /// container/instance role
+    #[arg(long, conflicts_with = "user")]
+    pub aws_sigv4: bool,
+
+    /// Region to sign for [default: $AWS_REGION]
+    #[arg(long, value_name = "REGION", requires = "aws_sigv4")]
+    pub aws_region: Option<String>,
+
+    /// Service to sign for: es, or aoss for OpenSearch Serverless
+    #[arg(long, value_name = "NAME", default_value = "es")]
+    pub aws_service: String,
+
+    /// PEM certificate for mutual TLS
+    #[arg(long, value_name = "FILE", requires = "client_key")]
+    pub client_cert: Option<String>,
+
+    /// PEM key for --client-cert
+    #[arg(long, value_name = "FILE", requires = "client_cert")]
+    pub client_key: Option<String>,
+
+    /// Trust the CAs in this PEM bundle instead of the built-in roots
+    #[arg(long, value_name = "FILE")]
+    pub ca_cert: Option<String>,
+
+    /// Don't verify the server certificate
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// Print each request line and its headers, with credentials blanked
+    /// out, and each response status, headers and the start of its body on
+    /// stderr
+    #[arg(long)]
+    pub debug_http: bool,
+
+    /// User-Agent to send instead of elastic_importer/VERSION
+    #[arg(long, value_name = "STR", value_parser = header_value)]
+    pub user_agent: Option<String>,
+
+    /// X-Opaque-Id to send with every request, naming the job in ES slow
+    /// logs, task lists and audit logs
+    #[arg(long, value_name = "ID", value_parser = header_value)]
+    pub opaque_id: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[command(next_help_heading = "Bulk requests")]
+pub struct BulkArgs {
+    /// Documents per bulk request
+    #[arg(long, value_name = "N", default_value_t = 1000, value_parser = positive())]
+    pub batch_size: usize,
+
+    /// Start at --batch-size and grow batches while bulk requests finish
+    /// within --target-bulk-ms without rejections; halve them whenever the
+    /// cluster rejects documents, fails or is slower
+    #[arg(long, conflicts_with_all = ["bench", "preview", "output"])]
+    pub adaptive_batch_size: bool,
+
+    /// Bulk request time --adaptive-batch-size aims to stay under
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 2000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "adaptive_batch_size"
+    )]
+    pub target_bulk_ms: u64,
+
+    /// Skip, with a warning, documents whose bulk item is over SIZE (e.g.
+    /// 1mb) rather than send a batch ES may refuse as too large
+    #[arg(long, value_name = "SIZE", value_parser = byte_size)]
+    pub max_doc_bytes: Option<usize>,
+
+    /// Write the documents skipped by --max-doc-bytes to FILE, as the bulk
+    /// action and document lines they would have been sent as
+    #[arg(long, value_name = "FILE", requires = "max_doc_bytes")]
+    pub dead_letter: Option<String>,
+
+    /// Bulk requests in flight at once; batches may complete out of order
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = positive())]
+    pub concurrency: usize,
+
+    /// Shard copies that must be active before each bulk request proceeds
+    #[arg(long, value_name = "N|all", value_parser = active_shards)]
+    pub wait_for_active_shards: Option<String>,
+
+    /// How long each bulk request waits for active shards, e.g. 1m
+    #[arg(long, value_name = "TIME", value_parser = time_value)]
+    pub bulk_timeout: Option<String>,
+
+    /// Resend documents rejected by a busy cluster up to N times
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub max_retries: u32,
+
+    /// Abort after N consecutive failed bulk requests (connection errors,
+    /// 5xx), pausing longer after each
+    #[arg(long, value_name = "N", default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_failures: u32,
+
+    /// Treat the index as a write alias and call _rollover between batches;
+    /// COND is a doc count (10000000), size (50gb) or age (7d)
+    #[arg(long, value_name = "COND[,COND]")]
+    pub rollover_after: Option<RolloverConditions>,
+
+    /// Parse and serialize the input as usual but discard the batches
+    /// instead of sending them, then report rows/s and MB/s
+    #[arg(long, conflicts_with = "rollover_after")]
+    pub bench: bool,
+
+    /// Print the bulk action and document lines of the first N documents,
+    /// with all types and transforms applied, and exit without contacting
+    /// ES
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = positive(),
+        conflicts_with_all = ["bench", "rollover_after"]
+    )]
+    pub preview: Option<usize>,
+
+    /// Write the bulk requests to FILE, or - for stdout, instead of sending
+    /// them, e.g. to pipe into gzip or curl --data-binary @-
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["bench", "preview", "rollover_after", "tui"]
+    )]
+    pub output: Option<String>,
+
+    /// Show a live dashboard of throughput, workers, retries and messages
+    /// instead of printing progress (needs the tui feature)
+    #[arg(long, conflicts_with_all = ["preview", "debug_http"])]
+    pub tui: bool,
+
+    /// Warn about each bulk request taking longer than MS milliseconds,
+    /// with the time ES reports and the node that answered
+    #[arg(
+        long,
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["bench", "preview", "output"]
+    )]
+    pub slow_bulk_ms: Option<u64>,
+
+    /// Save the body of each failed bulk request and the response to it in
+    /// DIR, as TIME-N.request.ndjson and TIME-N.response.txt
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["bench", "preview", "output"])]
+    pub capture_dir: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[command(next_help_heading = "Index setup")]
+#[command(group(ArgGroup::new("destructive").multiple(true).args(["recreate", "truncate"])))]
+pub struct SetupArgs {
+    /// Before importing, send the ES request in FILE, e.g. {"method":
+    /// "PUT", "path": "/_ingest/pipeline/p", "body": {...}}, or run
+    /// COMMAND with $ES_HOST and $ES_INDEX set; can be repeated
+    #[arg(long, value_name = "FILE|!COMMAND", conflicts_with_all = ["bench", "preview", "output"])]
+    pub pre_hook: Vec<String>,
+
+    /// The same after a complete import, e.g. to call _refresh or
+    /// _forcemerge
+    #[arg(long, value_name = "FILE|!COMMAND", conflicts_with_all = ["bench", "preview", "output"])]
+    pub post_hook: Vec<String>,
+
+    /// Stop when a hook fails, or report it and carry on
+    #[arg(long, value_enum, default_value_t = HookErrorPolicy::Abort)]
+    pub on_hook_error: HookErrorPolicy,
+
+    /// What to do when a fixed index name already exists
+    #[arg(long, value_enum, default_value_t = ExistingIndexPolicy::Prompt)]
+    pub on_existing_index: ExistingIndexPolicy,
+
+    /// Same as --on-existing-index append
+    #[arg(long, conflicts_with = "on_existing_index")]
+    pub append: bool,
+
+    /// Stop, rather than warn, when a column's type doesn't fit the
+    /// existing mapping of the index (e.g. keyword into long)
+    #[arg(long)]
+    pub strict_mapping: bool,
+
+    /// Delete the index and create it again with a mapping of the column
+    /// types before importing; asks first unless --yes is given
+    #[arg(long, conflicts_with_all = ["bench", "preview", "output", "rollover_after"])]
+    pub recreate: bool,
+
+    /// Index settings (shards, analysis, codec...) to create the index with,
+    /// as a JSON object; used by --recreate and when the index doesn't exist
+    /// yet
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["bench", "preview", "output"])]
+    pub settings: Option<String>,
+
+    /// Install component template NAME (the file name without .json if not
+    /// given) from FILE before importing; can be repeated
+    #[arg(
+        long,
+        value_name = "[NAME=]FILE",
+        value_parser = named_file,
+        conflicts_with_all = ["bench", "preview", "output"]
+    )]
+    pub component_template: Vec<(String, String)>,
+
+    /// Install composable index template NAME from FILE before importing,
+    /// composed of the --component-template ones unless it says otherwise
+    #[arg(
+        long,
+        value_name = "[NAME=]FILE",
+        value_parser = named_file,
+        conflicts_with_all = ["bench", "preview", "output"]
+    )]
+    pub index_template: Option<(String, String)>,
+
+    /// Mapping to create the index with in place of the one of the column
+    /// types, as a JSON object with properties; columns are still checked
+    /// against it
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["bench", "preview", "output"])]
+    pub mapping: Option<String>,
+
+    /// Delete all documents in the index before importing, for full
+    /// snapshots; asks first unless --yes is given
+    #[arg(long, conflicts_with_all = ["bench", "preview", "output", "recreate"])]
+    pub truncate: bool,
+
+    /// Only delete the documents matching this _delete_by_query body, e.g.
+    /// {"query": {"term": {"source": "feed"}}}
+    #[arg(long, value_name = "FILE", requires = "truncate")]
+    pub truncate_query: Option<String>,
+
+    /// Snapshot the index to repository REPO, and wait for it, before
+    /// --recreate or --truncate deletes anything
+    #[arg(long, value_name = "REPO", requires = "destructive")]
+    pub snapshot_repo: Option<String>,
+
+    /// If the import aborts or is interrupted, delete the index when this
+    /// run created it, or else the documents it created (not the ones it
+    /// overwrote)
+    #[arg(long, conflicts_with_all = ["bench", "preview", "output"])]
+    pub rollback_on_failure: bool,
+
+    /// After a complete import, point alias NAME at the index alone, taking
+    /// it off the indices it was on in the same _aliases request
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["bench", "preview", "output", "rollover_after"])]
+    pub alias: Option<String>,
+
+    /// Import into a new index, INDEX_NAME with -YYYYMMDDHHMMSS appended,
+    /// and only move --alias to it once its document count matches the
+    /// documents imported
+    #[arg(long, requires = "alias", conflicts_with_all = ["destructive", "append"])]
+    pub staged: bool,
+
+    /// Delete the indices --alias was on before, once it has moved
+    #[arg(long, requires = "alias")]
+    pub delete_old: bool,
+
+    /// Don't ask for confirmation
+    #[arg(long)]
+    pub yes: bool,
+
+    /// Set number_of_replicas of the index to 0 for the import, creating
+    /// the index first if need be, and put it back afterwards, also when the
+    /// import fails
+    #[arg(long, conflicts_with_all = ["bench", "preview", "output", "rollover_after"])]
+    pub tune_replicas: bool,
+
+    /// Set refresh_interval of the index to -1 for the import, then put it
+    /// back and refresh the index, also when the import fails
+    #[arg(long, conflicts_with_all = ["bench", "preview", "output", "rollover_after"])]
+    pub tune_refresh: bool,
+
+    /// After a complete import, force merge the index down to N segments
+    /// (1 if not given) and wait for it, for data that won't change
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_missing_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["bench", "preview", "output", "rollover_after"]
+    )]
+    pub forcemerge: Option<u32>,
+
+    /// Before sending anything, wait for _cluster/health to reach STATUS,
+    /// riding out a cluster that is still starting or recovering
+    #[arg(long, value_name = "STATUS", conflicts_with_all = ["bench", "preview", "output"])]
+    pub wait_for_status: Option<ClusterStatus>,
+
+    /// How long --wait-for-status waits before giving up, e.g. 90s or 5m
+    #[arg(
+        long,
+        value_name = "TIME",
+        default_value = "5m",
+        value_parser = duration,
+        requires = "wait_for_status"
+    )]
+    pub wait_timeout: Duration,
+}
+
+/// Exporting an index
+#[derive(clap::Args)]
+pub struct ExportArgs {
+    /// Index, alias or pattern to read
+    pub index: String,
+
+    /// File to write, or - for stdout
+    pub file: String,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub format: OutputFormat,
+
+    /// Only export the documents matching the query of this search body,
+    /// e.g. {"query": {"range": {"price": {"gte": 10}}}}
+    #[arg(long, value_name = "FILE")]
+    pub query: Option<String>,
+
+    /// Write each document's _id as field NAME, to import it again with
+    /// --id-column NAME
+    #[arg(long, value_name = "NAME")]
+    pub id_field: Option<String>,
+
+    /// Documents fetched per request
+    #[arg(long, value_name = "N", default_value_t = 1000, value_parser = positive())]
+    pub batch_size: usize,
+
+    /// Read the index in N slices side by side, for large indices
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = positive())]
+    pub slices: usize,
+
+    /// Only export these fields, dotted for nested ones, as CSV columns in
+    /// this order
+    #[arg(long, value_name = "FIELD[,FIELD]", value_delimiter = ',')]
+    pub fields: Vec<String>,
+
+    /// Write null and missing values in CSV as STR; import reads empty
+    /// cells as null
+    #[arg(long, value_name = "STR", default_value = "")]
+    pub null_value: String,
+
+    /// Write the date fields of the mapping in CSV with this strftime
+    /// format, e.g. "%Y-%m-%d %H:%M:%S"
+    #[arg(long, value_name = "FMT")]
+    pub date_format: Option<String>,
+
+    /// Turn at most N levels of objects into dotted CSV columns and write
+    /// deeper ones as JSON, as import --flatten --max-depth N reads them
+    #[arg(long, value_name = "N", value_parser = positive(), conflicts_with = "fields")]
+    pub max_depth: Option<usize>,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+}
+
+/// Deleting documents
+#[derive(clap::Args)]
+#[command(group(ArgGroup::new("condition").required(true).args(["query", "where_"])))]
+pub struct DeleteArgs {
+    /// Index, alias or pattern to delete from
+    pub index: String,
+
+    /// Delete the documents matching the query of this search body, e.g.
+    /// {"query": {"term": {"source": "feed"}}}
+    #[arg(long, value_name = "FILE")]
+    pub query: Option<String>,
+
+    /// Delete the documents for which this condition holds, e.g.
+    /// "source == 'feed' && price < 0"; fields are compared with values as
+    /// term and range queries, and == '' matches a missing field
+    #[arg(long = "where", value_name = "EXPRESSION", value_parser = where_query)]
+    pub where_: Option<String>,
+
+    /// Only count the matching documents
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Don't ask for confirmation
+    #[arg(long)]
+    pub yes: bool,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+}
+
+/// Looking at an index
+#[derive(clap::Args)]
+pub struct InspectArgs {
+    /// Index, alias or pattern to inspect
+    pub index: String,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+}
+
+#[derive(clap::Args)]
+pub struct AnalyzeArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Print the report as one JSON object instead of a table
+    #[arg(long)]
+    pub json: bool,
+
+    /// Compare the columns with the mapping and field statistics of this
+    /// index: fields it doesn't have yet, types it won't take, and
+    /// keyword columns of nearly all distinct values
+    #[arg(long, value_name = "NAME")]
+    pub index: Option<String>,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+}
+
+/// The cluster `reindex` reads from, when it isn't the one written to
+#[derive(clap::Args)]
+#[command(next_help_heading = "Source connection")]
+pub struct SourceArgs {
+    /// Elasticsearch URL to read from [default: --host]; with it, none of
+    /// the connection options apply to the source
+    #[arg(long, value_name = "URL")]
+    pub src_host: Option<String>,
+
+    /// Basic auth user for --src-host
+    #[arg(long, requires = "src_host")]
+    pub src_user: Option<String>,
+
+    /// Basic auth password for --src-host
+    #[arg(long, requires = "src_user")]
+    pub src_pass: Option<String>,
+
+    /// Read the --src-user password from the OS keyring, as
+    /// --credential-profile does
+    #[arg(
+        long,
+        value_name = "PROFILE",
+        requires = "src_user",
+        conflicts_with = "src_pass"
+    )]
+    pub src_credential_profile: Option<String>,
+
+    /// Read the --src-user password from FILE
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "src_user",
+        conflicts_with_all = ["src_pass", "src_credential_profile"]
+    )]
+    pub src_pass_file: Option<String>,
+
+    /// API key for --src-host
+    #[arg(
+        long,
+        value_name = "KEY",
+        requires = "src_host",
+        conflicts_with_all = ["src_user", "src_aws_sigv4"]
+    )]
+    pub src_api_key: Option<String>,
+
+    /// Read the --src-api-key from FILE
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "src_host",
+        conflicts_with_all = ["src_user", "src_aws_sigv4", "src_api_key"]
+    )]
+    pub src_api_key_file: Option<String>,
+
+    /// Sign requests to --src-host with AWS SigV4
+    #[arg(long, requires = "src_host", conflicts_with = "src_user")]
+    pub src_aws_sigv4: bool,
+
+    /// Region to sign for [default: $AWS_REGION]
+    #[arg(long, value_name = "REGION", requires = "src_aws_sigv4")]
+    pub src_aws_region: Option<String>,
+
+    /// Service to sign for
+    #[arg(long, value_name = "NAME", default_value = "es")]
+    pub src_aws_service: String,
+
+    /// PEM certificate for mutual TLS with --src-host
+    #[arg(long, value_name = "FILE", requires_all = ["src_client_key", "src_host"])]
+    pub src_client_cert: Option<String>,
+
+    /// PEM key for --src-client-cert
+    #[arg(long, value_name = "FILE", requires = "src_client_cert")]
+    pub src_client_key: Option<String>,
+
+    /// Trust the CAs in this PEM bundle for --src-host
+    #[arg(long, value_name = "FILE", requires = "src_host")]
+    pub src_ca_cert: Option<String>,
+
+    /// Don't verify the certificate of --src-host
+    #[arg(long, requires = "src_host")]
+    pub src_insecure: bool,
+}
+
+impl SourceArgs {
+    /// These options as those of a connection, if a source host is given
+    pub fn connection(self) -> Option<ConnectionArgs> {
+        Some(ConnectionArgs {
+            host: self.src_host?,
+            user: self.src_user,
+            pass: self.src_pass,
+            pass_file: self.src_pass_file,
+            credential_profile: self.src_credential_profile,
+            api_key: self.src_api_key,
+            api_key_file: self.src_api_key_file,
+            aws_sigv4: self.src_aws_sigv4,
+            aws_region: self.src_aws_region,
+            aws_service: self.src_aws_service,
+            client_cert: self.src_client_cert,
+            client_key: self.src_client_key,
+            ca_cert: self.src_ca_cert,
+            insecure: self.src_insecure,
+            // set from --debug-http, --user-agent and --opaque-id, which
+            // cover both clusters
+            debug_http: false,
+            user_agent: None,
+            opaque_id: None,
+        })
+    }
+}
+
+/// Parser for counts that must be at least 1
+fn positive() -> RangedU64ValueParser<usize> {
+    RangedU64ValueParser::new().range(1..)
+}
+
+fn key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| "expected COL=VALUE".to_string())
+}
+
+/// `[NAME=]FILE`, NAME defaulting to the file name without its extension
+fn named_file(s: &str) -> Result<(String, String), String> {
+    if let Some((name, path)) = s.split_once('=')
+        && !name.is_empty()
+        && !name.contains(['/', '\\'])
+    {
+        return Ok((name.to_string(), path.to_string()));
+    }
+    let name = std::path::Path::new(s)
+        .file_stem()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("no template name in {}", s))?;
+    Ok((name.to_string(), s.to_string()))
+}
+
+fn nest(s: &str) -> Result<(String, Vec<String>), String> {
+    let (name, columns) = s
+        .split_once(':')
+        .ok_or_else(|| "expected NAME:COL,COL".to_string())?;
+    let columns: Vec<String> = columns.split(',').map(|c| c.trim().to_string()).collect();
+    if name.is_empty() || columns.iter().any(String::is_empty) {
+        return Err("expected NAME:COL,COL".to_string());
+    }
+    Ok((name.to_string(), columns))
+}
+
+fn mask(s: &str) -> Result<(String, Mask), String> {
+    let (col, kind) = s
+        .split_once('=')
+        .ok_or_else(|| "expected COL=MASK".to_string())?;
+    let mask = Mask::from_str(kind, true)
+        .map_err(|_| format!("unknown mask {} (expected partial, redact or hash)", kind))?;
+    Ok((col.to_string(), mask))
+}
+
+fn truncation(s: &str) -> Result<(Option<String>, usize), String> {
+    match s.rsplit_once('=') {
+        Some((col, size)) => Ok((Some(col.to_string()), byte_size(size)?)),
+        None => Ok((None, byte_size(s)?)),
+    }
+}
+
+fn tz_column(s: &str) -> Result<(String, tz::Zone), String> {
+    let (col, zone) = s
+        .split_once('=')
+        .ok_or_else(|| "expected COL=ZONE".to_string())?;
+    Ok((col.trim().to_string(), tz::Zone::parse(zone.trim())?))
+}
+
+fn computed(s: &str) -> Result<(String, expr::Expr), String> {
+    let (name, source) = s
+        .split_once('=')
+        .ok_or_else(|| "expected NAME=EXPRESSION".to_string())?;
+    let expr = expr::Expr::parse(source).map_err(|e| format!("{}: {}", name, e))?;
+    Ok((name.trim().to_string(), expr))
+}
+
+/// A `--where` condition as the JSON of an ES query
+fn where_query(s: &str) -> Result<String, String> {
+    expr::Expr::parse(s)?.to_query()
+}
+
+fn declared_type(s: &str) -> Result<(String, ColumnType), String> {
+    let (col, ty) = s
+        .split_once('=')
+        .ok_or_else(|| "expected COL=TYPE".to_string())?;
+    Ok((col.to_string(), ty.parse()?))
+}
+
+/// `1%` as the fraction 0.01
+fn percent(s: &str) -> Result<f64, String> {
+    s.strip_suffix('%')
+        .and_then(|p| p.parse::<f64>().ok())
+        .filter(|p| *p > 0.0 && *p <= 100.0)
+        .map(|p| p / 100.0)
+        .ok_or_else(|| "expected a percentage like 1%".to_string())
+}
+
+fn ascii_char(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [c] if c.is_ascii() => Ok(*c),
+        _ => Err("expected a single ASCII character".to_string()),
+    }
+}
+
+fn active_shards(s: &str) -> Result<String, String> {
+    if s == "all" || s.parse::<u32>().is_ok_and(|n| n > 0) {
+        Ok(s.to_string())
+    } else {
+        Err("expected a positive number or all".to_string())
+    }
+}
+
+/// Whole seconds, minutes, hours or days, e.g. 90s
+fn duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err("expected a time like 90s or 5m".to_string()),
+    };
+    let n: u64 = num
+        .parse()
+        .map_err(|_| "expected a time like 90s or 5m".to_string())?;
+    n.checked_mul(secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| "time too large".to_string())
+}
+
+/// A number of bytes with an optional unit, e.g. 512kb or 1mb
+fn byte_size(s: &str) -> Result<usize, String> {
+    let lower = s.to_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(lower.len());
+    let (num, unit) = lower.split_at(split);
+    let scale = match unit {
+        "" | "b" => 1,
+        "kb" => 1 << 10,
+        "mb" => 1 << 20,
+        "gb" => 1 << 30,
+        _ => return Err("expected a size like 512kb or 1mb".to_string()),
+    };
+    num.parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| "expected a size like 512kb or 1mb".to_string())
+}
+
+/// A header value, on one line
+fn header_value(s: &str) -> Result<String, String> {
+    if s.contains(['\r', '\n']) {
+        return Err("must not contain line breaks".to_string());
+    }
+    Ok(s.to_string())
+}
+
+fn time_value(s: &str) -> Result<String, String> {
+    if is_time_value(s) {
+        Ok(s.to_string())
+    } else {
+        Err("expected a time value like 30s or 1m".to_string())
+    }
+}
@@ -0,0 +1,281 @@
+//! Live state of an import: counted as it goes, printed as messages on
+//! stderr, and drawn by `--tui` while it owns the terminal
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+/// What a bulk worker is doing
+#[derive(Clone, Copy)]
+// only drawn with the tui feature
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+pub enum Worker {
+    Idle,
+    /// Sending a batch of `docs` documents
+    Sending {
+        docs: usize,
+        since: Instant,
+    },
+    /// Waiting to resend after the cluster failed
+    Pausing {
+        until: Instant,
+    },
+}
+
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+pub struct Progress {
+    started: Instant,
+    /// Size of the input, for the ETA
+    input_len: u64,
+    /// How far into the input reading has got
+    input_pos: AtomicU64,
+    rows: AtomicU64,
+    indexed: AtomicU64,
+    failed: AtomicU64,
+    /// Documents resent after the cluster rejected them as busy
+    retried: AtomicU64,
+    workers: Mutex<Vec<Worker>>,
+    /// Set while the dashboard is drawn in place of messages
+    attached: AtomicBool,
+    /// Messages held back while attached, oldest first
+    messages: Mutex<Vec<String>>,
+}
+
+impl Progress {
+    pub fn new(workers: usize, input_len: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            input_len: input_len as u64,
+            input_pos: AtomicU64::new(0),
+            rows: AtomicU64::new(0),
+            indexed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            retried: AtomicU64::new(0),
+            workers: Mutex::new(vec![Worker::Idle; workers]),
+            attached: AtomicBool::new(false),
+            messages: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Print `msg` on stderr, or keep it for the dashboard to show
+    pub fn message(&self, msg: impl Into<String>) {
+        let msg = msg.into();
+        let mut messages = self.messages.lock().unwrap();
+        if self.attached.load(Ordering::Relaxed) {
+            messages.push(msg);
+        } else {
+            log!("{}", msg);
+        }
+    }
+
+    /// Rows read so far, and the input position after them
+    pub fn read(&self, rows: usize, pos: usize) {
+        self.rows.store(rows as u64, Ordering::Relaxed);
+        self.input_pos.store(pos as u64, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, indexed: usize, failed: usize) {
+        self.indexed.fetch_add(indexed as u64, Ordering::Relaxed);
+        self.failed.fetch_add(failed as u64, Ordering::Relaxed);
+    }
+
+    pub fn retried(&self, docs: usize) {
+        self.retried.fetch_add(docs as u64, Ordering::Relaxed);
+    }
+
+    /// Workers sending a batch right now
+    pub fn in_flight(&self) -> usize {
+        let workers = self.workers.lock().unwrap();
+        workers
+            .iter()
+            .filter(|w| matches!(w, Worker::Sending { .. }))
+            .count()
+    }
+
+    pub fn worker(&self, i: usize, state: Worker) {
+        if let Some(w) = self.workers.lock().unwrap().get_mut(i) {
+            *w = state;
+        }
+    }
+
+    /// Hold messages back for the dashboard
+    #[cfg(feature = "tui")]
+    fn attach(&self) {
+        self.attached.store(true, Ordering::Relaxed);
+    }
+
+    /// Print the messages held back since `attach`
+    #[cfg(feature = "tui")]
+    fn detach(&self) {
+        let mut messages = self.messages.lock().unwrap();
+        self.attached.store(false, Ordering::Relaxed);
+        for msg in messages.drain(..) {
+            log!("{}", msg);
+        }
+    }
+}
+
+/// Draw the dashboard until `done` is set. Ctrl-C or q sets `stop`, as the
+/// signal would without the dashboard, and a second one ends the process;
+/// held-back messages are printed once the terminal is back.
+#[cfg(feature = "tui")]
+pub fn run(progress: &Progress, done: &AtomicBool, stop: &AtomicBool, title: &str) {
+    use std::time::Duration;
+
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+
+    let tick = Duration::from_millis(500);
+    let mut terminal = ratatui::init();
+    progress.attach();
+    // documents indexed per tick, newest last
+    let mut rates: Vec<u64> = Vec::new();
+    let mut last = 0;
+    let mut quit = false;
+    while !done.load(Ordering::Relaxed) {
+        let indexed = progress.indexed.load(Ordering::Relaxed);
+        rates.push((indexed - last) * 1000 / tick.as_millis() as u64);
+        last = indexed;
+        if terminal
+            .draw(|frame| draw(frame, progress, &rates, title, stop))
+            .is_err()
+        {
+            break;
+        }
+        if event::poll(tick).unwrap_or(false)
+            && let Ok(Event::Key(key)) = event::read()
+            && key.kind == KeyEventKind::Press
+            && (key.code == KeyCode::Char('q')
+                || (key.code == KeyCode::Char('c')
+                    && key.modifiers.contains(KeyModifiers::CONTROL)))
+            && stop.swap(true, Ordering::Relaxed)
+        {
+            quit = true;
+            break;
+        }
+    }
+    ratatui::restore();
+    progress.detach();
+    if quit {
+        log!(
+            "Stopped: {} documents indexed",
+            progress.indexed.load(Ordering::Relaxed)
+        );
+        std::process::exit(130);
+    }
+}
+
+#[cfg(feature = "tui")]
+fn draw(
+    frame: &mut ratatui::Frame,
+    progress: &Progress,
+    rates: &[u64],
+    title: &str,
+    stop: &AtomicBool,
+) {
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Color, Style};
+    use ratatui::widgets::{Block, Gauge, List, Paragraph, Sparkline};
+
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    let elapsed = progress.started.elapsed();
+    let indexed = load(&progress.indexed);
+    let ratio = if progress.input_len == 0 {
+        0.0
+    } else {
+        (load(&progress.input_pos) as f64 / progress.input_len as f64).min(1.0)
+    };
+    let eta = if ratio > 0.0 && ratio < 1.0 {
+        let left = elapsed.as_secs_f64() * (1.0 - ratio) / ratio;
+        format!("ETA {}", clock(left as u64))
+    } else {
+        String::new()
+    };
+    let rate = indexed as f64 / elapsed.as_secs_f64().max(0.001);
+
+    let workers = progress.workers.lock().unwrap().clone();
+    let [header, gauge, graph, lower] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(3),
+        Constraint::Length(8),
+        Constraint::Min(4),
+    ])
+    .areas(frame.area());
+    let [workers_area, messages_area] =
+        Layout::horizontal([Constraint::Length(36), Constraint::Min(20)]).areas(lower);
+
+    let keys = if stop.load(Ordering::Relaxed) {
+        "stopping, q quits now"
+    } else {
+        "q stops"
+    };
+    let counts = format!(
+        "{} rows read, {} indexed, {} failed, {} retried, {:.0} docs/s, {} elapsed",
+        load(&progress.rows),
+        indexed,
+        load(&progress.failed),
+        load(&progress.retried),
+        rate,
+        clock(elapsed.as_secs())
+    );
+    frame.render_widget(
+        Paragraph::new(counts).block(Block::bordered().title(format!(" {} ({}) ", title, keys))),
+        header,
+    );
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title(" input "))
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(ratio)
+            .label(format!("{:.1}% {}", ratio * 100.0, eta)),
+        gauge,
+    );
+    let width = graph.width.saturating_sub(2) as usize;
+    let recent = &rates[rates.len().saturating_sub(width)..];
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(" docs/s "))
+            .style(Style::default().fg(Color::Cyan))
+            .data(recent),
+        graph,
+    );
+
+    let now = std::time::Instant::now();
+    let lines: Vec<String> = workers
+        .iter()
+        .enumerate()
+        .map(|(i, w)| match *w {
+            Worker::Idle => format!("{}: idle", i + 1),
+            Worker::Sending { docs, since } => format!(
+                "{}: sending {} docs ({:.1}s)",
+                i + 1,
+                docs,
+                now.duration_since(since).as_secs_f64()
+            ),
+            Worker::Pausing { until } => format!(
+                "{}: pausing ({:.0}s left)",
+                i + 1,
+                until.saturating_duration_since(now).as_secs_f64()
+            ),
+        })
+        .collect();
+    frame.render_widget(
+        List::new(lines).block(Block::bordered().title(" workers ")),
+        workers_area,
+    );
+    let messages = progress.messages.lock().unwrap();
+    let shown = messages_area.height.saturating_sub(2) as usize;
+    let recent: Vec<&str> = messages[messages.len().saturating_sub(shown)..]
+        .iter()
+        .map(String::as_str)
+        .collect();
+    frame.render_widget(
+        List::new(recent).block(Block::bordered().title(" messages ")),
+        messages_area,
+    );
+}
+
+/// Seconds as h:mm:ss
+#[cfg(feature = "tui")]
+fn clock(secs: u64) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
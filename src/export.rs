@@ -0,0 +1,378 @@
+//! `export`: write the documents of an index to a CSV or NDJSON file, read
+//! from a point in time with search_after, in slices side by side
+
+use std::io::Write;
+use std::sync::{Mutex, mpsc};
+use std::thread;
+
+use clap::ValueEnum;
+
+use chrono::{DateTime, Utc};
+
+use crate::json::{self, Value, json_escape};
+use crate::{Auth, HttpTarget, es_mapping, http_request, parse_datetime, schema};
+
+/// How long ES keeps the point in time open between two pages
+const KEEP_ALIVE: &str = "5m";
+
+/// Output formats of `export`
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// A header line of the mapped fields, then one line per document;
+    /// arrays and objects are written as JSON
+    Csv,
+    /// Each document's _source on a line of its own
+    Ndjson,
+}
+
+pub struct Export {
+    pub index: String,
+    pub format: OutputFormat,
+    /// Only export the documents matching this query
+    pub query: Option<Value>,
+    /// Documents fetched per request
+    pub page_size: usize,
+    /// Slices of the index read in parallel
+    pub slices: usize,
+    /// Write each document's _id as this field
+    pub id_field: Option<String>,
+    /// Only these fields, in this order; all mapped ones if empty
+    pub fields: Vec<String>,
+    /// CSV cell of missing and null values
+    pub null_value: String,
+    /// strftime format of date fields in CSV
+    pub date_format: Option<String>,
+    /// Levels of objects turned into dotted CSV columns, deeper ones being
+    /// written as JSON
+    pub max_depth: Option<usize>,
+}
+
+/// What `read_pages` reads
+pub struct Search<'a> {
+    pub index: &'a str,
+    pub query: Option<&'a Value>,
+    pub page_size: usize,
+    /// Slices of the index read in parallel
+    pub slices: usize,
+    /// Only these _source fields, all if empty
+    pub fields: &'a [String],
+}
+
+/// The `query` of a search body file like `{"query": {"term": {...}}}`
+pub fn load_query(path: &str) -> Result<Value, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let body = json::parse(&text).map_err(|e| format!("bad search body {}: {}", path, e))?;
+    body.get("query")
+        .cloned()
+        .ok_or_else(|| format!("search body {} has no \"query\"", path))
+}
+
+/// An open point in time of an index, closed when dropped
+struct Pit<'a> {
+    target: &'a HttpTarget,
+    auth: Option<&'a Auth>,
+    index: String,
+    /// The latest id ES gave out for it
+    id: Mutex<String>,
+}
+
+impl<'a> Pit<'a> {
+    fn open(target: &'a HttpTarget, auth: Option<&'a Auth>, index: &str) -> Result<Self, String> {
+        let path = format!(
+            "{}/{}/_pit?keep_alive={}",
+            target.base_path, index, KEEP_ALIVE
+        );
+        let resp = http_request(target, "POST", &path, "application/json", "", auth)?;
+        let id = match resp.status {
+            200 => json::parse(&resp.body)?
+                .get("id")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| format!("no id in _pit response: {}", resp.body))?,
+            404 => return Err(format!("index {} not found", index)),
+            status => {
+                return Err(format!(
+                    "cannot open a point in time of {}: HTTP {}: {}",
+                    index, status, resp.body
+                ));
+            }
+        };
+        Ok(Self {
+            target,
+            auth,
+            index: index.to_string(),
+            id: Mutex::new(id),
+        })
+    }
+
+    /// The hits matching `filter` (the query and _source parts of a search
+    /// body) after `after`, the sort values of the last hit of the page
+    /// before, in slice `slice` of `slices`
+    fn search(
+        &self,
+        filter: &str,
+        page_size: usize,
+        (slice, slices): (usize, usize),
+        after: Option<&Value>,
+    ) -> Result<Vec<Value>, String> {
+        let mut body = format!(
+            "{{\"size\":{},\"pit\":{{\"id\":\"{}\",\"keep_alive\":\"{}\"}},\"sort\":[\"_shard_doc\"],{}",
+            page_size,
+            json_escape(&self.id.lock().unwrap()),
+            KEEP_ALIVE,
+            filter
+        );
+        if slices > 1 {
+            body.push_str(&format!(
+                ",\"slice\":{{\"id\":{},\"max\":{}}}",
+                slice, slices
+            ));
+        }
+        if let Some(after) = after {
+            body.push_str(&format!(",\"search_after\":{}", after));
+        }
+        body.push('}');
+        let path = format!("{}/_search", self.target.base_path);
+        let resp = http_request(
+            self.target,
+            "POST",
+            &path,
+            "application/json",
+            &body,
+            self.auth,
+        )?;
+        if resp.status != 200 {
+            return Err(format!(
+                "search of {} returned HTTP {}: {}",
+                self.index, resp.status, resp.body
+            ));
+        }
+        let v = json::parse(&resp.body)?;
+        if let Some(id) = v.get("pit_id").and_then(Value::as_str) {
+            *self.id.lock().unwrap() = id.to_string();
+        }
+        let hits = v
+            .get("hits")
+            .and_then(|h| h.get("hits"))
+            .and_then(Value::as_array)
+            .unwrap_or_default();
+        Ok(hits.to_vec())
+    }
+}
+
+impl Drop for Pit<'_> {
+    fn drop(&mut self) {
+        let path = format!("{}/_pit", self.target.base_path);
+        let body = format!("{{\"id\":\"{}\"}}", json_escape(&self.id.lock().unwrap()));
+        // ES drops it after KEEP_ALIVE anyway
+        let _ = http_request(
+            self.target,
+            "DELETE",
+            &path,
+            "application/json",
+            &body,
+            self.auth,
+        );
+    }
+}
+
+/// Read the documents `search` asks for as of one point in time, its
+/// slices in parallel, and hand each page of hits (with `_id` and `_source`)
+/// to `page` as it comes in; stops at the first error of either
+pub fn read_pages(
+    target: &HttpTarget,
+    auth: Option<&Auth>,
+    search: &Search,
+    mut page: impl FnMut(&[Value]) -> Result<(), String>,
+) -> Result<(), String> {
+    let Search {
+        index,
+        query,
+        page_size,
+        slices,
+        fields,
+    } = *search;
+    let query = query.map_or_else(|| "{\"match_all\":{}}".to_string(), Value::to_string);
+    let mut filter = format!("\"query\":{}", query);
+    if !fields.is_empty() {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|f| format!("\"{}\"", json_escape(f)))
+            .collect();
+        filter.push_str(&format!(",\"_source\":[{}]", fields.join(",")));
+    }
+    let pit = Pit::open(target, auth, index)?;
+    thread::scope(|s| {
+        let (tx, rx) = mpsc::sync_channel(slices);
+        for slice in 0..slices {
+            let tx = tx.clone();
+            let (pit, filter) = (&pit, &filter);
+            s.spawn(move || {
+                let mut after = None;
+                loop {
+                    let hits = pit.search(filter, page_size, (slice, slices), after.as_ref());
+                    let last = match &hits {
+                        Ok(hits) if hits.len() == page_size => hits.last().cloned(),
+                        _ => None,
+                    };
+                    // the reader has stopped
+                    if tx.send(hits).is_err() {
+                        return;
+                    }
+                    match last.and_then(|hit| hit.get("sort").cloned()) {
+                        Some(sort) => after = Some(sort),
+                        None => return,
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for hits in rx {
+            page(&hits?)?;
+        }
+        Ok(())
+    })
+}
+
+/// Write the documents of `export.index` to `out` and return how many
+/// there were. CSV columns are `export.fields`, or else the index's mapped
+/// fields in mapping order, so that exports of the same index line up;
+/// fields missing from the mapping are left out.
+pub fn run(
+    export: &Export,
+    target: &HttpTarget,
+    auth: Option<&Auth>,
+    out: &mut impl Write,
+) -> Result<u64, String> {
+    let err = |e: std::io::Error| format!("cannot write export: {}", e);
+    let (columns, dates) = match export.format {
+        OutputFormat::Csv => {
+            let mapping = es_mapping(target, &export.index, auth)?
+                .ok_or_else(|| format!("index {} not found", export.index))?;
+            let mut columns: Vec<String> = Vec::new();
+            if export.fields.is_empty() {
+                for field in schema::leaf_fields(&mapping) {
+                    // address.geo.lat -> address.geo with --max-depth 2
+                    let field = match export.max_depth {
+                        Some(depth) => field.split('.').take(depth).collect::<Vec<_>>().join("."),
+                        None => field,
+                    };
+                    if !columns.contains(&field) {
+                        columns.push(field);
+                    }
+                }
+            } else {
+                columns = export.fields.clone();
+            }
+            let dates = schema::date_fields(&mapping);
+            let header: Vec<&str> = export
+                .id_field
+                .iter()
+                .chain(&columns)
+                .map(String::as_str)
+                .collect();
+            write_csv_line(out, &header).map_err(err)?;
+            (columns, dates)
+        }
+        OutputFormat::Ndjson => (Vec::new(), Vec::new()),
+    };
+
+    let mut total = 0;
+    let search = Search {
+        index: &export.index,
+        query: export.query.as_ref(),
+        page_size: export.page_size,
+        slices: export.slices,
+        fields: &export.fields,
+    };
+    read_pages(target, auth, &search, |hits| {
+        for hit in hits {
+            let id = hit.get("_id").and_then(Value::as_str).unwrap_or("");
+            let source = hit.get("_source").unwrap_or(&Value::Null);
+            match export.format {
+                OutputFormat::Csv => {
+                    let cells: Vec<String> = export
+                        .id_field
+                        .iter()
+                        .map(|_| id.to_string())
+                        .chain(
+                            columns
+                                .iter()
+                                .map(|c| cell(lookup(source, c), export, dates.contains(c))),
+                        )
+                        .collect();
+                    let cells: Vec<&str> = cells.iter().map(String::as_str).collect();
+                    write_csv_line(out, &cells).map_err(err)?;
+                }
+                OutputFormat::Ndjson => {
+                    let mut fields = source.as_object().unwrap_or_default().to_vec();
+                    if let Some(name) = &export.id_field {
+                        fields.retain(|(k, _)| k != name);
+                        fields.insert(0, (name.clone(), Value::String(id.to_string())));
+                    }
+                    writeln!(out, "{}", Value::Object(fields)).map_err(err)?;
+                }
+            }
+            total += 1;
+        }
+        Ok(())
+    })?;
+    Ok(total)
+}
+
+/// The value at a dotted field name, through nested objects or a key that
+/// itself contains dots
+fn lookup<'v>(v: &'v Value, path: &str) -> Option<&'v Value> {
+    if let Some(found) = v.get(path) {
+        return Some(found);
+    }
+    path.match_indices('.').find_map(|(at, _)| {
+        let child = v.get(&path[..at])?;
+        lookup(child, &path[at + 1..])
+    })
+}
+
+/// A CSV cell: strings as they are and other values as JSON, except for
+/// --date-format dates
+fn cell(v: Option<&Value>, export: &Export, date: bool) -> String {
+    let v = match v {
+        None | Some(Value::Null) => return export.null_value.clone(),
+        Some(v) => v,
+    };
+    if date
+        && let Some(format) = &export.date_format
+        && let Some(dt) = date_value(v)
+    {
+        return dt.format(format).to_string();
+    }
+    match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// A date field's value, a date string or epoch milliseconds; `None` for
+/// ones in other formats, left as they are
+fn date_value(v: &Value) -> Option<DateTime<Utc>> {
+    let text = match v {
+        Value::String(s) => s,
+        Value::Number(n) => n,
+        _ => return None,
+    };
+    parse_datetime(text).or_else(|| DateTime::from_timestamp_millis(text.parse().ok()?))
+}
+
+/// A CSV line, quoting fields with commas, quotes or line breaks
+fn write_csv_line(out: &mut impl Write, fields: &[&str]) -> std::io::Result<()> {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(out, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+    }
+    out.write_all(b"\n")
+}
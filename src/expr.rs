@@ -0,0 +1,729 @@
+//! Expressions for `--compute` fields, evaluated per row
+//!
+//! Numbers, 'single' or "double" quoted strings, column names (`price`,
+//! `address.city`, or `` `Total Sales` `` in backticks), arithmetic
+//! `+ - * / %`, comparisons `== != < <= > >=`, `&& || !`, parentheses and
+//! the functions in `FUNCTIONS`. Empty cells are null; null and
+//! non-numeric operands make arithmetic null, and null results are empty.
+
+use crate::json::json_escape;
+
+/// Name, minimum and maximum argument count
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("concat", 1, usize::MAX),
+    ("coalesce", 1, usize::MAX),
+    ("if", 3, 3),
+    ("upper", 1, 1),
+    ("lower", 1, 1),
+    ("trim", 1, 1),
+    ("length", 1, 1),
+    ("substr", 2, 3),
+    ("replace", 3, 3),
+    ("round", 1, 2),
+    ("floor", 1, 1),
+    ("ceil", 1, 1),
+    ("abs", 1, 1),
+    ("min", 1, usize::MAX),
+    ("max", 1, usize::MAX),
+];
+
+/// Parsed expression, bound to column positions with `bind`
+#[derive(Clone)]
+pub struct Expr(Node);
+
+#[derive(Clone)]
+enum Node {
+    Num(f64),
+    Str(String),
+    Column { name: String, index: usize },
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+    Call(&'static str, Vec<Node>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+/// Value during evaluation
+#[derive(Clone)]
+enum Val {
+    Null,
+    Num(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut p = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let node = p.or()?;
+        match p.tokens.get(p.pos) {
+            None => Ok(Expr(node)),
+            Some(t) => Err(format!("unexpected {} in expression", t.describe())),
+        }
+    }
+
+    /// Resolve column names against the columns of each row
+    pub fn bind(&mut self, columns: &[String]) -> Result<(), String> {
+        bind(&mut self.0, columns)
+    }
+
+    /// Value for one row, as cell text
+    pub fn eval(&self, rec: &[String]) -> String {
+        match eval(&self.0, rec) {
+            Val::Null => String::new(),
+            Val::Num(n) => format_number(n),
+            Val::Str(s) => s,
+            Val::Bool(b) => b.to_string(),
+        }
+    }
+
+    /// The expression as an ES query, for conditions that compare columns
+    /// with values and combine those with `&& || !`; comparing with `''`
+    /// tests for a missing field, as empty cells are null
+    pub fn to_query(&self) -> Result<String, String> {
+        query(&self.0)
+    }
+}
+
+fn query(node: &Node) -> Result<String, String> {
+    let bool_query = |clause: &str, nodes: &[&Node]| -> Result<String, String> {
+        let clauses = nodes
+            .iter()
+            .map(|n| query(n))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!(
+            "{{\"bool\":{{\"{}\":[{}]}}}}",
+            clause,
+            clauses.join(",")
+        ))
+    };
+    match node {
+        Node::Binary(Op::And, a, b) => bool_query("filter", &[a, b]),
+        Node::Binary(Op::Or, a, b) => bool_query("should", &[a, b]),
+        Node::Not(a) => bool_query("must_not", &[a]),
+        Node::Binary(op, a, b) => {
+            // with the value first, `1 < x` is `x > 1`
+            let (op, name, value) = match (&**a, &**b) {
+                (Node::Column { name, .. }, v) => (*op, name, v),
+                (v, Node::Column { name, .. }) => (op.flipped(), name, v),
+                _ => return Err(unsupported()),
+            };
+            let value = match value {
+                Node::Num(n) => format_number(*n),
+                Node::Neg(n) if let Node::Num(n) = &**n => format_number(-n),
+                Node::Str(s) if s.is_empty() => {
+                    let exists = format!("{{\"exists\":{{\"field\":\"{}\"}}}}", json_escape(name));
+                    return match op {
+                        Op::Eq => Ok(format!("{{\"bool\":{{\"must_not\":[{}]}}}}", exists)),
+                        Op::Ne => Ok(exists),
+                        _ => Err(unsupported()),
+                    };
+                }
+                Node::Str(s) => format!("\"{}\"", json_escape(s)),
+                _ => return Err(unsupported()),
+            };
+            let field = json_escape(name);
+            let range = |bound: &str| {
+                Ok(format!(
+                    "{{\"range\":{{\"{}\":{{\"{}\":{}}}}}}}",
+                    field, bound, value
+                ))
+            };
+            match op {
+                Op::Eq => Ok(format!("{{\"term\":{{\"{}\":{}}}}}", field, value)),
+                Op::Ne => Ok(format!(
+                    "{{\"bool\":{{\"must_not\":[{{\"term\":{{\"{}\":{}}}}}]}}}}",
+                    field, value
+                )),
+                Op::Lt => range("lt"),
+                Op::Le => range("lte"),
+                Op::Gt => range("gt"),
+                Op::Ge => range("gte"),
+                _ => Err(unsupported()),
+            }
+        }
+        _ => Err(unsupported()),
+    }
+}
+
+fn unsupported() -> String {
+    "only comparisons of a field with a value, joined by && || and !, make a query".to_string()
+}
+
+impl Op {
+    /// The comparison with its operands swapped
+    fn flipped(self) -> Self {
+        match self {
+            Op::Lt => Op::Gt,
+            Op::Le => Op::Ge,
+            Op::Gt => Op::Lt,
+            Op::Ge => Op::Le,
+            op => op,
+        }
+    }
+}
+
+fn bind(node: &mut Node, columns: &[String]) -> Result<(), String> {
+    match node {
+        Node::Column { name, index } => {
+            *index = columns
+                .iter()
+                .position(|c| c == name)
+                .ok_or_else(|| format!("no column named {}", name))?;
+        }
+        Node::Neg(a) | Node::Not(a) => bind(a, columns)?,
+        Node::Binary(_, a, b) => {
+            bind(a, columns)?;
+            bind(b, columns)?;
+        }
+        Node::Call(_, args) => {
+            for a in args {
+                bind(a, columns)?;
+            }
+        }
+        Node::Num(_) | Node::Str(_) => {}
+    }
+    Ok(())
+}
+
+/// Whole numbers without a fraction, others as shortest round-trip text
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        n.to_string()
+    }
+}
+
+impl Val {
+    fn num(&self) -> Option<f64> {
+        match self {
+            Val::Num(n) => Some(*n),
+            Val::Str(s) => s.trim().parse().ok().filter(|n: &f64| n.is_finite()),
+            Val::Bool(_) | Val::Null => None,
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Val::Null => String::new(),
+            Val::Num(n) => format_number(*n),
+            Val::Str(s) => s.clone(),
+            Val::Bool(b) => b.to_string(),
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            Val::Null => false,
+            Val::Num(n) => *n != 0.0,
+            Val::Bool(b) => *b,
+            Val::Str(s) => {
+                let s = s.trim();
+                !s.is_empty() && s != "0" && !s.eq_ignore_ascii_case("false")
+            }
+        }
+    }
+}
+
+fn eval(node: &Node, rec: &[String]) -> Val {
+    match node {
+        Node::Num(n) => Val::Num(*n),
+        Node::Str(s) => Val::Str(s.clone()),
+        Node::Column { index, .. } => match rec.get(*index) {
+            Some(v) if !v.trim().is_empty() => Val::Str(v.clone()),
+            _ => Val::Null,
+        },
+        Node::Neg(a) => eval(a, rec).num().map_or(Val::Null, |n| Val::Num(-n)),
+        Node::Not(a) => Val::Bool(!eval(a, rec).truthy()),
+        Node::Binary(Op::And, a, b) => Val::Bool(eval(a, rec).truthy() && eval(b, rec).truthy()),
+        Node::Binary(Op::Or, a, b) => Val::Bool(eval(a, rec).truthy() || eval(b, rec).truthy()),
+        Node::Binary(op, a, b) => binary(*op, eval(a, rec), eval(b, rec)),
+        Node::Call(name, args) => call(name, args, rec),
+    }
+}
+
+fn binary(op: Op, a: Val, b: Val) -> Val {
+    if matches!(op, Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem) {
+        let (Some(x), Some(y)) = (a.num(), b.num()) else {
+            return Val::Null;
+        };
+        let n = match op {
+            Op::Add => x + y,
+            Op::Sub => x - y,
+            Op::Mul => x * y,
+            Op::Div if y != 0.0 => x / y,
+            Op::Rem if y != 0.0 => x % y,
+            _ => return Val::Null,
+        };
+        return if n.is_finite() {
+            Val::Num(n)
+        } else {
+            Val::Null
+        };
+    }
+    // numbers compare as numbers, anything else as text
+    let ord = match (a.num(), b.num()) {
+        (Some(x), Some(y)) => x.partial_cmp(&y),
+        _ => Some(a.text().cmp(&b.text())),
+    };
+    let Some(ord) = ord else {
+        return Val::Null;
+    };
+    Val::Bool(match op {
+        Op::Eq => ord.is_eq(),
+        Op::Ne => ord.is_ne(),
+        Op::Lt => ord.is_lt(),
+        Op::Le => ord.is_le(),
+        Op::Gt => ord.is_gt(),
+        _ => ord.is_ge(),
+    })
+}
+
+fn call(name: &str, args: &[Node], rec: &[String]) -> Val {
+    // `if` and `coalesce` only evaluate the arguments they need
+    match name {
+        "if" => {
+            let branch = if eval(&args[0], rec).truthy() { 1 } else { 2 };
+            return eval(&args[branch], rec);
+        }
+        "coalesce" => {
+            return args
+                .iter()
+                .map(|a| eval(a, rec))
+                .find(|v| !matches!(v, Val::Null))
+                .unwrap_or(Val::Null);
+        }
+        _ => {}
+    }
+    let vals: Vec<Val> = args.iter().map(|a| eval(a, rec)).collect();
+    let num = |i: usize| vals.get(i).and_then(Val::num);
+    let text = |i: usize| vals[i].text();
+    let number = |n: Option<f64>| n.map_or(Val::Null, Val::Num);
+    let string = |s: String| if s.is_empty() { Val::Null } else { Val::Str(s) };
+    match name {
+        "concat" => string(vals.iter().map(Val::text).collect()),
+        "upper" => string(text(0).to_uppercase()),
+        "lower" => string(text(0).to_lowercase()),
+        "trim" => string(text(0).trim().to_string()),
+        "length" => Val::Num(text(0).chars().count() as f64),
+        "substr" => {
+            let (Some(start), len) = (num(1), num(2)) else {
+                return Val::Null;
+            };
+            let s = text(0);
+            let chars = s.chars().skip(start.max(0.0) as usize);
+            string(match len {
+                Some(len) => chars.take(len.max(0.0) as usize).collect(),
+                None => chars.collect(),
+            })
+        }
+        "replace" => string(text(0).replace(&text(1), &text(2))),
+        "round" => {
+            let scale = 10f64.powi(num(1).unwrap_or(0.0) as i32);
+            number(num(0).map(|n| (n * scale).round() / scale))
+        }
+        "floor" => number(num(0).map(f64::floor)),
+        "ceil" => number(num(0).map(f64::ceil)),
+        "abs" => number(num(0).map(f64::abs)),
+        "min" => number(vals.iter().filter_map(Val::num).reduce(f64::min)),
+        "max" => number(vals.iter().filter_map(Val::num).reduce(f64::max)),
+        _ => Val::Null,
+    }
+}
+
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Num(n) => format!("number {}", n),
+            Token::Str(s) => format!("string {:?}", s),
+            Token::Ident(s) => format!("name {}", s),
+            Token::Punct(p) => format!("'{}'", p),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    const PUNCT: &[&str] = &[
+        "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "!", "(", ")", ",",
+    ];
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if let Some(p) = PUNCT.iter().find(|p| rest.starts_with(**p)) {
+            tokens.push(Token::Punct(p));
+            rest = &rest[p.len()..];
+        } else if c == '"' || c == '\'' || c == '`' {
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, ch)) if ch == c => break i + 1,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, ch)) => value.push(ch),
+                        None => return Err("unterminated string in expression".into()),
+                    },
+                    Some((_, ch)) => value.push(ch),
+                    None => return Err("unterminated string in expression".into()),
+                }
+            };
+            tokens.push(if c == '`' {
+                Token::Ident(value)
+            } else {
+                Token::Str(value)
+            });
+            rest = &rest[end + 1..];
+        } else if c.is_ascii_digit() || c == '.' {
+            let end = rest
+                .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+                .unwrap_or(rest.len());
+            let n = rest[..end]
+                .parse()
+                .map_err(|_| format!("invalid number {} in expression", &rest[..end]))?;
+            tokens.push(Token::Num(n));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' || c == '@' {
+            let end = rest
+                .find(|ch: char| !(ch.is_alphanumeric() || matches!(ch, '_' | '.' | '@')))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(format!("unexpected character {:?} in expression", c));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn eat(&mut self, punct: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Punct(p)) if *p == punct => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Left-associative binary operators of one precedence level
+    fn level(
+        &mut self,
+        ops: &[(&str, Op)],
+        next: fn(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        let mut node = next(self)?;
+        'outer: loop {
+            for &(punct, op) in ops {
+                if self.eat(punct) {
+                    node = Node::Binary(op, Box::new(node), Box::new(next(self)?));
+                    continue 'outer;
+                }
+            }
+            return Ok(node);
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        self.level(&[("||", Op::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        self.level(&[("&&", Op::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        self.level(
+            &[
+                ("==", Op::Eq),
+                ("!=", Op::Ne),
+                ("<=", Op::Le),
+                (">=", Op::Ge),
+                ("<", Op::Lt),
+                (">", Op::Gt),
+            ],
+            Self::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Node, String> {
+        self.level(&[("+", Op::Add), ("-", Op::Sub)], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Node, String> {
+        self.level(
+            &[("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem)],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat("-") {
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        match token {
+            Token::Num(n) => Ok(Node::Num(*n)),
+            Token::Str(s) => Ok(Node::Str(s.clone())),
+            Token::Punct("(") => {
+                let node = self.or()?;
+                if !self.eat(")") {
+                    return Err("missing ')' in expression".into());
+                }
+                Ok(node)
+            }
+            Token::Ident(name) => {
+                let name = name.clone();
+                if !self.eat("(") {
+                    return Ok(Node::Column { name, index: 0 });
+                }
+                let &(func, min, max) = FUNCTIONS
+                    .iter()
+                    .find(|(f, _, _)| *f == name)
+                    .ok_or_else(|| format!("unknown function {}", name))?;
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.or()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        if !self.eat(",") {
+                            return Err(format!("expected ',' or ')' in call to {}", name));
+                        }
+                    }
+                }
+                if args.len() < min || args.len() > max {
+                    return Err(format!("wrong number of arguments to {}", name));
+                }
+                Ok(Node::Call(func, args))
+            }
+            t => Err(format!("unexpected {} in expression", t.describe())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `expr` evaluated on one row of named cells
+    fn run(expr: &str, row: &[(&str, &str)]) -> String {
+        let mut expr = Expr::parse(expr).unwrap();
+        let columns: Vec<String> = row.iter().map(|(name, _)| name.to_string()).collect();
+        expr.bind(&columns).unwrap();
+        let rec: Vec<String> = row.iter().map(|(_, value)| value.to_string()).collect();
+        expr.eval(&rec)
+    }
+
+    fn query(expr: &str) -> Result<String, String> {
+        Expr::parse(expr).unwrap().to_query()
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        assert_eq!(run("1 + 2 * 3", &[]), "7");
+        assert_eq!(run("(1 + 2) * 3", &[]), "9");
+        assert_eq!(run("10 - 4 - 3", &[]), "3");
+        assert_eq!(run("7 / 2", &[]), "3.5");
+        assert_eq!(run("7 % 4 * 2", &[]), "6");
+        assert_eq!(run("-2 * 3", &[]), "-6");
+        assert_eq!(run("1 + 1 == 2 && 3 < 2 || !0", &[]), "true");
+    }
+
+    #[test]
+    fn compares_numbers_as_numbers_and_the_rest_as_text() {
+        assert_eq!(run("'10' > '9'", &[]), "true");
+        assert_eq!(run("'b' > 'a'", &[]), "true");
+        assert_eq!(run("x == 'abc'", &[("x", "abc")]), "true");
+        assert_eq!(run("x != 3", &[("x", "3.0")]), "false");
+    }
+
+    #[test]
+    fn reads_quoted_strings_with_escapes() {
+        assert_eq!(run(r#"'it\'s'"#, &[]), "it's");
+        assert_eq!(run(r#""say \"hi\"""#, &[]), r#"say "hi""#);
+        assert_eq!(run(r#"concat('a', "b", 'c\\d')"#, &[]), r"abc\d");
+        assert!(Expr::parse("'open").is_err());
+        assert!(Expr::parse(r"'ends in a backslash\").is_err());
+    }
+
+    #[test]
+    fn backticks_name_columns() {
+        let row = [
+            ("Total Sales", "12"),
+            ("a`b", "x"),
+            ("address.city", "Oslo"),
+        ];
+        assert_eq!(run("`Total Sales` * 2", &row), "24");
+        assert_eq!(run(r"`a\`b`", &row), "x");
+        assert_eq!(run("upper(address.city)", &row), "OSLO");
+        let mut expr = Expr::parse("missing + 1").unwrap();
+        assert_eq!(
+            expr.bind(&["x".to_string()]).unwrap_err(),
+            "no column named missing"
+        );
+    }
+
+    #[test]
+    fn arithmetic_with_null_is_null() {
+        let row = [("qty", ""), ("name", "pear")];
+        assert_eq!(run("qty * 2", &row), "");
+        assert_eq!(run("-qty", &row), "");
+        assert_eq!(run("name + 1", &row), "");
+        assert_eq!(run("1 / 0", &row), "");
+        assert_eq!(run("5 % 0", &row), "");
+        assert_eq!(run("coalesce(qty, 0) * 2", &row), "0");
+        assert_eq!(run("qty == ''", &row), "true");
+        assert_eq!(run("!qty", &row), "true");
+    }
+
+    #[test]
+    fn if_and_coalesce_take_the_arguments_they_need() {
+        let row = [("price", "3"), ("discount", ""), ("fallback", "1")];
+        assert_eq!(run("if(price > 2, 'high', 1 / 0)", &row), "high");
+        assert_eq!(run("if(discount, 1 / 0, 'none')", &row), "none");
+        assert_eq!(run("coalesce(discount, fallback, 1 / 0)", &row), "1");
+        assert_eq!(run("coalesce(discount, 1 / 0)", &row), "");
+    }
+
+    #[test]
+    fn calls_functions() {
+        let row = [("s", "  Hello World  ")];
+        assert_eq!(run("trim(s)", &row), "Hello World");
+        assert_eq!(run("length(trim(s))", &row), "11");
+        assert_eq!(run("substr(trim(s), 6)", &row), "World");
+        assert_eq!(run("substr(trim(s), 0, 5)", &row), "Hello");
+        assert_eq!(
+            run("replace(trim(s), 'World', 'there')", &row),
+            "Hello there"
+        );
+        assert_eq!(run("round(2.456, 2)", &row), "2.46");
+        assert_eq!(run("min(3, 'x', 1)", &row), "1");
+        assert_eq!(run("max(3, 1)", &row), "3");
+        assert_eq!(
+            Expr::parse("nope(1)").err().unwrap(),
+            "unknown function nope"
+        );
+        assert_eq!(
+            Expr::parse("if(1, 2)").err().unwrap(),
+            "wrong number of arguments to if"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert_eq!(
+            Expr::parse("(1 + 2").err().unwrap(),
+            "missing ')' in expression"
+        );
+        assert_eq!(
+            Expr::parse("1 2").err().unwrap(),
+            "unexpected number 2 in expression"
+        );
+        assert_eq!(
+            Expr::parse("1 +").err().unwrap(),
+            "unexpected end of expression"
+        );
+        assert_eq!(
+            Expr::parse("a # b").err().unwrap(),
+            "unexpected character '#' in expression"
+        );
+    }
+
+    #[test]
+    fn comparisons_become_term_and_range_queries() {
+        assert_eq!(
+            query("status == 'active'").unwrap(),
+            r#"{"term":{"status":"active"}}"#
+        );
+        assert_eq!(
+            query("status != 'old'").unwrap(),
+            r#"{"bool":{"must_not":[{"term":{"status":"old"}}]}}"#
+        );
+        assert_eq!(
+            query("age >= 18").unwrap(),
+            r#"{"range":{"age":{"gte":18}}}"#
+        );
+        assert_eq!(
+            query("age < 1.5").unwrap(),
+            r#"{"range":{"age":{"lt":1.5}}}"#
+        );
+        assert_eq!(query("1 < age").unwrap(), r#"{"range":{"age":{"gt":1}}}"#);
+        assert_eq!(query("t <= -2").unwrap(), r#"{"range":{"t":{"lte":-2}}}"#);
+        assert_eq!(
+            query(r#"`a"b` == 'x'"#).unwrap(),
+            r#"{"term":{"a\"b":"x"}}"#
+        );
+    }
+
+    #[test]
+    fn comparing_with_an_empty_string_tests_for_the_field() {
+        assert_eq!(
+            query("email == ''").unwrap(),
+            r#"{"bool":{"must_not":[{"exists":{"field":"email"}}]}}"#
+        );
+        assert_eq!(
+            query("email != ''").unwrap(),
+            r#"{"exists":{"field":"email"}}"#
+        );
+        assert!(query("email < ''").is_err());
+    }
+
+    #[test]
+    fn logic_becomes_bool_queries() {
+        assert_eq!(
+            query("a == 1 && !(b == 2 || c > 3)").unwrap(),
+            concat!(
+                r#"{"bool":{"filter":[{"term":{"a":1}},{"bool":{"must_not":[{"bool":{"should":"#,
+                r#"[{"term":{"b":2}},{"range":{"c":{"gt":3}}}]}}]}}]}}"#
+            )
+        );
+        assert!(query("a + 1 == 2").is_err());
+        assert!(query("a == b").is_err());
+        assert!(query("a").is_err());
+    }
+}
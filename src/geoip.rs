@@ -0,0 +1,612 @@
+//! Offline IP geolocation for `--geoip`, from a MaxMind DB file such as
+//! GeoLite2-City.mmdb: a binary search tree over the address bits whose
+//! leaves point into a section of typed data records
+
+use std::cmp::Ordering;
+use std::net::IpAddr;
+
+use crate::json::Value;
+
+/// Fields added for each address, named as the geoip ingest processor
+/// names them
+pub const FIELDS: &[&str] = &[
+    "continent_name",
+    "country_iso_code",
+    "country_name",
+    "region_name",
+    "city_name",
+    "location",
+];
+
+/// Start of the metadata, near the end of the file
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+/// Maps and arrays nested deeper than this are taken for a corrupt file,
+/// as libmaxminddb does
+const MAX_DEPTH: usize = 512;
+
+pub struct Database {
+    buf: Vec<u8>,
+    node_count: usize,
+    /// Bits per record, two records to a node
+    record_size: usize,
+    ip_version: u64,
+    /// Node that IPv4 addresses start from in an IPv6 tree
+    ipv4_start: usize,
+    /// Offset of the data section
+    data: usize,
+}
+
+impl Database {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let buf = std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        Self::from_bytes(buf, path)
+    }
+
+    /// The database in `buf`, read from `path`
+    fn from_bytes(buf: Vec<u8>, path: &str) -> Result<Self, String> {
+        let bad = || format!("{} is not a MaxMind DB file", path);
+        let marker = buf
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .ok_or_else(bad)?;
+        let start = marker + METADATA_MARKER.len();
+        let decoder = Decoder {
+            buf: &buf,
+            base: start,
+        };
+        let metadata = decoder.value(start).map_err(|_| bad())?.0;
+        let field = |name| metadata.get(name).and_then(Value::as_u64);
+        let (Some(node_count), Some(record_size), Some(ip_version)) = (
+            field("node_count"),
+            field("record_size"),
+            field("ip_version"),
+        ) else {
+            return Err(bad());
+        };
+        if ![24, 28, 32].contains(&record_size) {
+            return Err(format!("{}: unsupported record size {}", path, record_size));
+        }
+        let data = (node_count as usize)
+            .checked_mul(record_size as usize / 4)
+            .and_then(|tree| tree.checked_add(16))
+            .filter(|&data| data <= marker)
+            .ok_or_else(bad)?;
+        let mut db = Self {
+            buf,
+            node_count: node_count as usize,
+            record_size: record_size as usize,
+            ip_version,
+            ipv4_start: 0,
+            data,
+        };
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= db.node_count {
+                    break;
+                }
+                node = db.record(node, 0)?;
+            }
+            db.ipv4_start = node;
+        }
+        Ok(db)
+    }
+
+    /// Values of `FIELDS` for `ip`: English names, and the location as
+    /// "lat,lon"; all empty for addresses it doesn't know or can't read
+    pub fn locate(&self, ip: &str) -> Vec<String> {
+        let record = ip
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|ip| self.lookup(ip).ok().flatten());
+        let Some(record) = record else {
+            return vec![String::new(); FIELDS.len()];
+        };
+        let text = |v: Option<&Value>| match v {
+            Some(Value::String(s) | Value::Number(s)) => s.clone(),
+            _ => String::new(),
+        };
+        let name = |v: Option<&Value>| text(v.and_then(|v| v.get("names")?.get("en")));
+        let coordinate = |c| text(record.get("location").and_then(|l| l.get(c)));
+        let (lat, lon) = (coordinate("latitude"), coordinate("longitude"));
+        let region = record
+            .get("subdivisions")
+            .and_then(Value::as_array)
+            .and_then(<[Value]>::first);
+        vec![
+            name(record.get("continent")),
+            text(record.get("country").and_then(|c| c.get("iso_code"))),
+            name(record.get("country")),
+            name(region),
+            name(record.get("city")),
+            match lat.is_empty() || lon.is_empty() {
+                true => String::new(),
+                false => format!("{},{}", lat, lon),
+            },
+        ]
+    }
+
+    /// The data record for `ip`, if the database has one
+    fn lookup(&self, ip: IpAddr) -> Result<Option<Value>, String> {
+        let (bytes, mut node) = match ip {
+            IpAddr::V4(v4) if self.ip_version == 6 => (v4.octets().to_vec(), self.ipv4_start),
+            IpAddr::V4(v4) => (v4.octets().to_vec(), 0),
+            IpAddr::V6(_) if self.ip_version == 4 => return Ok(None),
+            IpAddr::V6(v6) => (v6.octets().to_vec(), 0),
+        };
+        for i in 0..bytes.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (bytes[i / 8] >> (7 - i % 8)) & 1;
+            node = self.record(node, bit)?;
+        }
+        match node.cmp(&self.node_count) {
+            Ordering::Less => Err("MaxMind DB search tree deeper than the address".to_string()),
+            Ordering::Equal => Ok(None),
+            Ordering::Greater => {
+                let offset = (node - self.node_count)
+                    .checked_sub(16)
+                    .ok_or("MaxMind DB record points before the data section")?;
+                let decoder = Decoder {
+                    buf: &self.buf,
+                    base: self.data,
+                };
+                Ok(Some(decoder.value(self.data + offset)?.0))
+            }
+        }
+    }
+
+    /// Left (`bit` 0) or right record of `node`: another node, the node
+    /// count for no data, or past it a pointer into the data section
+    fn record(&self, node: usize, bit: u8) -> Result<usize, String> {
+        let size = self.record_size / 4;
+        let b = self
+            .buf
+            .get(node * size..(node + 1) * size)
+            .ok_or("truncated MaxMind DB search tree")?;
+        Ok(match (self.record_size, bit) {
+            (24, 0) => be(&b[..3]),
+            (24, _) => be(&b[3..]),
+            (28, 0) => (b[3] as usize >> 4) << 24 | be(&b[..3]),
+            (28, _) => (b[3] as usize & 0x0f) << 24 | be(&b[4..]),
+            (_, 0) => be(&b[..4]),
+            _ => be(&b[4..]),
+        })
+    }
+}
+
+/// Big-endian unsigned integer
+fn be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| n << 8 | b as usize)
+}
+
+/// Reads typed values; pointers are offsets from `base`
+struct Decoder<'a> {
+    buf: &'a [u8],
+    base: usize,
+}
+
+impl Decoder<'_> {
+    fn bytes(&self, at: usize, n: usize) -> Result<&[u8], String> {
+        at.checked_add(n)
+            .and_then(|end| self.buf.get(at..end))
+            .ok_or_else(|| "truncated MaxMind DB data".to_string())
+    }
+
+    /// The value at `at` and the offset after it
+    fn value(&self, at: usize) -> Result<(Value, usize), String> {
+        self.nested(at, 0)
+    }
+
+    /// The value at `at`, inside `depth` maps and arrays
+    fn nested(&self, at: usize, depth: usize) -> Result<(Value, usize), String> {
+        if depth > MAX_DEPTH {
+            return Err("MaxMind DB data nested too deep".to_string());
+        }
+        let ctrl = self.bytes(at, 1)?[0];
+        let mut at = at + 1;
+        let mut kind = ctrl >> 5;
+        if kind == 1 {
+            let n = ((ctrl >> 3) & 3) as usize + 1;
+            let high = (ctrl & 7) as usize;
+            let low = be(self.bytes(at, n)?);
+            let offset = match n {
+                1 => high << 8 | low,
+                2 => (high << 16 | low) + 2048,
+                3 => (high << 24 | low) + 526336,
+                _ => low,
+            };
+            // pointers don't point at pointers
+            let target = self.base + offset;
+            if self.bytes(target, 1)?[0] >> 5 == 1 {
+                return Err("MaxMind DB pointer to a pointer".to_string());
+            }
+            return Ok((self.nested(target, depth + 1)?.0, at + n));
+        }
+        if kind == 0 {
+            kind = 7 + self.bytes(at, 1)?[0];
+            at += 1;
+        }
+        let mut size = (ctrl & 0x1f) as usize;
+        if size >= 29 {
+            let n = size - 28;
+            let extra = be(self.bytes(at, n)?);
+            at += n;
+            size = [29, 285, 65821][n - 1] + extra;
+        }
+        // integers wider than their type, if they fit at all, are corrupt
+        let width = match kind {
+            5 => 2,
+            8 | 6 => 4,
+            9 => 8,
+            10 => 16,
+            _ => usize::MAX,
+        };
+        if size > width {
+            return Err(format!("MaxMind DB integer of {} bytes", size));
+        }
+        let number = |n: String| Ok((Value::Number(n), at + size));
+        match kind {
+            2 => {
+                let s = String::from_utf8_lossy(self.bytes(at, size)?).into_owned();
+                Ok((Value::String(s), at + size))
+            }
+            3 => {
+                let b: [u8; 8] = self.bytes(at, size)?.try_into().map_err(|_| "bad double")?;
+                number(f64::from_be_bytes(b).to_string())
+            }
+            15 => {
+                let b: [u8; 4] = self.bytes(at, size)?.try_into().map_err(|_| "bad float")?;
+                number(f32::from_be_bytes(b).to_string())
+            }
+            5 | 6 | 9 | 10 => {
+                let b = self.bytes(at, size)?;
+                number(b.iter().fold(0u128, |n, &b| n << 8 | b as u128).to_string())
+            }
+            8 => number((be(self.bytes(at, size)?) as u32 as i32).to_string()),
+            14 => Ok((Value::Bool(size != 0), at)),
+            // raw bytes have no JSON form
+            4 => Ok((Value::Null, at + size)),
+            // every entry takes at least a byte for its key and one for its
+            // value, which bounds what a corrupt size can make us allocate
+            7 => {
+                let mut map = Vec::with_capacity(size.min(self.room(at) / 2));
+                for _ in 0..size {
+                    let (key, next) = self.nested(at, depth + 1)?;
+                    let (value, next) = self.nested(next, depth + 1)?;
+                    map.push((key.as_str().unwrap_or_default().to_string(), value));
+                    at = next;
+                }
+                Ok((Value::Object(map), at))
+            }
+            11 => {
+                let mut items = Vec::with_capacity(size.min(self.room(at)));
+                for _ in 0..size {
+                    let (value, next) = self.nested(at, depth + 1)?;
+                    items.push(value);
+                    at = next;
+                }
+                Ok((Value::Array(items), at))
+            }
+            _ => Err(format!("unsupported MaxMind DB data type {}", kind)),
+        }
+    }
+
+    /// Bytes left from `at` to the end of the buffer
+    fn room(&self, at: usize) -> usize {
+        self.buf.len().saturating_sub(at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Control byte(s) of a value of `kind` and `size`
+    fn ctrl(kind: u8, size: usize) -> Vec<u8> {
+        let (code, extra) = match size {
+            0..29 => (size as u8, vec![]),
+            29..285 => (29, vec![(size - 29) as u8]),
+            285..65821 => (30, ((size - 285) as u16).to_be_bytes().to_vec()),
+            _ => (31, ((size - 65821) as u32).to_be_bytes()[1..].to_vec()),
+        };
+        let mut out = match kind {
+            0..=7 => vec![kind << 5 | code],
+            _ => vec![code, kind - 7],
+        };
+        out.extend(extra);
+        out
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        [ctrl(2, s.len()), s.as_bytes().to_vec()].concat()
+    }
+
+    fn uint(kind: u8, n: u64, width: usize) -> Vec<u8> {
+        [ctrl(kind, width), n.to_be_bytes()[8 - width..].to_vec()].concat()
+    }
+
+    fn double(f: f64) -> Vec<u8> {
+        [ctrl(3, 8), f.to_be_bytes().to_vec()].concat()
+    }
+
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut out = ctrl(7, entries.len());
+        for (key, value) in entries {
+            out.extend(string(key));
+            out.extend(value);
+        }
+        out
+    }
+
+    fn array(items: &[Vec<u8>]) -> Vec<u8> {
+        [ctrl(11, items.len()), items.concat()].concat()
+    }
+
+    /// A pointer to `offset` in the data section
+    fn pointer(offset: usize) -> Vec<u8> {
+        vec![0x20 | (offset >> 8) as u8, offset as u8]
+    }
+
+    fn names(en: &str) -> Vec<u8> {
+        map(&[("names", map(&[("en", string(en))]))])
+    }
+
+    #[derive(Clone, Copy)]
+    enum Record {
+        Node(usize),
+        Empty,
+        Data(usize),
+    }
+
+    /// A database file: a search tree with `networks` (an address, its
+    /// prefix length and the offset of its record in `data`), then `data`
+    /// and the metadata
+    fn build(
+        ip_version: u64,
+        record_size: usize,
+        networks: &[(&str, usize, usize)],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut nodes = vec![[Record::Empty; 2]];
+        for &(ip, prefix, offset) in networks {
+            let (bytes, prefix) = match ip.parse().unwrap() {
+                IpAddr::V4(v4) if ip_version == 6 => {
+                    ([vec![0; 12], v4.octets().to_vec()].concat(), prefix + 96)
+                }
+                IpAddr::V4(v4) => (v4.octets().to_vec(), prefix),
+                IpAddr::V6(v6) => (v6.octets().to_vec(), prefix),
+            };
+            let mut node = 0;
+            for i in 0..prefix {
+                let bit = (bytes[i / 8] >> (7 - i % 8)) as usize & 1;
+                if i + 1 == prefix {
+                    nodes[node][bit] = Record::Data(offset);
+                } else if let Record::Node(next) = nodes[node][bit] {
+                    node = next;
+                } else {
+                    nodes.push([Record::Empty; 2]);
+                    nodes[node][bit] = Record::Node(nodes.len() - 1);
+                    node = nodes.len() - 1;
+                }
+            }
+        }
+        let count = nodes.len();
+        let value = |r: Record| match r {
+            Record::Node(i) => i as u64,
+            Record::Empty => count as u64,
+            Record::Data(offset) => (count + 16 + offset) as u64,
+        };
+        let mut buf = Vec::new();
+        for [left, right] in nodes {
+            let (l, r) = (value(left), value(right));
+            match record_size {
+                24 => {
+                    buf.extend(&l.to_be_bytes()[5..]);
+                    buf.extend(&r.to_be_bytes()[5..]);
+                }
+                28 => {
+                    buf.extend(&l.to_be_bytes()[5..]);
+                    buf.push(((l >> 24) << 4 | r >> 24) as u8);
+                    buf.extend(&r.to_be_bytes()[5..]);
+                }
+                _ => {
+                    buf.extend(&l.to_be_bytes()[4..]);
+                    buf.extend(&r.to_be_bytes()[4..]);
+                }
+            }
+        }
+        buf.extend([0; 16]);
+        buf.extend(data);
+        buf.extend(METADATA_MARKER);
+        buf.extend(map(&[
+            ("node_count", uint(6, count as u64, 4)),
+            ("record_size", uint(5, record_size as u64, 2)),
+            ("ip_version", uint(5, ip_version, 2)),
+        ]));
+        buf
+    }
+
+    fn berlin() -> Vec<u8> {
+        map(&[
+            ("city", names("Berlin")),
+            ("continent", names("Europe")),
+            (
+                "country",
+                map(&[
+                    ("iso_code", string("DE")),
+                    ("names", map(&[("en", string("Germany"))])),
+                ]),
+            ),
+            (
+                "location",
+                map(&[("latitude", double(52.5)), ("longitude", double(13.4))]),
+            ),
+            ("subdivisions", array(&[names("Land Berlin")])),
+        ])
+    }
+
+    fn open(buf: Vec<u8>) -> Database {
+        Database::from_bytes(buf, "test.mmdb").unwrap()
+    }
+
+    #[test]
+    fn finds_networks_with_every_record_size() {
+        for record_size in [24, 28, 32] {
+            let db = open(build(4, record_size, &[("1.2.3.0", 24, 0)], &berlin()));
+            assert_eq!(
+                db.locate("1.2.3.4"),
+                [
+                    "Europe",
+                    "DE",
+                    "Germany",
+                    "Land Berlin",
+                    "Berlin",
+                    "52.5,13.4"
+                ],
+                "record size {}",
+                record_size
+            );
+            assert_eq!(db.locate("1.2.4.4"), vec![String::new(); FIELDS.len()]);
+            assert_eq!(db.locate("::1"), vec![String::new(); FIELDS.len()]);
+        }
+    }
+
+    #[test]
+    fn looks_up_ipv4_in_an_ipv6_tree() {
+        let data = [berlin(), map(&[("city", names("Paris"))])].concat();
+        let paris = berlin().len();
+        let db = open(build(
+            6,
+            28,
+            &[("10.0.0.0", 8, 0), ("2001:db8::", 32, paris)],
+            &data,
+        ));
+        assert_eq!(db.locate("10.1.2.3")[4], "Berlin");
+        assert_eq!(db.locate(" 2001:db8::1 ")[4], "Paris");
+        assert_eq!(db.locate("2001:db9::1")[4], "");
+        assert_eq!(db.locate("not an address")[4], "");
+    }
+
+    #[test]
+    fn follows_pointers_into_the_data_section() {
+        // records share the values they have in common, as in real
+        // databases: the city points at its country, which points at its names
+        let names_en = map(&[("en", string("Germany"))]);
+        let germany = map(&[("iso_code", string("DE")), ("names", pointer(0))]);
+        let city_at = names_en.len() + germany.len();
+        let city = map(&[
+            ("country", pointer(names_en.len())),
+            ("city", names("Bonn")),
+        ]);
+        let data = [names_en, germany, city].concat();
+        let db = open(build(4, 24, &[("5.0.0.0", 8, city_at)], &data));
+        let located = db.locate("5.6.7.8");
+        assert_eq!(
+            (located[1].as_str(), located[2].as_str()),
+            ("DE", "Germany")
+        );
+        assert_eq!(located[4], "Bonn");
+    }
+
+    #[test]
+    fn rejects_a_pointer_to_a_pointer() {
+        let data = [pointer(2), pointer(0)].concat();
+        let db = open(build(4, 24, &[("5.0.0.0", 8, 2)], &data));
+        let err = db.lookup("5.0.0.1".parse().unwrap()).unwrap_err();
+        assert!(err.contains("pointer to a pointer"), "{}", err);
+    }
+
+    #[test]
+    fn decodes_every_data_type() {
+        let long = "x".repeat(300);
+        let data = map(&[
+            ("long", string(&long)),
+            ("medium", string(&"y".repeat(40))),
+            ("uint16", uint(5, 443, 2)),
+            ("uint64", uint(9, u64::MAX, 8)),
+            ("int32", uint(8, 0xffff_fffe, 4)),
+            (
+                "float",
+                [ctrl(15, 4), 1.5f32.to_be_bytes().to_vec()].concat(),
+            ),
+            ("yes", ctrl(14, 1)),
+            ("bytes", [ctrl(4, 2), vec![1, 2]].concat()),
+            ("empty", array(&[])),
+        ]);
+        let decoder = Decoder {
+            buf: &data,
+            base: 0,
+        };
+        let (value, end) = decoder.value(0).unwrap();
+        assert_eq!(end, data.len());
+        let text = |key| match value.get(key) {
+            Some(Value::String(s) | Value::Number(s)) => s.clone(),
+            _ => panic!("{} is not a string or number", key),
+        };
+        assert_eq!(text("long"), long);
+        assert_eq!(text("medium").len(), 40);
+        assert_eq!(text("uint16"), "443");
+        assert_eq!(text("uint64"), u64::MAX.to_string());
+        assert_eq!(text("int32"), "-2");
+        assert_eq!(text("float"), "1.5");
+        assert!(matches!(value.get("yes"), Some(Value::Bool(true))));
+        assert!(matches!(value.get("bytes"), Some(Value::Null)));
+        assert_eq!(
+            value
+                .get("empty")
+                .and_then(Value::as_array)
+                .map(<[Value]>::len),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn corrupt_sizes_fail_without_allocating_them() {
+        // a map of 16 million entries with nothing after it
+        let data = ctrl(7, 16_843_036);
+        let err = Decoder {
+            buf: &data,
+            base: 0,
+        }
+        .value(0)
+        .unwrap_err();
+        assert!(err.contains("truncated"), "{}", err);
+        let data = [ctrl(10, 17), vec![0; 17]].concat();
+        let err = Decoder {
+            buf: &data,
+            base: 0,
+        }
+        .value(0)
+        .unwrap_err();
+        assert!(err.contains("integer of 17 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_data_nested_too_deep() {
+        let data = [vec![ctrl(11, 1); MAX_DEPTH + 1].concat(), array(&[])].concat();
+        let err = Decoder {
+            buf: &data,
+            base: 0,
+        }
+        .value(0)
+        .unwrap_err();
+        assert!(err.contains("nested too deep"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_files_without_metadata_or_a_tree() {
+        let err = Database::from_bytes(b"not a database".to_vec(), "x.mmdb").err();
+        assert_eq!(err.as_deref(), Some("x.mmdb is not a MaxMind DB file"));
+        let mut buf = METADATA_MARKER.to_vec();
+        buf.extend(map(&[
+            ("node_count", uint(6, 1000, 4)),
+            ("record_size", uint(5, 24, 2)),
+            ("ip_version", uint(5, 4, 2)),
+        ]));
+        let err = Database::from_bytes(buf, "x.mmdb").err();
+        assert_eq!(err.as_deref(), Some("x.mmdb is not a MaxMind DB file"));
+    }
+}
@@ -0,0 +1,171 @@
+//! Grok-style line patterns for `--format log`
+
+use regex::Regex;
+
+/// Built-in `%{NAME}` patterns, a subset of Logstash's grok-patterns
+/// rewritten for the `regex` crate (no look-around)
+const PATTERNS: &[(&str, &str)] = &[
+    ("USERNAME", r"[a-zA-Z0-9._-]+"),
+    ("USER", r"%{USERNAME}"),
+    ("INT", r"[+-]?[0-9]+"),
+    ("BASE10NUM", r"[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+)"),
+    ("NUMBER", r"%{BASE10NUM}"),
+    ("BASE16NUM", r"[+-]?(?:0x)?[0-9A-Fa-f]+"),
+    ("POSINT", r"\b[1-9][0-9]*\b"),
+    ("NONNEGINT", r"\b[0-9]+\b"),
+    ("WORD", r"\b\w+\b"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    ("QUOTEDSTRING", r#"(?:"(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*')"#),
+    ("QS", r"%{QUOTEDSTRING}"),
+    (
+        "UUID",
+        r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}",
+    ),
+    (
+        "IPV4",
+        r"(?:(?:25[0-5]|2[0-4][0-9]|1?[0-9]{1,2})\.){3}(?:25[0-5]|2[0-4][0-9]|1?[0-9]{1,2})",
+    ),
+    (
+        "IPV6",
+        r"(?:[0-9A-Fa-f]{0,4}:){2,7}(?:%{IPV4}|[0-9A-Fa-f]{1,4})?",
+    ),
+    ("IP", r"(?:%{IPV6}|%{IPV4})"),
+    (
+        "HOSTNAME",
+        r"\b[0-9A-Za-z][0-9A-Za-z-]{0,62}(?:\.[0-9A-Za-z][0-9A-Za-z-]{0,62})*\.?\b",
+    ),
+    ("IPORHOST", r"(?:%{IP}|%{HOSTNAME})"),
+    ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+    ("UNIXPATH", r"(?:/[^/\s]*)+"),
+    ("WINPATH", r"(?:[A-Za-z]+:|\\)(?:\\[^\\?*\s]*)+"),
+    ("PATH", r"(?:%{UNIXPATH}|%{WINPATH})"),
+    ("URIPROTO", r"[A-Za-z][A-Za-z0-9+.-]+"),
+    ("URIHOST", r"%{IPORHOST}(?::%{POSINT})?"),
+    ("URIPATH", r"(?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+"),
+    ("URIPARAM", r"\?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*"),
+    ("URIPATHPARAM", r"%{URIPATH}(?:%{URIPARAM})?"),
+    (
+        "URI",
+        r"%{URIPROTO}://(?:%{USER}(?::[^@]*)?@)?(?:%{URIHOST})?(?:%{URIPATHPARAM})?",
+    ),
+    (
+        "MONTH",
+        r"\b(?:Jan(?:uary)?|Feb(?:ruary)?|Mar(?:ch)?|Apr(?:il)?|May|June?|July?|Aug(?:ust)?|Sep(?:tember)?|Oct(?:ober)?|Nov(?:ember)?|Dec(?:ember)?)\b",
+    ),
+    ("MONTHNUM", r"(?:0?[1-9]|1[0-2])"),
+    ("MONTHDAY", r"(?:0[1-9]|[12][0-9]|3[01]|[1-9])"),
+    (
+        "DAY",
+        r"(?:Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?)",
+    ),
+    ("YEAR", r"(?:\d\d){1,2}"),
+    ("HOUR", r"(?:2[0123]|[01]?[0-9])"),
+    ("MINUTE", r"(?:[0-5][0-9])"),
+    ("SECOND", r"(?:(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?)"),
+    ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+    ("DATE_US", r"%{MONTHNUM}[/-]%{MONTHDAY}[/-]%{YEAR}"),
+    ("DATE_EU", r"%{MONTHDAY}[./-]%{MONTHNUM}[./-]%{YEAR}"),
+    ("ISO8601_TIMEZONE", r"(?:Z|[+-]%{HOUR}(?::?%{MINUTE}))"),
+    (
+        "TIMESTAMP_ISO8601",
+        r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?",
+    ),
+    ("HTTPDATE", r"%{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}"),
+    ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+    (
+        "LOGLEVEL",
+        r"(?:[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo?(?:rmation)?|INFO?(?:RMATION)?|[Ww]arn?(?:ing)?|WARN?(?:ING)?|[Ee]rr?(?:or)?|ERR?(?:OR)?|[Cc]rit?(?:ical)?|CRIT?(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|EMERG(?:ENCY)?|[Ee]merg(?:ency)?)",
+    ),
+    ("PROG", r"[\x21-\x5a\x5c\x5e-\x7e]+"),
+    ("SYSLOGPROG", r"%{PROG:program}(?:\[%{POSINT:pid}\])?"),
+    (
+        "SYSLOGBASE",
+        r"%{SYSLOGTIMESTAMP:timestamp} %{IPORHOST:logsource} %{SYSLOGPROG}:",
+    ),
+    (
+        "COMMONAPACHELOG",
+        r#"%{IPORHOST:clientip} %{USER:ident} %{USER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{NUMBER:response} (?:%{NUMBER:bytes}|-)"#,
+    ),
+    (
+        "COMBINEDAPACHELOG",
+        r"%{COMMONAPACHELOG} %{QS:referrer} %{QS:agent}",
+    ),
+];
+
+/// Access log layouts for `--format accesslog --preset`. A `-` for an
+/// absent value leaves the field empty.
+const PRESETS: &[(&str, &str)] = &[
+    (
+        "apache-common",
+        r#"^(?P<client_ip>\S+) (?:-|(?P<ident>\S+)) (?:-|(?P<user>\S+)) \[(?P<timestamp>[^\]]+)\] "(?:(?P<verb>[A-Z]+) (?P<path>\S+)(?: HTTP/(?P<http_version>[0-9.]+))?|[^"]*)" (?P<status>[0-9]{3}) (?:-|(?P<bytes>[0-9]+))"#,
+    ),
+    (
+        "apache-combined",
+        r#"^(?P<client_ip>\S+) (?:-|(?P<ident>\S+)) (?:-|(?P<user>\S+)) \[(?P<timestamp>[^\]]+)\] "(?:(?P<verb>[A-Z]+) (?P<path>\S+)(?: HTTP/(?P<http_version>[0-9.]+))?|[^"]*)" (?P<status>[0-9]{3}) (?:-|(?P<bytes>[0-9]+)) "(?:-|(?P<referrer>(?:[^"\\]|\\.)*))" "(?:-|(?P<user_agent>(?:[^"\\]|\\.)*))""#,
+    ),
+    // nginx's default "combined" log_format, often with $http_x_forwarded_for
+    // appended
+    (
+        "nginx",
+        r#"^(?P<client_ip>\S+) - (?:-|(?P<user>\S+)) \[(?P<timestamp>[^\]]+)\] "(?:(?P<verb>[A-Z]+) (?P<path>\S+)(?: HTTP/(?P<http_version>[0-9.]+))?|[^"]*)" (?P<status>[0-9]{3}) (?P<bytes>[0-9]+) "(?:-|(?P<referrer>(?:[^"\\]|\\.)*))" "(?:-|(?P<user_agent>(?:[^"\\]|\\.)*))"(?: "(?:-|(?P<forwarded_for>[^"]*))")?"#,
+    ),
+];
+
+/// Pattern of a built-in access log preset
+pub fn preset(name: &str) -> Result<Regex, String> {
+    let (_, pattern) = PRESETS.iter().find(|(n, _)| *n == name).ok_or_else(|| {
+        format!(
+            "unknown --preset {} (expected apache-common, apache-combined or nginx)",
+            name
+        )
+    })?;
+    Regex::new(pattern).map_err(|e| format!("invalid preset {}: {}", name, e))
+}
+
+/// Compile a line pattern: a regex whose named groups become fields, in
+/// which `%{NAME}` stands for a built-in pattern and `%{NAME:field}`
+/// captures it as `field`
+pub fn compile(pattern: &str) -> Result<Regex, String> {
+    let expanded = expand(pattern, 0)?;
+    let regex = Regex::new(&expanded).map_err(|e| format!("invalid --pattern: {}", e))?;
+    if regex.capture_names().flatten().next().is_none() {
+        return Err("--pattern has no named groups, e.g. %{WORD:level} or (?P<level>\\w+)".into());
+    }
+    Ok(regex)
+}
+
+fn expand(pattern: &str, depth: usize) -> Result<String, String> {
+    // the built-in patterns nest a few levels deep, never this many
+    if depth > 16 {
+        return Err("grok patterns nested too deeply".into());
+    }
+    let mut out = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find("%{") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unterminated {} in --pattern", &rest[start..]))?;
+        let spec = &rest[start + 2..start + end];
+        let (name, field) = match spec.split_once(':') {
+            Some((name, field)) => (name, Some(field)),
+            None => (spec, None),
+        };
+        let body = PATTERNS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, p)| *p)
+            .ok_or_else(|| format!("unknown grok pattern %{{{}}}", name))?;
+        let body = expand(body, depth + 1)?;
+        match field {
+            Some(field) => out.push_str(&format!("(?P<{}>{})", field, body)),
+            None => out.push_str(&format!("(?:{})", body)),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
@@ -0,0 +1,140 @@
+//! `--pre-hook` and `--post-hook`: ES requests or shell commands run before
+//! and after the import
+
+use std::process::Command;
+
+use clap::ValueEnum;
+
+use crate::json::{self, Value};
+use crate::{Auth, HttpTarget, http_request};
+
+/// What to do when a hook fails
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum HookErrorPolicy {
+    /// Stop with an error: before the import nothing is indexed, after it
+    /// the exit status reports the failure
+    Abort,
+    /// Print a warning and carry on
+    Warn,
+}
+
+pub enum Hook {
+    /// A request read from a JSON file as
+    /// `{"method": "PUT", "path": "/_ingest/pipeline/p", "body": {...}}`;
+    /// a string body is sent as it is, anything else as JSON
+    Request {
+        file: String,
+        method: String,
+        path: String,
+        body: String,
+    },
+    /// A command run with `sh -c`
+    Command(String),
+}
+
+impl Hook {
+    /// A hook from its option value: `!COMMAND` or the path of a request
+    /// file, which is read right away so a bad one fails before the import
+    pub fn load(spec: &str) -> Result<Self, String> {
+        if let Some(command) = spec.strip_prefix('!') {
+            return Ok(Self::Command(command.to_string()));
+        }
+        let text = std::fs::read_to_string(spec)
+            .map_err(|e| format!("cannot read hook {}: {}", spec, e))?;
+        let v = json::parse(&text).map_err(|e| format!("bad hook {}: {}", spec, e))?;
+        let field = |key: &str| {
+            v.get(key)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| format!("hook {} needs a string \"{}\"", spec, key))
+        };
+        let method = field("method")?.to_ascii_uppercase();
+        let path = field("path")?;
+        if !path.starts_with('/') {
+            return Err(format!("hook {}: path must start with /", spec));
+        }
+        let body = match v.get("body") {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+        };
+        Ok(Self::Request {
+            file: spec.to_string(),
+            method,
+            path,
+            body,
+        })
+    }
+
+    /// Run the hook; a request must get a 2xx response and a command must
+    /// exit with status 0. Commands see the cluster URL and index name in
+    /// `ES_HOST` and `ES_INDEX`.
+    pub fn run(
+        &self,
+        target: &HttpTarget,
+        auth: Option<&Auth>,
+        host: &str,
+        index: &str,
+    ) -> Result<(), String> {
+        match self {
+            Self::Request {
+                file,
+                method,
+                path,
+                body,
+            } => {
+                let full = format!("{}{}", target.base_path, path);
+                let content_type = if body.contains('\n') {
+                    "application/x-ndjson"
+                } else {
+                    "application/json"
+                };
+                let resp = http_request(target, method, &full, content_type, body, auth)
+                    .map_err(|e| format!("{}: {}", file, e))?;
+                if !(200..300).contains(&resp.status) {
+                    return Err(format!(
+                        "{}: {} {} returned HTTP {}: {}",
+                        file, method, path, resp.status, resp.body
+                    ));
+                }
+                Ok(())
+            }
+            Self::Command(command) => {
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("ES_HOST", host)
+                    .env("ES_INDEX", index)
+                    .status()
+                    .map_err(|e| format!("!{}: {}", command, e))?;
+                if !status.success() {
+                    return Err(format!("!{}: {}", command, status));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Run `hooks` in order, stopping at the first failure under
+/// `HookErrorPolicy::Abort`
+pub fn run_all(
+    stage: &str,
+    hooks: &[Hook],
+    policy: HookErrorPolicy,
+    target: &HttpTarget,
+    auth: Option<&Auth>,
+    host: &str,
+    index: &str,
+) -> Result<(), String> {
+    for hook in hooks {
+        if let Err(e) = hook.run(target, auth, host, index) {
+            let msg = format!("{} failed: {}", stage, e);
+            match policy {
+                HookErrorPolicy::Abort => return Err(msg),
+                HookErrorPolicy::Warn => log!("{} (continuing)", msg),
+            }
+        }
+    }
+    Ok(())
+}
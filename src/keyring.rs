@@ -0,0 +1,91 @@
+//! `--credential-profile`: passwords and API keys kept in the OS keyring,
+//! read with the platform's own tool (or API, on Windows) so they never
+//! appear on the command line
+
+#[cfg(unix)]
+use std::process::Command;
+
+/// Service name the keyring entries are stored under
+const SERVICE: &str = "elastic_importer";
+
+/// The secret stored for `profile`: a password, or an API key
+#[cfg(unix)]
+pub fn secret(profile: &str) -> Result<String, String> {
+    let (program, args) = lookup(profile);
+    let out = Command::new(program)
+        .args(&args)
+        .output()
+        .map_err(|e| format!("cannot run {}: {}", program, e))?;
+    if !out.status.success() {
+        return Err(format!(
+            "nothing stored for credential profile {} in the keyring: {}",
+            profile,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    let secret = String::from_utf8(out.stdout)
+        .map_err(|_| format!("secret of credential profile {} isn't UTF-8", profile))?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Keychain entry with service `SERVICE` and account `profile`
+#[cfg(target_os = "macos")]
+fn lookup(profile: &str) -> (&'static str, Vec<&str>) {
+    (
+        "security",
+        vec!["find-generic-password", "-s", SERVICE, "-a", profile, "-w"],
+    )
+}
+
+/// Secret Service item with attributes `service` and `profile`
+#[cfg(all(unix, not(target_os = "macos")))]
+fn lookup(profile: &str) -> (&'static str, Vec<&str>) {
+    (
+        "secret-tool",
+        vec!["lookup", "service", SERVICE, "profile", profile],
+    )
+}
+
+/// Generic Credential Manager entry named `SERVICE:profile`, as `cmdkey
+/// /generic` stores it: the secret is UTF-16
+#[cfg(windows)]
+pub fn secret(profile: &str) -> Result<String, String> {
+    use windows_sys::Win32::Security::Credentials::{
+        CRED_TYPE_GENERIC, CREDENTIALW, CredFree, CredReadW,
+    };
+
+    let target: Vec<u16> = format!("{}:{}", SERVICE, profile)
+        .encode_utf16()
+        .chain([0])
+        .collect();
+    let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
+    // SAFETY: the target name is NUL-terminated, and on success the
+    // credential is read before CredFree releases it
+    let units: Vec<u16> = unsafe {
+        if CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) == 0 {
+            return Err(format!(
+                "nothing stored for credential profile {} in the Credential Manager: {}",
+                profile,
+                std::io::Error::last_os_error()
+            ));
+        }
+        let c = &*credential;
+        let blob = match c.CredentialBlob.is_null() {
+            true => &[][..],
+            false => std::slice::from_raw_parts(c.CredentialBlob, c.CredentialBlobSize as usize),
+        };
+        let units = blob
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        CredFree(credential.cast());
+        units
+    };
+    String::from_utf16(&units)
+        .map_err(|_| format!("secret of credential profile {} isn't UTF-16", profile))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn secret(_profile: &str) -> Result<String, String> {
+    Err("--credential-profile needs the macOS Keychain, a Secret Service keyring or the Windows Credential Manager".into())
+}
@@ -1,74 +1,987 @@
+/// eprintln! with the --run-id of the import in front, once there is one
+macro_rules! log {
+    ($($arg:tt)*) => {
+        match $crate::RUN_ID.get() {
+            Some(id) => eprintln!("[{}] {}", id, format_args!($($arg)*)),
+            None => eprintln!($($arg)*),
+        }
+    };
+}
+
+mod analyze;
+mod cli;
+mod dashboard;
+mod export;
+mod expr;
+mod geoip;
+mod grok;
+mod hook;
+mod json;
+mod keyring;
+mod ndjson;
+mod netrc;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod prompt;
+mod schema;
+#[cfg(feature = "script")]
+mod script;
+mod sigv4;
+mod sniff;
+mod syslog;
+mod tls;
+mod tz;
+mod wkt;
+mod xml;
+
 use base64::Engine;
 use base64::engine::general_purpose;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use hmac::{Hmac, KeyInit, Mac};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::env;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read, Write};
-use std::net::TcpStream;
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, Utc};
+use memmap2::Mmap;
+use regex::Regex;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use cli::Format;
+use json::{Value, json_escape, write_escaped};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+use schema::ColumnType;
+use tls::TlsOptions;
+
+/// How to read and type the input file, for every subcommand that reads one
+struct InputOptions {
+    file: String,
+    csv: CsvOptions,
+    /// Rows sampled to decide each column's type; 0 types every value on
+    /// its own
+    infer_sample: usize,
+    /// Sample rows at random from the whole input instead of the first ones
+    infer_random: bool,
+    /// JSON file of declared column types, overriding inference
+    schema: Option<String>,
+    /// `--type` declarations, applied after the schema file
+    types: Vec<(String, ColumnType)>,
+    /// Skip rows with values that don't fit their declared type
+    strict: bool,
+    /// Memory-map the input file instead of reading it into a buffer
+    mmap: bool,
+    /// WebAssembly plugin reading the input or transforming documents
+    plugin: Option<String>,
+}
 
-/// CLI arguments
+/// Options of `import`
 struct Args {
-    csv_file: String,
+    input: InputOptions,
     index_name: String,
-    host: String,
+    connection: Connection,
     batch_size: usize,
-    user: Option<String>,
-    password: Option<String>,
+    /// Bulk request time `--adaptive-batch-size` aims for
+    adaptive_target: Option<Duration>,
+    /// Bulk requests in flight at once
+    concurrency: usize,
+    /// Import only a random part of the rows
+    sample: Option<Sampling>,
+    /// Drop rows repeating the values of these columns
+    dedupe_by: Vec<String>,
+    keep: DedupeKeep,
+    /// Write dotted column names as nested objects
+    unflatten: bool,
+    /// `--group-by` column
+    group_by: Option<String>,
+    /// `--nest` arrays: field name and columns
+    nests: Vec<(String, Vec<String>)>,
+    /// `--merge-by` column, and how the other columns are merged
+    merge_by: Option<String>,
+    merge_strategy: MergeStrategy,
+    /// Column of file paths read into `ATTACHMENT_FIELD`
+    attach_column: Option<String>,
+    /// Ingest pipeline documents with attachments go through
+    attach_pipeline: String,
+    /// Rhai script run on every document
+    script: Option<String>,
+    id_template: Option<String>,
+    id_hash: Option<HashAlgorithm>,
+    id_hash_columns: Vec<String>,
+    timestamp_field: Option<String>,
+    /// Identifies this import in logs, as X-Opaque-Id and in --run-id-field
+    run_id: String,
+    /// Field to write the run ID to in every document
+    run_id_field: Option<String>,
+    /// Fields to add from another index
+    es_lookup: Option<EsLookup>,
+    rollover_after: Option<RolloverConditions>,
+    wait_for_active_shards: Option<String>,
+    bulk_timeout: Option<String>,
+    max_retries: u32,
+    max_failures: u32,
+    /// `--slow-bulk-ms`
+    slow_bulk: Option<u64>,
+    /// Skip documents whose bulk item is larger
+    max_doc_bytes: Option<usize>,
+    /// Where the skipped documents are written
+    dead_letter: Option<String>,
+    /// Where failed bulk requests and their responses are saved
+    capture_dir: Option<String>,
+    checkpoint: Option<String>,
+    /// Leave out documents whose _id is already in the index
+    resume_from_index: bool,
+    /// `--pre-hook` and `--post-hook` values, in order
+    pre_hooks: Vec<String>,
+    post_hooks: Vec<String>,
+    on_hook_error: hook::HookErrorPolicy,
+    /// Run the pipeline without sending anything and report throughput
+    bench: bool,
+    /// Print the first N documents instead of sending them
+    preview: Option<usize>,
+    /// File, or - for stdout, to write the bulk requests to
+    output: Option<String>,
+    /// Draw a live dashboard instead of printing progress messages
+    tui: bool,
+    /// Delete the index and create it with a mapping of the column types
+    recreate: bool,
+    /// `--component-template` names and files
+    component_templates: Vec<(String, String)>,
+    /// `--index-template` name and file
+    index_template: Option<(String, String)>,
+    /// `--settings` file for creating the index
+    settings: Option<String>,
+    /// `--mapping` file for creating the index
+    mapping: Option<String>,
+    on_existing_index: ExistingIndexPolicy,
+    /// Fail instead of warning when columns don't fit the existing mapping
+    strict_mapping: bool,
+    /// Delete the index's documents before importing
+    truncate: bool,
+    /// `_delete_by_query` body for `--truncate`, instead of all documents
+    truncate_query: Option<String>,
+    /// Don't ask before deleting anything
+    yes: bool,
+    /// Repository to snapshot the index to before deleting from it
+    snapshot_repo: Option<String>,
+    /// Undo an aborted import
+    rollback_on_failure: bool,
+    /// Alias to move to the index after the import
+    alias: Option<String>,
+    /// Whether to check the document count before moving the alias
+    staged: bool,
+    /// Delete the indices the alias is moved off
+    delete_old: bool,
+    /// Drop the replicas of the index while importing
+    tune_replicas: bool,
+    /// Turn off refreshes of the index while importing
+    tune_refresh: bool,
+    /// Segments to force merge the index down to after the import
+    forcemerge: Option<u32>,
+    /// Cluster health to wait for before starting, and for how long
+    wait_for_status: Option<(ClusterStatus, Duration)>,
+    /// Documents read by `reindex`, imported instead of `input.file`
+    source: Option<Source>,
 }
 
-fn parse_args() -> Args {
-    let mut csv_file = String::new();
-    let mut index_name = String::new();
-    let mut host = String::from("http://localhost:9200");
-    let mut batch_size = 1000;
-    let mut user: Option<String> = None;
-    let mut password: Option<String> = None;
+/// The documents of an index, read into memory as the contents of a file
+/// would be
+struct Source {
+    /// Their `_source`, one per line
+    ndjson: Vec<u8>,
+    /// Their `_id`, in the same order
+    ids: Vec<String>,
+}
 
-    let mut it = env::args().skip(1).peekable();
-    while let Some(arg) = it.next() {
-        match arg.as_str() {
-            "--host" => {
-                if let Some(v) = it.next() {
-                    host = v;
-                }
-            }
-            "--batch-size" => {
-                if let Some(v) = it.next() {
-                    batch_size = v.parse().unwrap_or(1000);
-                }
-            }
-            "--user" => {
-                if let Some(v) = it.next() {
-                    user = Some(v);
+/// What to do with a record whose field count doesn't match the header
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum RaggedPolicy {
+    /// Abort the import
+    Error,
+    /// Drop the record
+    Skip,
+    /// Fill missing trailing fields with empty values; a record with
+    /// surplus fields still aborts the import
+    Pad,
+    /// Drop surplus trailing fields; a record with missing fields still
+    /// aborts the import
+    Truncate,
+}
+
+/// What to do with input that isn't valid UTF-8
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum InvalidUtf8Policy {
+    /// Abort before importing anything
+    Error,
+    /// Import it with U+FFFD in place of each invalid sequence
+    Replace,
+    /// Drop and report the records containing invalid sequences
+    SkipRow,
+}
+
+/// What to do when two columns share the same name
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum DuplicateHeaderPolicy {
+    /// Abort the import
+    Error,
+    /// Rename later occurrences to `name_2`, `name_3`, ...
+    Suffix,
+}
+
+/// `_cluster/health` status for `--wait-for-status`
+#[derive(Clone, Copy, ValueEnum)]
+enum ClusterStatus {
+    Yellow,
+    Green,
+}
+
+impl ClusterStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Yellow => "yellow",
+            Self::Green => "green",
+        }
+    }
+}
+
+/// What to do when the target index already exists
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ExistingIndexPolicy {
+    /// Stop before writing anything
+    Abort,
+    /// Ask on the terminal, and abort without one
+    Prompt,
+    /// Add the documents to it
+    Append,
+}
+
+/// How input lines are split into fields
+#[derive(Clone)]
+enum RecordFormat {
+    Csv,
+    /// Fixed-width columns, counted in characters, with `pad` trimmed from
+    /// both ends of each field
+    Fixed {
+        widths: Vec<usize>,
+        pad: char,
+    },
+    /// Elements at a path of an XML document, their fields laid out in
+    /// `columns` order
+    Xml {
+        records: xml::Records,
+        columns: Vec<String>,
+    },
+    /// One record per line matching `pattern`, a field per named group
+    Log {
+        pattern: Regex,
+    },
+    /// RFC 3164 or RFC 5424 messages, one per line, split into
+    /// `syslog::FIELDS`; `now` dates RFC 3164 timestamps, which lack a year
+    Syslog {
+        now: DateTime<Utc>,
+    },
+    /// One JSON object per line, its fields laid out in `columns` order.
+    /// `max_depth` is set with `--flatten`; `types` are the JSON types found
+    /// under each column.
+    Ndjson {
+        columns: Vec<String>,
+        max_depth: Option<usize>,
+        types: Vec<Option<ColumnType>>,
+    },
+    /// Actions and documents of a _bulk body, a record per action with the
+    /// lines of it in one field
+    Bulk,
+}
+
+/// Options controlling how CSV input is read
+#[derive(Clone)]
+struct CsvOptions {
+    format: RecordFormat,
+    /// Column names when the input has no header line
+    headers: Option<Vec<String>>,
+    on_ragged: RaggedPolicy,
+    invalid_utf8: InvalidUtf8Policy,
+    on_duplicate_header: DuplicateHeaderPolicy,
+    /// Lines starting with this byte are skipped
+    comment: Option<u8>,
+    sanitize_headers: bool,
+    /// Guess the CSV delimiter, quote character and header line
+    sniff: bool,
+    /// Columns whose values lose thousands separators and currency symbols
+    numeric_cleanup: Vec<String>,
+    /// Columns whose sizes and counts like 1.5GB and 2.3k become numbers
+    expand_units: Vec<String>,
+    /// Remove control characters but newline and tab from values
+    strip_control_chars: bool,
+    /// (column, value) to use for empty cells
+    defaults: Vec<(String, String)>,
+    /// Fields computed from each row, appended as columns in this order
+    computed: Vec<(String, expr::Expr)>,
+    /// Columns looked up in another file, appended after those
+    lookup: Option<Lookup>,
+    /// Column of IP addresses to add `geoip::FIELDS` for, and the database
+    geoip: Option<(String, Arc<geoip::Database>)>,
+    /// (column, mask) applied last, computed fields included
+    masks: Vec<(String, Mask)>,
+    /// Columns replaced by pseudonyms, with the key
+    anonymize: Vec<String>,
+    anonymize_key: Vec<u8>,
+    /// Byte limits of column values, for all columns when `None`
+    truncate: Vec<(Option<String>, usize)>,
+    /// Zones of naive date-times by column, and for the other columns
+    tz_columns: Vec<(String, Arc<tz::Zone>)>,
+    input_tz: Option<Arc<tz::Zone>>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            format: RecordFormat::Csv,
+            headers: None,
+            on_ragged: RaggedPolicy::Error,
+            invalid_utf8: InvalidUtf8Policy::Error,
+            on_duplicate_header: DuplicateHeaderPolicy::Error,
+            comment: None,
+            sanitize_headers: false,
+            sniff: false,
+            numeric_cleanup: Vec::new(),
+            expand_units: Vec::new(),
+            strip_control_chars: false,
+            defaults: Vec::new(),
+            computed: Vec::new(),
+            lookup: None,
+            geoip: None,
+            masks: Vec::new(),
+            anonymize: Vec::new(),
+            anonymize_key: Vec::new(),
+            truncate: Vec::new(),
+            tz_columns: Vec::new(),
+            input_tz: None,
+        }
+    }
+}
+
+/// Object of the `--geoip` fields, as the geoip ingest processor names it
+const GEOIP_FIELD: &str = "geoip";
+
+/// `--lookup`: rows of a dimension file by their key
+#[derive(Clone)]
+struct Lookup {
+    key: String,
+    fields: Vec<String>,
+    /// Values of `fields` for each key; the first row with a key counts
+    values: HashMap<String, Vec<String>>,
+}
+
+impl Lookup {
+    fn load(file: &str, key: &str, fields: &[String]) -> Result<Self, String> {
+        let buf = std::fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+        let csv = CsvIter::from_input(Input::Owned(buf), CsvOptions::default())
+            .map_err(|e| format!("--lookup {}: {}", file, e))?;
+        let column = |name: &str| {
+            csv.headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| format!("--lookup {}: no column named {}", file, name))
+        };
+        let key_column = column(key)?;
+        let fields: Vec<String> = fields.iter().map(|f| f.trim().to_string()).collect();
+        let columns = fields
+            .iter()
+            .map(|f| column(f))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut values = HashMap::new();
+        for row in csv {
+            let mut row = row.map_err(|e| format!("--lookup {}: {}", file, e))?;
+            values.entry(row[key_column].1.clone()).or_insert_with(|| {
+                columns
+                    .iter()
+                    .map(|&i| std::mem::take(&mut row[i].1))
+                    .collect()
+            });
+        }
+        Ok(Self {
+            key: key.to_string(),
+            fields,
+            values,
+        })
+    }
+}
+
+/// Digest used for content-hash document IDs
+#[derive(Clone, Copy, ValueEnum)]
+enum HashAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+/// `--mask` treatment of a column's values
+#[derive(Clone, Copy, ValueEnum)]
+enum Mask {
+    /// Star out all but the first character of an email's local part or
+    /// the last four letters and digits of anything else, keeping
+    /// separators: j***@example.com, ***-**-6789
+    Partial,
+    /// Replace the value with [REDACTED]
+    Redact,
+    /// Replace the value with its SHA-256, so equal values still match
+    Hash,
+}
+
+impl Mask {
+    /// `s` masked; empty values stay empty
+    fn apply(self, s: &str) -> String {
+        if s.is_empty() {
+            return String::new();
+        }
+        match self {
+            Mask::Redact => "[REDACTED]".to_string(),
+            Mask::Hash => Sha256::digest(s.as_bytes())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            Mask::Partial => match s.split_once('@') {
+                Some((local, domain)) => {
+                    let mut chars = local.chars();
+                    let first = chars.next().map(String::from).unwrap_or_default();
+                    format!("{}{}@{}", first, "*".repeat(chars.count()), domain)
                 }
-            }
-            "--pass" => {
-                if let Some(v) = it.next() {
-                    password = Some(v);
+                None => {
+                    let total = s.chars().filter(|c| c.is_alphanumeric()).count();
+                    let mut seen = 0;
+                    s.chars()
+                        .map(|c| {
+                            if !c.is_alphanumeric() {
+                                return c;
+                            }
+                            seen += 1;
+                            if seen + 4 > total { c } else { '*' }
+                        })
+                        .collect()
                 }
-            }
-            _ if csv_file.is_empty() => csv_file = arg,
-            _ if index_name.is_empty() => index_name = arg,
-            _ => {}
+            },
         }
     }
+}
 
-    if csv_file.is_empty() || index_name.is_empty() {
-        eprintln!(
-            "Usage: elastic_importer <csv_file> <index_name> [--host http://localhost:9200] [--batch-size 1000] [--user USER --pass PASS]"
-        );
-        std::process::exit(1);
+/// `--anonymize` pseudonym of `value`: its HMAC-SHA256 under `key`, in hex
+fn pseudonym(key: &[u8], value: &str) -> String {
+    if value.is_empty() {
+        return String::new();
     }
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(value.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
 
-    Args {
-        csv_file,
-        index_name,
-        host,
-        batch_size,
+/// Print an error with the usage of `subcommand` and exit
+fn usage_error(subcommand: &str, msg: &str) -> ! {
+    let mut cmd = cli::Cli::command();
+    cmd.build();
+    let cmd = cmd
+        .find_subcommand_mut(subcommand)
+        .expect("usage of a known subcommand");
+    cmd.error(ErrorKind::ValueValidation, msg).exit()
+}
+
+/// Input options checked against each other and turned into reader
+/// settings
+fn resolve_input(args: cli::InputArgs) -> Result<InputOptions, String> {
+    let f = args.format;
+    let mut csv = CsvOptions {
+        on_ragged: f.on_ragged,
+        invalid_utf8: f.invalid_utf8,
+        on_duplicate_header: f.on_duplicate_header,
+        comment: f.comment,
+        sanitize_headers: f.sanitize_headers,
+        sniff: f.sniff,
+        ..CsvOptions::default()
+    };
+    if f.sniff && f.format != Format::Csv {
+        return Err("--sniff requires --format csv".into());
+    }
+    let mut types = Vec::new();
+
+    let widths = match &f.widths_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("cannot read --widths-file {}: {}", path, e))?,
+        ),
+        None => f.widths,
+    };
+    match (f.format, widths) {
+        (Format::Fixed, Some(spec)) => {
+            let (names, widths) = parse_widths(&spec)?;
+            csv.headers = names;
+            csv.format = RecordFormat::Fixed {
+                widths,
+                pad: f.pad_char,
+            };
+        }
+        (Format::Fixed, None) => {
+            return Err("--format fixed requires --widths or --widths-file".into());
+        }
+        (_, Some(_)) => return Err("--widths requires --format fixed".into()),
+        _ => {}
+    }
+    match (f.format, f.record_path) {
+        (Format::Xml, Some(path)) => {
+            csv.format = RecordFormat::Xml {
+                records: xml::Records::new(&path)?,
+                columns: Vec::new(),
+            };
+        }
+        (Format::Xml, None) => return Err("--format xml requires --record-path".into()),
+        (_, Some(_)) => return Err("--record-path requires --format xml".into()),
+        _ => {}
+    }
+    match (f.format, f.pattern) {
+        (Format::Log, Some(pattern)) => {
+            csv.format = RecordFormat::Log {
+                pattern: grok::compile(&pattern)?,
+            };
+        }
+        (Format::Log, None) => return Err("--format log requires --pattern".into()),
+        (_, Some(_)) => return Err("--pattern requires --format log".into()),
+        _ => {}
+    }
+    if f.format == Format::Syslog {
+        csv.format = RecordFormat::Syslog { now: Utc::now() };
+    }
+    if f.format == Format::Bulk {
+        csv.format = RecordFormat::Bulk;
+    }
+    match (f.format, f.flatten) {
+        (Format::Ndjson, flatten) => {
+            csv.format = RecordFormat::Ndjson {
+                columns: Vec::new(),
+                max_depth: flatten.then(|| f.max_depth.unwrap_or(usize::MAX)),
+                types: Vec::new(),
+            };
+        }
+        (_, true) => return Err("--flatten requires --format ndjson".into()),
+        _ => {}
+    }
+    match (f.format, f.preset) {
+        (Format::Accesslog, Some(preset)) => {
+            csv.format = RecordFormat::Log {
+                pattern: grok::preset(&preset)?,
+            };
+            // ahead of any --type, which can override them
+            let preset_types = [
+                ("timestamp", ColumnType::Date),
+                ("http_version", ColumnType::Keyword),
+                ("status", ColumnType::Long),
+                ("bytes", ColumnType::Long),
+            ];
+            types.extend(preset_types.map(|(col, ty)| (col.to_string(), ty)));
+        }
+        (Format::Accesslog, None) => return Err("--format accesslog requires --preset".into()),
+        (_, Some(_)) => return Err("--preset requires --format accesslog".into()),
+        _ => {}
+    }
+    typed_input(args.file, csv, types, args.types, f.mmap, args.plugin)
+}
+
+/// Input options completed with the value and type options; `types` are
+/// declared ahead of any `--type`
+fn typed_input(
+    file: String,
+    mut csv: CsvOptions,
+    mut types: Vec<(String, ColumnType)>,
+    t: cli::TypeArgs,
+    mmap: bool,
+    plugin: Option<String>,
+) -> Result<InputOptions, String> {
+    csv.numeric_cleanup = t
+        .numeric_cleanup
+        .iter()
+        .map(|c| c.trim().to_string())
+        .collect();
+    csv.expand_units = t
+        .expand_units
+        .iter()
+        .map(|c| c.trim().to_string())
+        .collect();
+    csv.strip_control_chars = t.strip_control_chars;
+    csv.defaults = t.defaults;
+    csv.computed = t.compute;
+    if let (Some(file), Some(key)) = (&t.lookup, &t.lookup_key) {
+        csv.lookup = Some(Lookup::load(file, key.trim(), &t.lookup_fields)?);
+    }
+    if let (Some(col), Some(file)) = (t.geoip, &t.geoip_db) {
+        csv.geoip = Some((
+            col.trim().to_string(),
+            Arc::new(geoip::Database::open(file)?),
+        ));
+        for field in geoip::FIELDS {
+            let ty = match *field {
+                "location" => ColumnType::GeoPoint,
+                _ => ColumnType::Keyword,
+            };
+            types.push((format!("{}.{}", GEOIP_FIELD, field), ty));
+        }
+    }
+    csv.masks = t.masks;
+    csv.truncate = t.truncate_fields;
+    csv.tz_columns = t
+        .tz_columns
+        .into_iter()
+        .map(|(col, zone)| (col, Arc::new(zone)))
+        .collect();
+    csv.input_tz = t.input_tz.map(Arc::new);
+    if !t.anonymize.is_empty() {
+        let key = t
+            .anonymize_key
+            .or_else(|| env::var("ANONYMIZE_KEY").ok())
+            .filter(|k| !k.is_empty())
+            .ok_or("--anonymize requires --anonymize-key or $ANONYMIZE_KEY")?;
+        csv.anonymize = t.anonymize.iter().map(|c| c.trim().to_string()).collect();
+        csv.anonymize_key = key.into_bytes();
+    }
+    types.extend(t.types);
+    if t.strict && t.schema.is_none() && types.is_empty() {
+        return Err("--strict requires --schema or --type".into());
+    }
+    Ok(InputOptions {
+        file,
+        csv,
+        infer_sample: t.infer_sample,
+        infer_random: t.infer_random,
+        schema: t.schema,
+        types,
+        strict: t.strict,
+        mmap,
+        plugin,
+    })
+}
+
+/// A password or key kept in a file, without the trailing newline editors
+/// and `echo` leave
+fn secret_file(path: &str) -> Result<String, String> {
+    let s = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    Ok(s.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Connection options, with the SigV4 region looked up in the environment;
+/// `secrets_from_env` also looks there for a password or API key left out
+fn resolve_connection(
+    c: cli::ConnectionArgs,
+    secrets_from_env: bool,
+) -> Result<Connection, String> {
+    let aws_sigv4 = if c.aws_sigv4 {
+        let region = c
+            .aws_region
+            .or_else(|| env::var("AWS_REGION").ok())
+            .or_else(|| env::var("AWS_DEFAULT_REGION").ok())
+            .ok_or("--aws-sigv4 requires --aws-region or $AWS_REGION")?;
+        Some((region, c.aws_service))
+    } else {
+        None
+    };
+    // --pass or --api-key, then its file, then $VAR, then $VAR_FILE
+    let secret = |value: Option<String>, file: Option<String>, var: &str| {
+        if value.is_some() {
+            return Ok(value);
+        }
+        if let Some(file) = file {
+            return secret_file(&file).map(Some);
+        }
+        if !secrets_from_env {
+            return Ok(None);
+        }
+        if let Ok(value) = env::var(var) {
+            return Ok(Some(value));
+        }
+        match env::var(format!("{}_FILE", var)) {
+            Ok(file) => secret_file(&file).map(Some),
+            Err(_) => Ok(None),
+        }
+    };
+    let api_key = match (c.user.is_some() || c.aws_sigv4, &c.credential_profile) {
+        (true, _) => None,
+        (false, Some(profile)) => Some(keyring::secret(profile)?),
+        (false, None) => secret(c.api_key, c.api_key_file, "ELASTIC_API_KEY")?,
+    };
+    let hostname = c.host.split_once("://").map_or(&*c.host, |(_, rest)| rest);
+    let hostname = hostname.split(['/', ':']).next().unwrap_or_default();
+    let mut user = c.user;
+    let mut password = match (&user, &c.credential_profile) {
+        (None, _) => None,
+        (Some(_), Some(profile)) => Some(keyring::secret(profile)?),
+        (Some(u), None) => match secret(c.pass, c.pass_file, "ELASTIC_PASSWORD")? {
+            Some(pass) => Some(pass),
+            None => netrc::lookup(hostname, Some(u)).map(|(_, pass)| pass),
+        },
+    };
+    if user.is_none()
+        && api_key.is_none()
+        && aws_sigv4.is_none()
+        && let Some((login, pass)) = netrc::lookup(hostname, None)
+    {
+        user = Some(login);
+        password = Some(pass);
+    }
+    if let (Some(user), None) = (&user, &password) {
+        let question = format!("Password for {} on {}: ", user, c.host);
+        password = Some(prompt::password(&question)?);
+    }
+    Ok(Connection {
+        host: c.host,
         user,
         password,
+        api_key,
+        tls: TlsOptions {
+            client_cert: c.client_cert,
+            client_key: c.client_key,
+            ca_cert: c.ca_cert,
+            insecure: c.insecure,
+        },
+        aws_sigv4,
+        debug_http: c.debug_http,
+        user_agent: c.user_agent,
+        opaque_id: c.opaque_id,
+    })
+}
+
+/// `import` options checked against each other
+fn resolve_import(args: cli::ImportArgs) -> Result<Args, String> {
+    if args.input.format.flatten && args.documents.unflatten {
+        return Err("--flatten can't be combined with --unflatten".into());
+    }
+    let input = resolve_input(args.input)?;
+    let escape = |s: &str| s.replace('{', "{{").replace('}', "}}");
+
+    // clap has checked that exactly one of these is given
+    let index_name = match &args.index_column {
+        Some(col) => {
+            let prefix = args.index_prefix.as_deref().unwrap_or("");
+            format!("{}{{{}}}", escape(prefix), escape(col))
+        }
+        None => args.index_name.or(args.index).unwrap_or_default(),
+    };
+    let parts = (args.documents, args.connection, args.bulk, args.setup);
+    let mut import = import_options(input, index_name, parts, args.checkpoint)?;
+    import.resume_from_index = args.resume_from_index;
+    import.group_by = args.group_by;
+    import.nests = args.nest;
+    import.merge_by = args.merge_by;
+    import.merge_strategy = args.merge_strategy;
+    import.attach_column = args.attach_column;
+    import.attach_pipeline = args.attach_pipeline;
+    Ok(import)
+}
+
+/// `reindex` options as those of an import from the documents of the
+/// source index, and the connection to read them over
+fn resolve_reindex(args: cli::ReindexArgs) -> Result<(Args, Connection), String> {
+    // documents are read as NDJSON lines of their _source
+    let csv = CsvOptions {
+        format: RecordFormat::Ndjson {
+            columns: Vec::new(),
+            max_depth: None,
+            types: Vec::new(),
+        },
+        ..CsvOptions::default()
+    };
+    let input = typed_input(args.src, csv, Vec::new(), args.types, false, args.plugin)?;
+    let source = args
+        .source
+        .connection()
+        .map(|c| resolve_connection(c, false))
+        .transpose()?;
+    let parts = (args.documents, args.connection, args.bulk, args.setup);
+    let import = import_options(input, args.dst, parts, None)?;
+    let source = match source {
+        Some(source) => Connection {
+            debug_http: import.connection.debug_http,
+            user_agent: import.connection.user_agent.clone(),
+            opaque_id: import.connection.opaque_id.clone(),
+            ..source
+        },
+        None => import.connection.clone(),
+    };
+    Ok((import, source))
+}
+
+/// A run ID for an import that wasn't given one: the time it started and a
+/// random suffix, e.g. 20240301T101500-3fa9c2
+fn new_run_id() -> String {
+    format!(
+        "{}-{:06x}",
+        Utc::now().format("%Y%m%dT%H%M%S"),
+        XorShift::from_clock().next() & 0xff_ffff
+    )
+}
+
+/// The options `import` and `reindex` share
+fn import_options(
+    input: InputOptions,
+    index_name: String,
+    (d, c, b, s): (
+        cli::DocumentArgs,
+        cli::ConnectionArgs,
+        cli::BulkArgs,
+        cli::SetupArgs,
+    ),
+    checkpoint: Option<String>,
+) -> Result<Args, String> {
+    let escape = |s: &str| s.replace('{', "{{").replace('}', "}}");
+    let id_template = match d.id_column {
+        Some(col) => Some(format!("{{{}}}", escape(&col))),
+        None => d.id_template,
+    };
+    let sample = match (d.sample, d.sample_rows) {
+        (Some(fraction), _) => Some(Sampling::Fraction(fraction)),
+        (None, Some(n)) => Some(Sampling::Rows(n)),
+        (None, None) => None,
+    };
+    let mut connection = resolve_connection(c, true)?;
+    let run_id = d.run_id.unwrap_or_else(new_run_id);
+    connection.opaque_id.get_or_insert_with(|| run_id.clone());
+    // a new index for each staged import
+    let index_name = match s.staged {
+        true => format!("{}-{}", index_name, Utc::now().format("%Y%m%d%H%M%S")),
+        false => index_name,
+    };
+    // a preview is one batch of exactly the documents to show, and
+    // written out batches are in input order
+    let (batch_size, concurrency) = match b.preview {
+        Some(n) => (n, 1),
+        None if b.output.is_some() => (b.batch_size, 1),
+        None => (b.batch_size, b.concurrency),
+    };
+
+    Ok(Args {
+        input,
+        index_name,
+        connection,
+        batch_size,
+        adaptive_target: b
+            .adaptive_batch_size
+            .then(|| Duration::from_millis(b.target_bulk_ms)),
+        concurrency,
+        sample,
+        dedupe_by: d.dedupe_by.iter().map(|c| c.trim().to_string()).collect(),
+        keep: d.keep,
+        unflatten: d.unflatten,
+        group_by: None,
+        nests: Vec::new(),
+        merge_by: None,
+        merge_strategy: MergeStrategy::Last,
+        attach_column: None,
+        attach_pipeline: String::new(),
+        script: d.script,
+        id_template,
+        id_hash: d.id_hash,
+        id_hash_columns: d
+            .id_hash_columns
+            .iter()
+            .map(|c| c.trim().to_string())
+            .collect(),
+        timestamp_field: d.timestamp_field,
+        run_id,
+        run_id_field: d.run_id_field,
+        es_lookup: d.es_lookup,
+        rollover_after: b.rollover_after,
+        wait_for_active_shards: b.wait_for_active_shards,
+        bulk_timeout: b.bulk_timeout,
+        max_retries: b.max_retries,
+        max_failures: b.max_failures,
+        checkpoint,
+        resume_from_index: false,
+        pre_hooks: s.pre_hook,
+        post_hooks: s.post_hook,
+        on_hook_error: s.on_hook_error,
+        slow_bulk: b.slow_bulk_ms,
+        max_doc_bytes: b.max_doc_bytes,
+        dead_letter: b.dead_letter,
+        capture_dir: b.capture_dir,
+        bench: b.bench,
+        preview: b.preview,
+        output: b.output,
+        tui: b.tui,
+        recreate: s.recreate,
+        snapshot_repo: s.snapshot_repo,
+        rollback_on_failure: s.rollback_on_failure,
+        alias: s.alias,
+        staged: s.staged,
+        delete_old: s.delete_old,
+        component_templates: s.component_template,
+        index_template: s.index_template,
+        settings: s.settings,
+        mapping: s.mapping,
+        on_existing_index: if s.append {
+            ExistingIndexPolicy::Append
+        } else {
+            s.on_existing_index
+        },
+        strict_mapping: s.strict_mapping,
+        tune_replicas: s.tune_replicas,
+        tune_refresh: s.tune_refresh,
+        forcemerge: s.forcemerge,
+        wait_for_status: s.wait_for_status.map(|status| (status, s.wait_timeout)),
+        truncate: s.truncate,
+        truncate_query: s.truncate_query,
+        yes: s.yes,
+        source: None,
+    })
+}
+/// Column widths for `--format fixed`, separated by commas or newlines:
+/// `10,4,25` or `name:10,code:4`. Spec file lines may also read `name 10`
+/// and `#` starts a comment. Names, if any, must be given for every column
+/// and replace the header line.
+fn parse_widths(spec: &str) -> Result<(Option<Vec<String>>, Vec<usize>), String> {
+    let mut names = Vec::new();
+    let mut widths = Vec::new();
+    for item in spec.split([',', '\n']) {
+        let item = item.split('#').next().unwrap_or("").trim();
+        if item.is_empty() {
+            continue;
+        }
+        let (name, width) = match item.rsplit_once([':', ' ', '\t']) {
+            Some((name, width)) => (Some(name.trim().to_string()), width),
+            None => (None, item),
+        };
+        let width = width
+            .parse::<usize>()
+            .ok()
+            .filter(|&w| w > 0)
+            .ok_or_else(|| format!("invalid column width: {}", item))?;
+        names.extend(name);
+        widths.push(width);
+    }
+    if widths.is_empty() {
+        return Err("no column widths given".into());
     }
+    match names.len() {
+        0 => Ok((None, widths)),
+        n if n == widths.len() => Ok((Some(names), widths)),
+        _ => Err("name either every fixed-width column or none".into()),
+    }
+}
+
+/// ES time unit value such as "30s" or "1m"
+fn is_time_value(s: &str) -> bool {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    !num.is_empty() && ["d", "h", "m", "s", "ms", "micros", "nanos"].contains(&unit)
 }
 
 /// HTTP target struct
@@ -76,14 +989,34 @@ struct HttpTarget {
     host: String,
     port: u16,
     base_path: String,
+    /// Set for https:// targets
+    tls: Option<Arc<ClientConfig>>,
+    /// Connect, read and write timeout; none for ES itself
+    timeout: Option<Duration>,
+    /// Print requests and responses on stderr, for --debug-http
+    debug: bool,
+    user_agent: String,
+    /// Sent as X-Opaque-Id
+    opaque_id: Option<String>,
 }
 
-fn parse_http_target(url: &str) -> Result<HttpTarget, String> {
-    let prefix = "http://";
-    if !url.starts_with(prefix) {
-        return Err("Only http:// supported".into());
+/// Run ID of the import going on, set once it starts
+static RUN_ID: OnceLock<String> = OnceLock::new();
+
+/// User-Agent of requests unless --user-agent says otherwise
+const USER_AGENT: &str = concat!("elastic_importer/", env!("CARGO_PKG_VERSION"));
+
+fn parse_http_target(url: &str, tls: &TlsOptions) -> Result<HttpTarget, String> {
+    let (rest, https) = if let Some(rest) = url.strip_prefix("https://") {
+        (rest, true)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (rest, false)
+    } else {
+        return Err("Only http:// and https:// supported".into());
+    };
+    if !https && (tls.client_cert.is_some() || tls.ca_cert.is_some() || tls.insecure) {
+        return Err("TLS options require an https:// host".into());
     }
-    let rest = &url[prefix.len()..];
     let parts: Vec<&str> = rest.splitn(2, '/').collect();
     let host_port = parts[0];
     let base_path = if parts.len() == 2 {
@@ -96,83 +1029,164 @@ fn parse_http_target(url: &str) -> Result<HttpTarget, String> {
         let port = p.parse::<u16>().map_err(|_| "Invalid port")?;
         (h.to_string(), port)
     } else {
-        (host_port.to_string(), 9200)
+        // Elasticsearch's own port over http; https hosts without one are
+        // usually behind a proxy or a managed service such as Amazon
+        // OpenSearch Service, on the standard port
+        (host_port.to_string(), if https { 443 } else { 9200 })
     };
 
     Ok(HttpTarget {
         host,
         port,
         base_path,
+        tls: if https {
+            Some(tls::client_config(tls)?)
+        } else {
+            None
+        },
+        timeout: None,
+        debug: false,
+        user_agent: USER_AGENT.to_string(),
+        opaque_id: None,
     })
 }
 
-/// Detect JSON type: number, bool, string
-fn infer_type(s: &str) -> String {
-    if s.is_empty() {
-        return "null".into();
-    }
-    if let Ok(i) = i64::from_str(s) {
-        return i.to_string();
-    }
-    if let Ok(f) = f64::from_str(s) {
-        return f.to_string();
-    }
-    match s.to_lowercase().as_str() {
-        "true" => "true".into(),
-        "false" => "false".into(),
-        _ => format!("\"{}\"", json_escape(s)),
+/// CSV input held in memory: read in full, or mapped from a local file
+enum Input {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Input {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Input::Owned(buf) => buf,
+            Input::Mapped(map) => map,
+        }
     }
 }
 
-/// Escape string for JSON
-fn json_escape(s: &str) -> String {
-    let mut out = String::with_capacity(s.len() + 2);
-    for ch in s.chars() {
-        match ch {
-            '\\' => out.push_str("\\\\"),
-            '\"' => out.push_str("\\\""),
-            '\n' => out.push_str("\\n"),
-            '\r' => out.push_str("\\r"),
-            '\t' => out.push_str("\\t"),
-            c if c < ' ' => {
-                out.push_str(&format!("\\u{:04x}", c as u32));
-            }
-            c => out.push(c),
+/// `input` with U+FFFD in place of each invalid UTF-8 sequence, and the
+/// offsets of those replacements in it
+fn repair_utf8(input: Input) -> (Input, Vec<usize>) {
+    if std::str::from_utf8(&input).is_ok() {
+        return (input, Vec::new());
+    }
+    let mut repaired = String::with_capacity(input.len());
+    let mut replaced = Vec::new();
+    for chunk in input.utf8_chunks() {
+        repaired.push_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            replaced.push(repaired.len());
+            repaired.push(char::REPLACEMENT_CHARACTER);
         }
     }
-    out
+    (Input::Owned(repaired.into_bytes()), replaced)
 }
 
 /// CSV reader
 struct CsvReader {
-    buf: String,
+    buf: Input,
     idx: usize,
+    /// Line number at `idx` (1-based)
+    line: usize,
+    /// Line on which the last returned record started
+    record_line: usize,
+    /// Lines starting with this byte are skipped
+    comment: Option<u8>,
+    format: RecordFormat,
+    /// CSV field delimiter and quote character
+    delimiter: u8,
+    quote: u8,
+    /// Lines skipped because they didn't match the `--format log` pattern
+    /// or weren't syslog messages
+    unmatched: Vec<usize>,
 }
 
 impl CsvReader {
-    fn new(mut reader: impl BufRead) -> io::Result<Self> {
-        let mut buf = String::new();
-        reader.read_to_string(&mut buf)?;
-        Ok(Self { buf, idx: 0 })
+    /// Fails if the input isn't valid UTF-8, so fields split at ASCII
+    /// delimiters are valid too
+    fn new(buf: Input, comment: Option<u8>, format: RecordFormat) -> Result<Self, String> {
+        if let Err(e) = std::str::from_utf8(&buf) {
+            return Err(format!(
+                "input is not valid UTF-8 (at byte {}), see --invalid-utf8",
+                e.valid_up_to()
+            ));
+        }
+        Ok(Self {
+            buf,
+            idx: 0,
+            line: 1,
+            record_line: 1,
+            comment,
+            format,
+            delimiter: b',',
+            quote: b'"',
+            unmatched: Vec::new(),
+        })
     }
 
-    fn next_record(&mut self) -> Option<Vec<String>> {
+    /// Advance past any comment lines at the current position
+    fn skip_comments(&mut self) {
+        let Some(comment) = self.comment else {
+            return;
+        };
+        let bytes = &*self.buf;
+        while self.idx < bytes.len() && bytes[self.idx] == comment {
+            match bytes[self.idx..]
+                .iter()
+                .position(|&b| b == b'\n' || b == b'\r')
+            {
+                Some(p) => {
+                    self.idx += p + 1;
+                    if bytes[self.idx - 1] == b'\r' && bytes.get(self.idx) == Some(&b'\n') {
+                        self.idx += 1;
+                    }
+                    self.line += 1;
+                }
+                None => self.idx = bytes.len(),
+            }
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<Vec<String>>, String> {
+        match self.format {
+            RecordFormat::Xml { .. } => return self.next_xml(),
+            RecordFormat::Ndjson { .. } => return self.next_ndjson(),
+            RecordFormat::Bulk => return self.next_bulk(),
+            _ => {}
+        }
+        self.skip_comments();
         if self.idx >= self.buf.len() {
-            return None;
+            return Ok(None);
         }
-        let bytes = self.buf.as_bytes();
+        match self.format {
+            RecordFormat::Fixed { .. } => Ok(Some(self.next_fixed())),
+            RecordFormat::Log { .. } | RecordFormat::Syslog { .. } => Ok(self.next_line()),
+            _ => Ok(self.next_csv()),
+        }
+    }
+
+    fn next_csv(&mut self) -> Option<Vec<String>> {
+        let bytes = &*self.buf;
+        let (delimiter, quote) = (self.delimiter, self.quote);
         let mut fields = Vec::new();
-        let mut field = String::new();
+        let mut field: Vec<u8> = Vec::new();
         let mut in_quotes = false;
+        let mut terminated = false;
         let mut i = self.idx;
+        self.record_line = self.line;
+        let take =
+            |field: &mut Vec<u8>| String::from_utf8_lossy(&std::mem::take(field)).into_owned();
 
         while i < bytes.len() {
-            let c = bytes[i] as char;
+            let c = bytes[i];
             if in_quotes {
                 match c {
-                    '"' => {
-                        if i + 1 < bytes.len() && bytes[i + 1] as char == '"' {
-                            field.push('"');
+                    c if c == quote => {
+                        if bytes.get(i + 1) == Some(&c) {
+                            field.push(c);
                             i += 2;
                         } else {
                             in_quotes = false;
@@ -180,50 +1194,50 @@ impl CsvReader {
                         }
                     }
                     _ => {
+                        if c == b'\n' || (c == b'\r' && bytes.get(i + 1) != Some(&b'\n')) {
+                            self.line += 1;
+                        }
                         field.push(c);
                         i += 1;
                     }
                 }
             } else {
                 match c {
-                    '"' => {
+                    c if c == quote => {
                         in_quotes = true;
                         i += 1;
                     }
-                    ',' => {
-                        fields.push(field.clone());
-                        field.clear();
+                    c if c == delimiter => {
+                        fields.push(take(&mut field));
                         i += 1;
                     }
-                    '\n' => {
-                        fields.push(field.clone());
-                        field.clear();
+                    b'\n' | b'\r' => {
+                        fields.push(take(&mut field));
                         i += 1;
-                        break;
-                    }
-                    '\r' => {
-                        if i + 1 < bytes.len() && bytes[i + 1] as char == '\n' {
-                            fields.push(field.clone());
-                            field.clear();
-                            i += 2;
-                            break;
-                        } else {
-                            fields.push(field.clone());
-                            field.clear();
+                        if c == b'\r' && bytes.get(i) == Some(&b'\n') {
                             i += 1;
-                            break;
                         }
+                        self.line += 1;
+                        terminated = true;
+                        break;
                     }
                     _ => {
-                        field.push(c);
-                        i += 1;
+                        // copy the run of plain bytes up to the next delimiter
+                        let run = bytes[i..]
+                            .iter()
+                            .position(|&b| {
+                                b == delimiter || b == quote || matches!(b, b'\n' | b'\r')
+                            })
+                            .unwrap_or(bytes.len() - i);
+                        field.extend_from_slice(&bytes[i..i + run]);
+                        i += run;
                     }
                 }
             }
         }
 
-        if i >= bytes.len() && (!field.is_empty() || !fields.is_empty()) {
-            fields.push(field);
+        if !terminated && (!field.is_empty() || !fields.is_empty()) {
+            fields.push(take(&mut field));
         }
 
         self.idx = i;
@@ -233,177 +1247,5171 @@ impl CsvReader {
             Some(fields)
         }
     }
-}
 
-/// CSV iterator with headers
-struct CsvIter {
-    rdr: CsvReader,
-    headers: Vec<String>,
-}
+    /// Next XML record, its fields placed by column
+    fn next_xml(&mut self) -> Result<Option<Vec<String>>, String> {
+        let RecordFormat::Xml { records, columns } = &mut self.format else {
+            return Ok(None);
+        };
+        let newlines = |s: &[u8]| s.iter().filter(|&&b| b == b'\n').count();
+        let from = self.idx;
+        let next = records.next(&self.buf, &mut self.idx);
+        let Some((start, fields)) = next.map_err(|e| {
+            format!(
+                "line {}: {}",
+                self.line + newlines(&self.buf[from..self.idx]),
+                e
+            )
+        })?
+        else {
+            return Ok(None);
+        };
+        self.record_line = self.line + newlines(&self.buf[from..start]);
+        self.line = self.record_line + newlines(&self.buf[start..self.idx]);
+        let mut rec = vec![String::new(); columns.len()];
+        for (name, value) in fields {
+            if let Some(i) = columns.iter().position(|c| *c == name) {
+                rec[i] = value;
+            }
+        }
+        Ok(Some(rec))
+    }
 
-impl CsvIter {
-    fn from_reader(r: impl BufRead) -> io::Result<Self> {
-        let mut rdr = CsvReader::new(r)?;
-        let headers = rdr.next_record().unwrap_or_default();
-        Ok(Self { rdr, headers })
+    /// The next action line and, unless it's a delete, the document line
+    /// after it; blank lines are passed over
+    fn next_bulk(&mut self) -> Result<Option<Vec<String>>, String> {
+        let action = loop {
+            match self.take_line() {
+                Some(line) if line.trim().is_empty() => {}
+                Some(line) => break line,
+                None => return Ok(None),
+            }
+        };
+        self.record_line = self.line - 1;
+        let op = json::parse(&action)
+            .ok()
+            .and_then(|v| {
+                v.as_object()
+                    .and_then(|f| f.first())
+                    .map(|(op, _)| op.clone())
+            })
+            .ok_or_else(|| format!("line {}: not a bulk action: {}", self.record_line, action))?;
+        let mut item = format!("{}\n", action);
+        match op.as_str() {
+            "delete" => {}
+            "index" | "create" | "update" => {
+                let doc = self.take_line().ok_or_else(|| {
+                    format!(
+                        "line {}: {} action without a document",
+                        self.record_line, op
+                    )
+                })?;
+                item.push_str(&doc);
+                item.push('\n');
+            }
+            _ => {
+                return Err(format!(
+                    "line {}: unknown bulk action {}",
+                    self.record_line, op
+                ));
+            }
+        }
+        Ok(Some(vec![item]))
     }
-}
 
-impl Iterator for CsvIter {
-    type Item = Vec<(String, String)>;
-    fn next(&mut self) -> Option<Self::Item> {
-        let rec = self.rdr.next_record()?;
-        if rec.is_empty() {
+    /// The next line, without its line break
+    fn take_line(&mut self) -> Option<String> {
+        let bytes = &*self.buf;
+        if self.idx >= bytes.len() {
             return None;
         }
-        let mut row = Vec::with_capacity(self.headers.len());
-        for (i, name) in self.headers.iter().enumerate() {
-            let val = rec.get(i).map(|s| s.trim()).unwrap_or("");
-            row.push((name.clone(), val.to_string()));
-        }
-        Some(row)
+        let end = bytes[self.idx..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(bytes.len(), |p| self.idx + p);
+        let line = String::from_utf8_lossy(&bytes[self.idx..end]);
+        let line = line.strip_suffix('\r').unwrap_or(&line).to_string();
+        self.line += 1;
+        self.idx = (end + 1).min(bytes.len());
+        Some(line)
     }
-}
 
-/// Convert dict to JSON with type inference
-fn dict_to_json(row: &[(String, String)]) -> String {
-    let mut out = String::from("{");
-    let mut first = true;
-    for (k, v) in row {
-        if !first {
-            out.push(',');
+    /// Next NDJSON object, its fields placed by column
+    fn next_ndjson(&mut self) -> Result<Option<Vec<String>>, String> {
+        let RecordFormat::Ndjson {
+            columns, max_depth, ..
+        } = &self.format
+        else {
+            return Ok(None);
+        };
+        let Some(fields) = ndjson::next(&self.buf, &mut self.idx, &mut self.line, *max_depth)?
+        else {
+            return Ok(None);
+        };
+        self.record_line = self.line - 1;
+        let mut rec = vec![String::new(); columns.len()];
+        for (name, value) in fields {
+            if let Some(i) = columns.iter().position(|c| *c == name) {
+                rec[i] = ndjson::text(value);
+            }
         }
-        first = false;
-        out.push('"');
-        out.push_str(&json_escape(k));
-        out.push_str("\":");
-        out.push_str(&infer_type(v));
+        Ok(Some(rec))
     }
-    out.push('}');
-    out
-}
-
-/// Send bulk request to ES
-fn http_post_bulk(
-    target: &HttpTarget,
-    bulk_path: &str,
-    body: &str,
-    auth: Option<(String, String)>,
-) -> Result<String, String> {
-    let addr = format!("{}:{}", target.host, target.port);
-    let mut stream = TcpStream::connect(&addr).map_err(|e| format!("connect error: {}", e))?;
-    let mut request = format!(
-        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\nContent-Length: {}\r\n",
-        bulk_path,
-        target.host,
-        body.as_bytes().len()
-    );
 
-    if let Some((user, pass)) = auth {
-        let token = general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
-        request.push_str(&format!("Authorization: Basic {}\r\n", token));
+    /// Fields of the next line that parses as a log pattern match or syslog
+    /// message. Blank lines are passed over, other lines that don't parse
+    /// are noted in `unmatched`.
+    fn next_line(&mut self) -> Option<Vec<String>> {
+        let bytes = &*self.buf;
+        while self.idx < bytes.len() {
+            let end = bytes[self.idx..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |p| self.idx + p);
+            let line = String::from_utf8_lossy(&bytes[self.idx..end]);
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            self.record_line = self.line;
+            self.line += 1;
+            self.idx = (end + 1).min(bytes.len());
+            let fields = match &self.format {
+                RecordFormat::Log { pattern } => pattern.captures(line).map(|caps| {
+                    pattern
+                        .capture_names()
+                        .flatten()
+                        .map(|name| caps.name(name).map_or("", |m| m.as_str()).to_string())
+                        .collect()
+                }),
+                RecordFormat::Syslog { now } => syslog::parse(line, *now),
+                _ => None,
+            };
+            if fields.is_some() {
+                return fields;
+            }
+            if !line.trim().is_empty() {
+                self.unmatched.push(self.record_line);
+            }
+        }
+        None
     }
 
-    request.push_str("\r\n");
-    request.push_str(body);
+    /// One line split at fixed character widths. Text past the last column
+    /// is ignored and a short line leaves the remaining fields empty.
+    fn next_fixed(&mut self) -> Vec<String> {
+        let RecordFormat::Fixed { widths, pad } = &self.format else {
+            return Vec::new();
+        };
+        let bytes = &*self.buf;
+        let end = bytes[self.idx..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(bytes.len(), |p| self.idx + p);
+        let line = String::from_utf8_lossy(&bytes[self.idx..end]);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        self.record_line = self.line;
+        self.line += 1;
+        self.idx = (end + 1).min(bytes.len());
+        // blank line
+        if line.is_empty() {
+            return vec![String::new()];
+        }
+        let mut chars = line.chars();
+        widths
+            .iter()
+            .map(|&w| {
+                let field: String = chars.by_ref().take(w).collect();
+                field.trim_matches(*pad).to_string()
+            })
+            .collect()
+    }
+}
 
-    stream
-        .write_all(request.as_bytes())
-        .map_err(|e| format!("write error: {}", e))?;
-    stream.flush().map_err(|e| format!("flush error: {}", e))?;
-    let mut resp = String::new();
-    stream
-        .read_to_string(&mut resp)
-        .map_err(|e| format!("read error: {}", e))?;
-    Ok(resp)
+/// Turn a column name into a clean field name: "Total Sales ($)" -> "total_sales"
+fn sanitize_header(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for ch in name.trim().to_lowercase().chars() {
+        if ch.is_alphanumeric() || ch == '.' {
+            out.push(ch);
+        } else if !out.ends_with('_') {
+            out.push('_');
+        }
+    }
+    // leading underscores are reserved for metadata fields
+    out.trim_matches(|c| c == '_' || c == '.').to_string()
 }
 
-/// Ping ES
-fn es_ping(target: &HttpTarget, auth: Option<(String, String)>) -> bool {
-    let addr = format!("{}:{}", target.host, target.port);
-    if let Ok(mut stream) = TcpStream::connect(&addr) {
-        let path = if target.base_path.is_empty() {
-            "/"
-        } else {
-            &target.base_path
-        };
-        let mut req = format!(
-            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
-            path, target.host
-        );
-        if let Some((u, p)) = auth {
-            let token = general_purpose::STANDARD.encode(format!("{}:{}", u, p));
-            req.push_str(&format!("Authorization: Basic {}\r\n", token));
+/// Make column names unique according to `policy`
+fn dedupe_headers(headers: &mut [String], policy: DuplicateHeaderPolicy) -> Result<(), String> {
+    for i in 1..headers.len() {
+        if !headers[..i].contains(&headers[i]) {
+            continue;
         }
-        req.push_str("\r\n");
-        if stream.write_all(req.as_bytes()).is_ok() {
-            let mut resp = String::new();
-            if stream.read_to_string(&mut resp).is_ok() {
-                return resp.starts_with("HTTP/1.1 200");
-            }
+        if policy == DuplicateHeaderPolicy::Error {
+            return Err(format!(
+                "duplicate column name \"{}\" (use --on-duplicate-header suffix)",
+                headers[i]
+            ));
         }
+        let mut n = 2;
+        let renamed = loop {
+            let candidate = format!("{}_{}", headers[i], n);
+            if !headers.contains(&candidate) {
+                break candidate;
+            }
+            n += 1;
+        };
+        log!(
+            "renamed duplicate column \"{}\" to \"{}\"",
+            headers[i],
+            renamed
+        );
+        headers[i] = renamed;
     }
-    false
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = parse_args();
-    let target =
-        parse_http_target(&args.host).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+/// CSV iterator with headers
+struct CsvIter {
+    rdr: CsvReader,
+    headers: Vec<String>,
+    on_ragged: RaggedPolicy,
+    /// Lines of ragged records that were skipped, padded or truncated
+    ragged_lines: Vec<usize>,
+    /// Offsets of invalid UTF-8 whose records are to be skipped
+    invalid_utf8: Vec<usize>,
+    /// Lines of the records skipped for it
+    invalid_utf8_lines: Vec<usize>,
+    /// Columns to strip of separators and currency symbols
+    numeric_cleanup: Vec<usize>,
+    /// Columns of `--expand-units`
+    expand_units: Vec<usize>,
+    strip_control_chars: bool,
+    /// Fallback values for empty cells, by column
+    defaults: Vec<(usize, String)>,
+    /// Columns of the input; `--compute`, `--lookup` and then `--geoip`
+    /// fields follow them in `headers`
+    width: usize,
+    computed: Vec<expr::Expr>,
+    /// `--lookup` with the column of its key
+    lookup: Option<(usize, Lookup)>,
+    /// `--geoip` column and database
+    geoip: Option<(usize, Arc<geoip::Database>)>,
+    masks: Vec<(usize, Mask)>,
+    anonymize: Vec<usize>,
+    anonymize_key: Vec<u8>,
+    /// (column, byte limit) of `--truncate-field`
+    truncate: Vec<(usize, usize)>,
+    /// Zones of the input columns whose naive date-times become UTC
+    zones: Vec<(usize, Arc<tz::Zone>)>,
+}
 
-    if !Path::new(&args.csv_file).exists() {
-        return Err(format!("CSV file not found: {}", args.csv_file).into());
+impl CsvIter {
+    fn from_reader(
+        mut r: impl BufRead,
+        opts: CsvOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf)?;
+        Self::from_input(Input::Owned(buf), opts)
     }
 
-    let auth = match (&args.user, &args.password) {
-        (Some(u), Some(p)) => Some((u.clone(), p.clone())),
-        _ => None,
-    };
-
-    if !es_ping(&target, auth.clone()) {
-        return Err(format!("Cannot connect to ES at {}", args.host).into());
+    /// Parse a local file in place through a memory map
+    fn from_mmap(file: &File, opts: CsvOptions) -> Result<Self, Box<dyn std::error::Error>> {
+        // SAFETY: the map is only read, and the input file isn't expected to
+        // change while it is imported
+        let map = unsafe { Mmap::map(file)? };
+        Self::from_input(Input::Mapped(map), opts)
     }
 
-    let file = File::open(&args.csv_file)?;
-    let reader = BufReader::new(file);
-    let mut csv = CsvIter::from_reader(reader)?;
-
-    let bulk_path = format!("{}/_bulk", target.base_path);
-    let mut batch: Vec<String> = Vec::with_capacity(args.batch_size * 2);
-    let mut total_docs = 0;
-
-    while let Some(row) = csv.next() {
-        batch.push(format!(
-            "{{\"index\":{{\"_index\":\"{}\"}}}}",
-            args.index_name
-        ));
-        batch.push(dict_to_json(&row));
-
-        if batch.len() / 2 >= args.batch_size {
-            let mut body = batch.join("\n");
-            body.push('\n');
-            let resp = http_post_bulk(&target, &bulk_path, &body, auth.clone())?;
-            if resp.contains("\"errors\":true") {
-                eprintln!("Bulk errors detected");
+    /// Parse the NDJSON a source plugin makes of the input
+    #[cfg(feature = "plugins")]
+    fn from_plugin(
+        plugin: &mut plugin::Plugin,
+        mut r: impl BufRead,
+        mut opts: CsvOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        match opts.format {
+            RecordFormat::Csv => {
+                opts.format = RecordFormat::Ndjson {
+                    columns: Vec::new(),
+                    max_depth: None,
+                    types: Vec::new(),
+                }
             }
-            total_docs += batch.len() / 2;
-            batch.clear();
+            RecordFormat::Ndjson { .. } => {}
+            _ => return Err("a source --plugin reads NDJSON, not another --format".into()),
         }
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf)?;
+        Self::from_input(Input::Owned(plugin.read(&buf)?), opts)
     }
 
-    if !batch.is_empty() {
-        let mut body = batch.join("\n");
-        body.push('\n');
-        let resp = http_post_bulk(&target, &bulk_path, &body, auth.clone())?;
-        if resp.contains("\"errors\":true") {
-            eprintln!("Bulk errors detected");
+    fn from_input(input: Input, opts: CsvOptions) -> Result<Self, Box<dyn std::error::Error>> {
+        let (input, mut invalid_utf8) = match opts.invalid_utf8 {
+            InvalidUtf8Policy::Error => (input, Vec::new()),
+            _ => repair_utf8(input),
+        };
+        if opts.invalid_utf8 == InvalidUtf8Policy::Replace && !invalid_utf8.is_empty() {
+            log!(
+                "input is not valid UTF-8: {} invalid sequences replaced with U+FFFD",
+                invalid_utf8.len()
+            );
+            invalid_utf8.clear();
+        }
+        let mut format = opts.format;
+        let mut headers = opts.headers;
+        match &mut format {
+            RecordFormat::Xml { records, columns } => {
+                *columns = records.field_names(&input)?;
+                headers = Some(columns.clone());
+            }
+            RecordFormat::Log { pattern } => {
+                headers = Some(
+                    pattern
+                        .capture_names()
+                        .flatten()
+                        .map(String::from)
+                        .collect(),
+                );
+            }
+            RecordFormat::Syslog { .. } => {
+                headers = Some(syslog::FIELDS.iter().map(|f| f.to_string()).collect());
+            }
+            RecordFormat::Bulk => headers = Some(vec!["_bulk".to_string()]),
+            RecordFormat::Ndjson {
+                columns,
+                max_depth,
+                types,
+            } => {
+                (*columns, *types) = ndjson::scan(&input, *max_depth)?;
+                headers = Some(columns.clone());
+            }
+            _ => {}
+        }
+        let dialect = match (&format, &headers) {
+            (RecordFormat::Csv, None) if opts.sniff => Some(sniff::sniff(&input, opts.comment)),
+            _ => None,
+        };
+        let mut rdr = CsvReader::new(input, opts.comment, format)?;
+        if let Some(dialect) = &dialect {
+            log!("--sniff: {}", dialect);
+            rdr.delimiter = dialect.delimiter;
+            rdr.quote = dialect.quote;
+            if !dialect.header {
+                // name the columns after the first record, then read it again
+                let (idx, line) = (rdr.idx, rdr.line);
+                let width = rdr.next_record()?.map_or(0, |r| r.len());
+                (rdr.idx, rdr.line) = (idx, line);
+                headers = Some((1..=width).map(|i| format!("field_{}", i)).collect());
+            }
+        }
+        let mut headers = match headers {
+            Some(headers) => headers,
+            None => rdr.next_record()?.unwrap_or_default(),
+        };
+        if opts.sanitize_headers {
+            for (i, h) in headers.iter_mut().enumerate() {
+                *h = sanitize_header(h);
+                if h.is_empty() {
+                    *h = format!("field_{}", i + 1);
+                }
+            }
+        }
+        dedupe_headers(&mut headers, opts.on_duplicate_header)?;
+        let column = |flag: &str, col: &str| {
+            headers
+                .iter()
+                .position(|h| h == col)
+                .ok_or_else(|| format!("{}: no column named {}", flag, col))
+        };
+        let numeric_cleanup = opts
+            .numeric_cleanup
+            .iter()
+            .map(|col| column("--numeric-cleanup", col))
+            .collect::<Result<_, _>>()?;
+        let expand_units = opts
+            .expand_units
+            .iter()
+            .map(|col| column("--expand-units", col))
+            .collect::<Result<_, _>>()?;
+        let defaults = opts
+            .defaults
+            .iter()
+            .map(|(col, value)| Ok((column("--default", col)?, value.clone())))
+            .collect::<Result<_, String>>()?;
+        let width = headers.len();
+        let mut computed = Vec::new();
+        for (name, mut expr) in opts.computed {
+            if headers.contains(&name) {
+                return Err(format!("--compute {}: column already exists", name).into());
+            }
+            expr.bind(&headers)
+                .map_err(|e| format!("--compute {}: {}", name, e))?;
+            headers.push(name);
+            computed.push(expr);
+        }
+        let lookup = match opts.lookup {
+            Some(lookup) => {
+                let key = headers
+                    .iter()
+                    .position(|h| *h == lookup.key)
+                    .ok_or_else(|| format!("--lookup-key: no column named {}", lookup.key))?;
+                if let Some(field) = lookup.fields.iter().find(|f| headers.contains(f)) {
+                    return Err(format!("--lookup-fields {}: column already exists", field).into());
+                }
+                headers.extend(lookup.fields.iter().cloned());
+                Some((key, lookup))
+            }
+            None => None,
+        };
+        let geoip = match opts.geoip {
+            Some((col, db)) => {
+                let i = headers
+                    .iter()
+                    .position(|h| *h == col)
+                    .ok_or_else(|| format!("--geoip: no column named {}", col))?;
+                let fields = geoip::FIELDS
+                    .iter()
+                    .map(|f| format!("{}.{}", GEOIP_FIELD, f));
+                for field in fields {
+                    if headers.contains(&field) {
+                        return Err(format!("--geoip: column {} already exists", field).into());
+                    }
+                    headers.push(field);
+                }
+                Some((i, db))
+            }
+            None => None,
+        };
+        let masks = opts
+            .masks
+            .iter()
+            .map(|(col, mask)| {
+                let i = headers
+                    .iter()
+                    .position(|h| h == col)
+                    .ok_or_else(|| format!("--mask: no column named {}", col))?;
+                Ok((i, *mask))
+            })
+            .collect::<Result<_, String>>()?;
+        let anonymize = opts
+            .anonymize
+            .iter()
+            .map(|col| {
+                headers
+                    .iter()
+                    .position(|h| h == col)
+                    .ok_or_else(|| format!("--anonymize: no column named {}", col))
+            })
+            .collect::<Result<_, _>>()?;
+        for (col, _) in &opts.truncate {
+            if let Some(col) = col
+                && !headers.contains(col)
+            {
+                return Err(format!("--truncate-field: no column named {}", col).into());
+            }
+        }
+        // a column's own limit or else the one for all columns
+        let limit = |name: &String| {
+            let own = opts
+                .truncate
+                .iter()
+                .rev()
+                .find(|(c, _)| c.as_ref() == Some(name));
+            own.or_else(|| opts.truncate.iter().rev().find(|(c, _)| c.is_none()))
+                .map(|&(_, max)| max)
+        };
+        let truncate = headers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| Some((i, limit(name)?)))
+            .collect();
+        for (col, _) in &opts.tz_columns {
+            if !headers[..width].contains(col) {
+                return Err(format!("--tz-column: no column named {}", col).into());
+            }
+        }
+        let zones = headers[..width]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, name)| {
+                let own = opts.tz_columns.iter().rev().find(|(c, _)| c == name);
+                let zone = own.map(|(_, z)| z).or(opts.input_tz.as_ref())?;
+                Some((i, zone.clone()))
+            })
+            .collect();
+        Ok(Self {
+            rdr,
+            headers,
+            on_ragged: opts.on_ragged,
+            ragged_lines: Vec::new(),
+            invalid_utf8,
+            invalid_utf8_lines: Vec::new(),
+            numeric_cleanup,
+            expand_units,
+            strip_control_chars: opts.strip_control_chars,
+            defaults,
+            width,
+            computed,
+            lookup,
+            geoip,
+            masks,
+            anonymize,
+            anonymize_key: opts.anonymize_key,
+            truncate,
+            zones,
+        })
+    }
+
+    /// Strip control characters if asked, apply per-column value cleanups
+    /// and unit expansion, then defaults, to a raw record, turn local
+    /// date-times into UTC, append the computed fields, mask or anonymize
+    /// what is to be and cut values to their --truncate-field
+    fn clean(&self, rec: &mut Vec<String>) {
+        if self.strip_control_chars {
+            let stripped = |c: char| c.is_control() && c != '\n' && c != '\t';
+            for v in rec.iter_mut().filter(|v| v.contains(stripped)) {
+                v.retain(|c| !stripped(c));
+            }
+        }
+        for &i in &self.numeric_cleanup {
+            if let Some(v) = rec.get_mut(i) {
+                *v = schema::clean_numeric(v);
+            }
+        }
+        for &i in &self.expand_units {
+            if let Some(v) = rec.get_mut(i)
+                && let Some(n) = schema::expand_units(v)
+            {
+                *v = n;
+            }
+        }
+        for (i, value) in &self.defaults {
+            if let Some(v) = rec.get_mut(*i)
+                && v.trim().is_empty()
+            {
+                v.clone_from(value);
+            }
+        }
+        for (i, zone) in &self.zones {
+            if let Some(v) = rec.get_mut(*i)
+                && let Some(local) = parse_naive_datetime(v.trim())
+            {
+                *v = zone
+                    .to_utc(local)
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true);
+            }
+        }
+        if !self.computed.is_empty() {
+            rec.resize(self.width, String::new());
+            for expr in &self.computed {
+                let value = expr.eval(rec);
+                rec.push(value);
+            }
+        }
+        if let Some((key, lookup)) = &self.lookup {
+            rec.resize(self.width + self.computed.len(), String::new());
+            match lookup.values.get(rec[*key].trim()) {
+                Some(values) => rec.extend(values.iter().cloned()),
+                None => rec.resize(rec.len() + lookup.fields.len(), String::new()),
+            }
+        }
+        if let Some((i, db)) = &self.geoip {
+            let width = self.width
+                + self.computed.len()
+                + self.lookup.as_ref().map_or(0, |(_, l)| l.fields.len());
+            rec.resize(width, String::new());
+            let located = db.locate(&rec[*i]);
+            rec.extend(located);
+        }
+        for &(i, mask) in &self.masks {
+            if let Some(v) = rec.get_mut(i) {
+                *v = mask.apply(v);
+            }
+        }
+        for &i in &self.anonymize {
+            if let Some(v) = rec.get_mut(i) {
+                *v = pseudonym(&self.anonymize_key, v);
+            }
+        }
+        for &(i, max) in &self.truncate {
+            if let Some(v) = rec.get_mut(i)
+                && v.len() > max
+            {
+                let mut end = max;
+                while !v.is_char_boundary(end) {
+                    end -= 1;
+                }
+                v.truncate(end);
+            }
+        }
+    }
+
+    /// Up to `n` records with the header's field count, from the start of
+    /// the data or, with `random`, picked uniformly from all of it. The
+    /// reader is left where it was.
+    fn sample(&mut self, n: usize, random: bool) -> Vec<Vec<String>> {
+        let (idx, line) = (self.rdr.idx, self.rdr.line);
+        let format = self.rdr.format.clone();
+        let unmatched = self.rdr.unmatched.len();
+        let mut sample = Vec::new();
+        let mut rng = XorShift::from_clock();
+        let mut seen = 0;
+        // errors surface again when the records are read for real
+        while let Ok(Some(mut rec)) = self.rdr.next_record() {
+            if rec.len() != self.width {
+                continue;
+            }
+            self.clean(&mut rec);
+            seen += 1;
+            if sample.len() < n {
+                sample.push(rec);
+                if !random && sample.len() == n {
+                    break;
+                }
+            } else {
+                // reservoir sampling: keep this record with probability n/seen
+                let slot = (rng.next() % seen as u64) as usize;
+                if slot < n {
+                    sample[slot] = rec;
+                }
+            }
+        }
+        self.rdr.idx = idx;
+        self.rdr.line = line;
+        self.rdr.format = format;
+        self.rdr.unmatched.truncate(unmatched);
+        sample
+    }
+
+    /// Whether the record read from `start` up to the reader's position is
+    /// to be skipped for invalid UTF-8
+    fn has_invalid_utf8(&self, start: usize) -> bool {
+        let after = self.invalid_utf8.partition_point(|&at| at < start);
+        self.invalid_utf8
+            .get(after)
+            .is_some_and(|&at| at < self.rdr.idx)
+    }
+
+    /// Numbers of the rows iteration will yield (from 0) that are the last
+    /// with their values of `columns`, found without consuming them
+    fn last_by(&mut self, columns: &[usize]) -> HashSet<usize> {
+        let (idx, line) = (self.rdr.idx, self.rdr.line);
+        let format = self.rdr.format.clone();
+        let unmatched = self.rdr.unmatched.len();
+        let mut last = HashMap::new();
+        let mut n = 0;
+        loop {
+            let start = self.rdr.idx;
+            let Ok(Some(mut rec)) = self.rdr.next_record() else {
+                break;
+            };
+            let blank = rec.len() == 1 && rec[0].is_empty();
+            if blank
+                || (rec.len() != self.width && self.on_ragged == RaggedPolicy::Skip)
+                || self.has_invalid_utf8(start)
+            {
+                continue;
+            }
+            self.clean(&mut rec);
+            let key: Vec<String> = columns
+                .iter()
+                .map(|&i| rec.get(i).map_or("", |v| v.trim()).to_string())
+                .collect();
+            last.insert(key, n);
+            n += 1;
+        }
+        self.rdr.idx = idx;
+        self.rdr.line = line;
+        self.rdr.format = format;
+        self.rdr.unmatched.truncate(unmatched);
+        last.into_values().collect()
+    }
+
+    /// Number of rows iteration will yield, counted without consuming them
+    fn count(&mut self) -> usize {
+        let (idx, line) = (self.rdr.idx, self.rdr.line);
+        let format = self.rdr.format.clone();
+        let unmatched = self.rdr.unmatched.len();
+        let mut n = 0;
+        loop {
+            let start = self.rdr.idx;
+            let Ok(Some(rec)) = self.rdr.next_record() else {
+                break;
+            };
+            let blank = rec.len() == 1 && rec[0].is_empty();
+            if !blank
+                && (rec.len() == self.width || self.on_ragged != RaggedPolicy::Skip)
+                && !self.has_invalid_utf8(start)
+            {
+                n += 1;
+            }
+        }
+        self.rdr.idx = idx;
+        self.rdr.line = line;
+        self.rdr.format = format;
+        self.rdr.unmatched.truncate(unmatched);
+        n
+    }
+}
+
+/// Which row of those sharing a `--dedupe-by` key is imported
+#[derive(Clone, Copy, ValueEnum)]
+enum DedupeKeep {
+    First,
+    Last,
+}
+
+/// `--dedupe-by`: which rows repeat the key of another one
+enum Dedupe {
+    /// Keeping the first, the keys seen so far
+    First {
+        columns: Vec<usize>,
+        seen: HashSet<Vec<String>>,
+    },
+    /// Keeping the last, the numbers of the rows to keep
+    Last(HashSet<usize>),
+}
+
+impl Dedupe {
+    fn new(columns: Vec<usize>, keep: DedupeKeep, csv: &mut CsvIter) -> Self {
+        match keep {
+            DedupeKeep::First => Self::First {
+                columns,
+                seen: HashSet::new(),
+            },
+            DedupeKeep::Last => Self::Last(csv.last_by(&columns)),
+        }
+    }
+
+    /// Whether row `n` (from 0) is to be dropped
+    fn is_duplicate(&mut self, n: usize, row: &[(String, String)]) -> bool {
+        match self {
+            Self::First { columns, seen } => {
+                !seen.insert(columns.iter().map(|&i| row[i].1.clone()).collect())
+            }
+            Self::Last(keep) => !keep.contains(&n),
+        }
+    }
+}
+
+/// xorshift, seeded from the clock; samples needn't be repeatable
+struct XorShift(u64);
+
+impl XorShift {
+    fn from_clock() -> Self {
+        Self(Utc::now().timestamp_nanos_opt().unwrap_or(1) as u64 | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// `--sample` or `--sample-rows`: which rows are imported
+#[derive(Clone, Copy)]
+enum Sampling {
+    /// Each row with this probability
+    Fraction(f64),
+    /// This many rows, picked uniformly
+    Rows(usize),
+}
+
+/// Picks the rows of a `Sampling`, in input order
+struct Sampler {
+    rng: XorShift,
+    fraction: Option<f64>,
+    /// Rows still to pick and rows left to pick them from, for `Rows`
+    wanted: u64,
+    left: u64,
+}
+
+impl Sampler {
+    fn new(sampling: Sampling, csv: &mut CsvIter) -> Self {
+        let (fraction, wanted, left) = match sampling {
+            Sampling::Fraction(f) => (Some(f), 0, 0),
+            Sampling::Rows(n) => (None, n as u64, csv.count() as u64),
+        };
+        Self {
+            rng: XorShift::from_clock(),
+            fraction,
+            wanted,
+            left,
+        }
+    }
+
+    /// Whether to import the next row
+    fn keep(&mut self) -> bool {
+        if let Some(f) = self.fraction {
+            // the top 53 bits as a uniform float in [0, 1)
+            let x = (self.rng.next() >> 11) as f64 / (1u64 << 53) as f64;
+            return x < f;
+        }
+        if self.left == 0 {
+            return false;
+        }
+        // selection sampling: every remaining row is equally likely to fill
+        // the remaining slots
+        let keep = self.rng.next() % self.left < self.wanted;
+        self.left -= 1;
+        if keep {
+            self.wanted -= 1;
+        }
+        keep
+    }
+}
+
+impl Iterator for CsvIter {
+    type Item = Result<Vec<(String, String)>, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = self.rdr.idx;
+            let mut rec = match self.rdr.next_record() {
+                Ok(rec) => rec?,
+                Err(e) => return Some(Err(e)),
+            };
+            if rec.is_empty() {
+                return None;
+            }
+            // blank line
+            if rec.len() == 1 && rec[0].is_empty() {
+                continue;
+            }
+
+            if self.has_invalid_utf8(start) {
+                let line = self.rdr.record_line;
+                log!("line {}: invalid UTF-8 (skipped)", line);
+                self.invalid_utf8_lines.push(line);
+                continue;
+            }
+
+            if rec.len() != self.width {
+                let line = self.rdr.record_line;
+                let short = rec.len() < self.width;
+                let action = match self.on_ragged {
+                    RaggedPolicy::Skip => "skipped",
+                    RaggedPolicy::Pad if short => "padded",
+                    RaggedPolicy::Truncate if !short => "truncated",
+                    policy => {
+                        let hint = match policy {
+                            RaggedPolicy::Pad => " (--on-ragged pad only fills short records)",
+                            RaggedPolicy::Truncate => {
+                                " (--on-ragged truncate only cuts long records)"
+                            }
+                            _ => "",
+                        };
+                        return Some(Err(format!(
+                            "line {}: expected {} fields, found {}{}",
+                            line,
+                            self.width,
+                            rec.len(),
+                            hint
+                        )));
+                    }
+                };
+                log!(
+                    "line {}: expected {} fields, found {} ({})",
+                    line,
+                    self.width,
+                    rec.len(),
+                    action
+                );
+                self.ragged_lines.push(line);
+                if self.on_ragged == RaggedPolicy::Skip {
+                    continue;
+                }
+            }
+
+            self.clean(&mut rec);
+            let mut row = Vec::with_capacity(self.headers.len());
+            for (i, name) in self.headers.iter().enumerate() {
+                let val = rec.get(i).map(|s| s.trim()).unwrap_or("");
+                row.push((name.clone(), val.to_string()));
+            }
+            return Some(Ok(row));
+        }
+    }
+}
+
+/// Append dict as JSON, typing each field by its column's inferred type
+/// (`types` is in header order) or, past those, by its own value. With a
+/// `nesting`, fields are written in its shape instead of flat.
+fn write_document(
+    out: &mut String,
+    row: &[(String, String)],
+    types: &[Option<ColumnType>],
+    nesting: Option<&[(String, Nesting)]>,
+) {
+    if let Some(nesting) = nesting {
+        return write_nested(out, nesting, row, types);
+    }
+    out.push('{');
+    for (i, (k, v)) in row.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push('"');
+        write_escaped(out, k);
+        out.push_str("\":");
+        schema::write_value(out, v, types.get(i).copied().flatten());
+    }
+    out.push('}');
+}
+
+/// Object shape of dotted field names for `--unflatten`:
+/// `address.city` is field `city` of object `address`
+enum Nesting {
+    /// Index of the field in each row
+    Field(usize),
+    Object(Vec<(String, Nesting)>),
+}
+
+impl Nesting {
+    /// Shape of rows with these field names. A name can't be both a field
+    /// and an object, as `address` and `address.city` would make it.
+    fn new(names: &[String]) -> Result<Vec<(String, Nesting)>, String> {
+        let mut root = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            let clash = || format!("--unflatten: {} is both a field and an object", name);
+            let parts: Vec<&str> = name.split('.').collect();
+            if parts.iter().any(|p| p.is_empty()) {
+                return Err(format!("--unflatten: can't nest field name {:?}", name));
+            }
+            let (leaf, path) = parts.split_last().unwrap();
+            let mut level = &mut root;
+            for part in path {
+                let at = match level.iter().position(|(k, _)| k == part) {
+                    Some(at) => at,
+                    None => {
+                        level.push((part.to_string(), Nesting::Object(Vec::new())));
+                        level.len() - 1
+                    }
+                };
+                level = match &mut level[at].1 {
+                    Nesting::Object(children) => children,
+                    Nesting::Field(_) => return Err(clash()),
+                };
+            }
+            if level.iter().any(|(k, _)| k == leaf) {
+                return Err(clash());
+            }
+            level.push((leaf.to_string(), Nesting::Field(i)));
+        }
+        Ok(root)
+    }
+}
+
+fn write_nested(
+    out: &mut String,
+    nesting: &[(String, Nesting)],
+    row: &[(String, String)],
+    types: &[Option<ColumnType>],
+) {
+    out.push('{');
+    for (n, (k, node)) in nesting.iter().enumerate() {
+        if n > 0 {
+            out.push(',');
+        }
+        out.push('"');
+        write_escaped(out, k);
+        out.push_str("\":");
+        match node {
+            Nesting::Field(i) => {
+                let v = row.get(*i).map_or("", |(_, v)| v.as_str());
+                schema::write_value(out, v, types.get(*i).copied().flatten());
+            }
+            Nesting::Object(children) => write_nested(out, children, row, types),
+        }
+    }
+    out.push('}');
+}
+
+/// `--group-by` and `--nest`: consecutive rows with the same key are one
+/// document, with the columns of each `--nest` gathered into an array of an
+/// object per row. The other columns are those of the group's first row.
+struct Grouping {
+    key: usize,
+    /// Columns kept as they are
+    parent: Vec<usize>,
+    nests: Vec<Nest>,
+    open: Option<Group>,
+}
+
+/// An array field of `--nest`
+struct Nest {
+    name: String,
+    columns: Vec<usize>,
+    /// Types of `columns`
+    types: Vec<Option<ColumnType>>,
+}
+
+/// Rows of the group being read
+struct Group {
+    first: Vec<(String, String)>,
+    /// JSON objects of each array so far, comma-separated
+    items: Vec<String>,
+    rows: usize,
+}
+
+impl Grouping {
+    fn new(
+        key: &str,
+        nests: &[(String, Vec<String>)],
+        headers: &[String],
+        types: &[Option<ColumnType>],
+    ) -> Result<Self, String> {
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| format!("no column named {}", name))
+        };
+        let key = column(key).map_err(|e| format!("--group-by: {}", e))?;
+        let mut nested = Vec::new();
+        let mut arrays = Vec::new();
+        for (name, columns) in nests {
+            let mut nest = Nest {
+                name: name.clone(),
+                columns: Vec::new(),
+                types: Vec::new(),
+            };
+            for col in columns {
+                let i = column(col).map_err(|e| format!("--nest {}: {}", name, e))?;
+                if i == key || nested.contains(&i) {
+                    return Err(format!(
+                        "--nest {}: column {} is the key or nested already",
+                        name, col
+                    ));
+                }
+                nested.push(i);
+                nest.columns.push(i);
+                nest.types.push(types.get(i).copied().flatten());
+            }
+            arrays.push(nest);
+        }
+        let parent: Vec<usize> = (0..headers.len()).filter(|i| !nested.contains(i)).collect();
+        if let Some(nest) = arrays
+            .iter()
+            .find(|n| parent.iter().any(|&i| headers[i] == n.name))
+        {
+            return Err(format!("--nest {} collides with a CSV column", nest.name));
+        }
+        Ok(Self {
+            key,
+            parent,
+            nests: arrays,
+            open: None,
+        })
+    }
+
+    /// Field names of the documents
+    fn headers(&self, headers: &[String]) -> Vec<String> {
+        let parent = self.parent.iter().map(|&i| headers[i].clone());
+        parent
+            .chain(self.nests.iter().map(|n| n.name.clone()))
+            .collect()
+    }
+
+    /// Types of the document fields; the arrays are JSON
+    fn types(&self, types: &[Option<ColumnType>]) -> Vec<Option<ColumnType>> {
+        let parent = self.parent.iter().map(|&i| types.get(i).copied().flatten());
+        parent
+            .chain(self.nests.iter().map(|_| Some(ColumnType::Object)))
+            .collect()
+    }
+
+    /// Add a row, returning the document of the group before it once it
+    /// starts a new one
+    fn push(&mut self, row: Vec<(String, String)>) -> Option<Vec<(String, String)>> {
+        let done = match &self.open {
+            Some(group) if group.first[self.key].1 != row[self.key].1 => self.finish(),
+            _ => None,
+        };
+        let group = self.open.get_or_insert_with(|| Group {
+            first: Vec::new(),
+            items: vec![String::new(); self.nests.len()],
+            rows: 0,
+        });
+        for (nest, items) in self.nests.iter().zip(&mut group.items) {
+            // an outer join's row without a match adds no item
+            if nest.columns.iter().all(|&i| row[i].1.is_empty()) {
+                continue;
+            }
+            if !items.is_empty() {
+                items.push(',');
+            }
+            let item: Vec<(String, String)> =
+                nest.columns.iter().map(|&i| row[i].clone()).collect();
+            write_document(items, &item, &nest.types, None);
+        }
+        if group.rows == 0 {
+            group.first = row;
+        }
+        group.rows += 1;
+        done
+    }
+
+    /// Document of the group being read, if any
+    fn finish(&mut self) -> Option<Vec<(String, String)>> {
+        let group = self.open.take()?;
+        let mut first = group.first;
+        let mut doc: Vec<(String, String)> = self
+            .parent
+            .iter()
+            .map(|&i| std::mem::take(&mut first[i]))
+            .collect();
+        for (nest, items) in self.nests.iter().zip(group.items) {
+            doc.push((nest.name.clone(), format!("[{}]", items)));
+        }
+        Some(doc)
+    }
+
+    /// Rows read into the group not yet finished
+    fn pending(&self) -> usize {
+        self.open.as_ref().map_or(0, |g| g.rows)
+    }
+}
+
+/// How `--merge-by` combines the values of a column
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum MergeStrategy {
+    /// The last non-empty value wins
+    Last,
+    /// An array of the non-empty values
+    Array,
+}
+
+/// Per-column figures of an import for its summary: empty values, values
+/// not fitting the column's type that go out as strings instead, and the
+/// range of numeric and date columns. Kept for the columns of the input,
+/// before rows are grouped or merged into documents.
+struct ColumnStats {
+    name: String,
+    ty: Option<ColumnType>,
+    empty: usize,
+    misfits: usize,
+    numbers: Option<(f64, f64)>,
+    dates: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl ColumnStats {
+    fn new(name: &str, ty: Option<ColumnType>) -> Self {
+        Self {
+            name: name.to_string(),
+            ty,
+            empty: 0,
+            misfits: 0,
+            numbers: None,
+            dates: None,
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        if value.is_empty() {
+            self.empty += 1;
+            return;
+        }
+        // values of untyped columns are typed one by one, so always fit
+        let Some(ty) = self.ty else {
+            return;
+        };
+        if !ty.fits(value) {
+            self.misfits += 1;
+            return;
+        }
+        let number = match ty {
+            ColumnType::Long
+            | ColumnType::UnsignedLong
+            | ColumnType::Double
+            | ColumnType::Decimal => f64::from_str(value).ok(),
+            ColumnType::Duration(unit) => unit.of(value),
+            ColumnType::Date => {
+                if let Some(dt) = parse_datetime(value) {
+                    let (min, max) = self.dates.get_or_insert((dt, dt));
+                    (*min, *max) = ((*min).min(dt), (*max).max(dt));
+                }
+                None
+            }
+            _ => None,
+        };
+        if let Some(n) = number {
+            let (min, max) = self.numbers.get_or_insert((n, n));
+            (*min, *max) = (min.min(n), max.max(n));
+        }
+    }
+
+    /// One summary line for the column
+    fn describe(&self) -> String {
+        let mut line = format!(
+            "  {}: {}, {} empty",
+            self.name,
+            self.ty
+                .map_or("typed by value".to_string(), |t| t.to_string()),
+            self.empty
+        );
+        if let Some(ty) = self.ty.filter(|_| self.misfits > 0) {
+            line.push_str(&format!(", {} not a valid {}", self.misfits, ty));
+        }
+        if let Some((min, max)) = self.numbers {
+            line.push_str(&format!(", {} to {}", min, max));
+        }
+        if let Some((min, max)) = self.dates {
+            let date = |dt: DateTime<Utc>| dt.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+            line.push_str(&format!(", {} to {}", date(min), date(max)));
+        }
+        line
+    }
+}
+
+/// `--merge-by`: all rows with the same key are one document. They may be
+/// anywhere in the input, so the documents are held until it ends.
+struct Merging {
+    key: usize,
+    strategy: MergeStrategy,
+    /// Column types, for the array items
+    types: Vec<Option<ColumnType>>,
+    /// Documents in the order their key first appeared; with `Array` the
+    /// values are JSON items, comma-separated, until `finish`
+    docs: VecDeque<Vec<(String, String)>>,
+    /// Position in `docs` of each key, while reading
+    positions: HashMap<String, usize>,
+    rows: usize,
+}
+
+impl Merging {
+    fn new(
+        key: &str,
+        strategy: MergeStrategy,
+        headers: &[String],
+        types: &[Option<ColumnType>],
+    ) -> Result<Self, String> {
+        let key = headers
+            .iter()
+            .position(|h| h == key)
+            .ok_or_else(|| format!("--merge-by: no column named {}", key))?;
+        Ok(Self {
+            key,
+            strategy,
+            types: types.to_vec(),
+            docs: VecDeque::new(),
+            positions: HashMap::new(),
+            rows: 0,
+        })
+    }
+
+    /// Types of the document fields; arrays are JSON
+    fn types(&self) -> Vec<Option<ColumnType>> {
+        match self.strategy {
+            MergeStrategy::Last => self.types.clone(),
+            MergeStrategy::Array => (0..self.types.len())
+                .map(|i| match i == self.key {
+                    true => self.types[i],
+                    false => Some(ColumnType::Object),
+                })
+                .collect(),
+        }
+    }
+
+    fn push(&mut self, row: Vec<(String, String)>) {
+        self.rows += 1;
+        let at = *self
+            .positions
+            .entry(row[self.key].1.clone())
+            .or_insert_with(|| {
+                let empty = row.iter().map(|(k, _)| (k.clone(), String::new()));
+                self.docs.push_back(empty.collect());
+                self.docs.len() - 1
+            });
+        let doc = &mut self.docs[at];
+        for (i, (_, v)) in row.into_iter().enumerate() {
+            let field = &mut doc[i].1;
+            if i == self.key || (self.strategy == MergeStrategy::Last && !v.is_empty()) {
+                *field = v;
+            } else if !v.is_empty() {
+                if !field.is_empty() {
+                    field.push(',');
+                }
+                schema::write_value(field, &v, self.types.get(i).copied().flatten());
+            }
+        }
+    }
+
+    /// The next document, once the input has been read
+    fn finish(&mut self) -> Option<Vec<(String, String)>> {
+        self.positions.clear();
+        let mut doc = self.docs.pop_front()?;
+        if self.strategy == MergeStrategy::Array {
+            for (i, (_, v)) in doc.iter_mut().enumerate() {
+                if i != self.key {
+                    *v = format!("[{}]", v);
+                }
+            }
+        }
+        Some(doc)
+    }
+
+    /// Rows read into documents not sent yet
+    fn pending(&self) -> usize {
+        if self.docs.is_empty() { 0 } else { self.rows }
+    }
+}
+
+/// Piece of a `{placeholder}` template
+enum TemplatePart {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// Split a template like "{country}-{order_id}" into literals and
+/// placeholders. `{{` and `}}` stand for literal braces.
+fn parse_template(s: &str) -> Result<Vec<TemplatePart>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unclosed '{{' in template: {}", s)),
+                    }
+                }
+                if name.is_empty() {
+                    return Err(format!("empty placeholder in template: {}", s));
+                }
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(TemplatePart::Placeholder(name));
+            }
+            '}' => return Err(format!("unmatched '}}' in template: {}", s)),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(TemplatePart::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// Document _id built from column values
+struct IdTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl IdTemplate {
+    fn new(template: &str, headers: &[String]) -> Result<Self, String> {
+        let parts = parse_template(template)?;
+        for part in &parts {
+            if let TemplatePart::Placeholder(name) = part
+                && !headers.contains(name)
+            {
+                return Err(format!("unknown column in id template: {}", name));
+            }
+        }
+        Ok(Self { parts })
+    }
+
+    fn render(&self, row: &[(String, String)]) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(s) => out.push_str(s),
+                TemplatePart::Placeholder(name) => {
+                    if let Some((_, v)) = row.iter().find(|(k, _)| k == name) {
+                        out.push_str(v);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Document _id derived from a hash of the row content
+struct IdHash {
+    algorithm: HashAlgorithm,
+    /// Columns to hash, sorted so the _id doesn't depend on column order
+    columns: Vec<String>,
+}
+
+impl IdHash {
+    fn new(
+        algorithm: HashAlgorithm,
+        columns: &[String],
+        headers: &[String],
+    ) -> Result<Self, String> {
+        let mut columns = if columns.is_empty() {
+            headers.to_vec()
+        } else {
+            for c in columns {
+                if !headers.contains(c) {
+                    return Err(format!("unknown column in --id-hash-columns: {}", c));
+                }
+            }
+            columns.to_vec()
+        };
+        columns.sort();
+        columns.dedup();
+        Ok(Self { algorithm, columns })
+    }
+
+    fn render(&self, row: &[(String, String)]) -> String {
+        // name \x1f value \x1e per column keeps ("ab","c") distinct from ("a","bc")
+        let mut input = Vec::new();
+        for name in &self.columns {
+            let value = row.iter().find(|(k, _)| k == name).map_or("", |(_, v)| v);
+            input.extend_from_slice(name.as_bytes());
+            input.push(0x1f);
+            input.extend_from_slice(value.as_bytes());
+            input.push(0x1e);
+        }
+        let digest = match self.algorithm {
+            HashAlgorithm::Sha1 => Sha1::digest(&input).to_vec(),
+            HashAlgorithm::Sha256 => Sha256::digest(&input).to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Source of document IDs
+enum IdGenerator {
+    Template(IdTemplate),
+    Hash(IdHash),
+}
+
+impl IdGenerator {
+    fn render(&self, row: &[(String, String)]) -> String {
+        match self {
+            Self::Template(t) => t.render(row),
+            Self::Hash(h) => h.render(row),
+        }
+    }
+}
+
+/// Parse the date formats commonly found in CSV exports; naive values are
+/// taken as UTC
+fn parse_datetime(s: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    // access log time, 10/Oct/2000:13:55:36 -0700
+    if let Ok(dt) = DateTime::parse_from_str(s, "%d/%b/%Y:%H:%M:%S %z") {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Some(dt) = parse_naive_datetime(s) {
+        return Some(dt.and_utc());
+    }
+    for fmt in ["%Y-%m-%d", "%Y/%m/%d", "%Y%m%d"] {
+        if let Ok(d) = NaiveDate::parse_from_str(s, fmt) {
+            return d.and_hms_opt(0, 0, 0).map(|dt| dt.and_utc());
+        }
+    }
+    None
+}
+
+/// A date-time without an offset, in the formats `parse_datetime` reads
+/// as UTC
+fn parse_naive_datetime(s: &str) -> Option<NaiveDateTime> {
+    [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y/%m/%d %H:%M:%S",
+    ]
+    .into_iter()
+    .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+}
+
+/// Check a strftime format string up front; chrono panics on bad ones
+fn validate_strftime(fmt: &str) -> Result<(), String> {
+    if StrftimeItems::new(fmt).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid date format: {}", fmt));
+    }
+    Ok(())
+}
+
+/// Lowercase a column value for use in an index name, rejecting values ES
+/// would refuse
+fn index_name_from_value(column: &str, value: &str) -> Result<String, String> {
+    const ILLEGAL: &[char] = &['\\', '/', '*', '?', '"', '<', '>', '|', ' ', ',', '#', ':'];
+    if value.is_empty() {
+        return Err(format!(
+            "column {} is empty, cannot derive index name",
+            column
+        ));
+    }
+    if value.contains(ILLEGAL) {
+        return Err(format!(
+            "column {}: \"{}\" contains characters not allowed in index names",
+            column, value
+        ));
+    }
+    Ok(value.to_lowercase())
+}
+
+/// Piece of a resolved index name template
+enum IndexPart {
+    Literal(String),
+    Column {
+        name: String,
+        format: Option<String>,
+    },
+}
+
+/// Index name with `{now/d}` date math and `{column:%Y.%m}` placeholders
+struct IndexTemplate {
+    parts: Vec<IndexPart>,
+}
+
+impl IndexTemplate {
+    /// Whether every row goes to the same index
+    fn is_fixed(&self) -> bool {
+        self.parts
+            .iter()
+            .all(|p| matches!(p, IndexPart::Literal(_)))
+    }
+
+    /// `now` is resolved once so an import spanning midnight stays in one index
+    fn new(template: &str, headers: &[String], now: DateTime<Utc>) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        for part in parse_template(template)? {
+            let placeholder = match part {
+                TemplatePart::Literal(s) => {
+                    parts.push(IndexPart::Literal(s));
+                    continue;
+                }
+                TemplatePart::Placeholder(p) => p,
+            };
+            let (name, format) = match placeholder.split_once(':') {
+                Some((n, f)) => (n, Some(f.to_string())),
+                None => (placeholder.as_str(), None),
+            };
+            if let Some(f) = &format {
+                validate_strftime(f)?;
+            }
+
+            if name == "now" || name.starts_with("now/") {
+                let default = match name {
+                    "now" | "now/d" => "%Y.%m.%d",
+                    "now/H" => "%Y.%m.%d.%H",
+                    "now/w" => "%G.%V",
+                    "now/M" => "%Y.%m",
+                    "now/y" => "%Y",
+                    _ => return Err(format!("unsupported date math in index name: {}", name)),
+                };
+                let fmt = format.as_deref().unwrap_or(default);
+                parts.push(IndexPart::Literal(now.format(fmt).to_string()));
+            } else if headers.iter().any(|h| h == name) {
+                parts.push(IndexPart::Column {
+                    name: name.to_string(),
+                    format,
+                });
+            } else {
+                return Err(format!("unknown column in index name: {}", name));
+            }
+        }
+        Ok(Self { parts })
+    }
+
+    fn render(&self, row: &[(String, String)]) -> Result<String, String> {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                IndexPart::Literal(s) => out.push_str(s),
+                IndexPart::Column { name, format } => {
+                    let value = row.iter().find(|(k, _)| k == name).map_or("", |(_, v)| v);
+                    match format {
+                        Some(fmt) => {
+                            let dt = parse_datetime(value).ok_or_else(|| {
+                                format!("column {}: cannot parse date \"{}\"", name, value)
+                            })?;
+                            out.push_str(&dt.format(fmt).to_string());
+                        }
+                        None => out.push_str(&index_name_from_value(name, value)?),
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Append bulk action metadata line
+fn write_action(out: &mut String, index: &str, id: Option<&str>) {
+    out.push_str("{\"index\":{\"_index\":\"");
+    write_escaped(out, index);
+    if let Some(id) = id {
+        out.push_str("\",\"_id\":\"");
+        write_escaped(out, id);
+    }
+    out.push_str("\"}}");
+}
+
+/// JSON of a document to index, with the `_id` and `_index` a transform
+/// gave it, if any
+#[cfg(any(feature = "script", feature = "plugins"))]
+struct Doc {
+    json: String,
+    id: Option<String>,
+    index: Option<String>,
+}
+
+/// `--script` and `--plugin` transforms, run in that order
+#[cfg(any(feature = "script", feature = "plugins"))]
+struct Transforms {
+    #[cfg(feature = "script")]
+    script: Option<script::Script>,
+    #[cfg(feature = "plugins")]
+    plugin: Option<plugin::Plugin>,
+}
+
+#[cfg(any(feature = "script", feature = "plugins"))]
+impl Transforms {
+    /// Options that set the transforms, for messages
+    fn describe(&self) -> String {
+        let mut names = Vec::new();
+        #[cfg(feature = "script")]
+        if self.script.is_some() {
+            names.push("--script");
+        }
+        #[cfg(feature = "plugins")]
+        if self.plugin.as_ref().is_some_and(|p| p.is_transform()) {
+            names.push("--plugin");
+        }
+        names.join(" and ")
+    }
+
+    /// Documents to index in place of a row, or `None` if there are no
+    /// transforms and the row is indexed as it is
+    fn apply(
+        &mut self,
+        row: &[(String, String)],
+        types: &[Option<ColumnType>],
+        nesting: Option<&[(String, Nesting)]>,
+    ) -> Result<Option<Vec<Doc>>, String> {
+        let mut docs = None;
+        #[cfg(feature = "script")]
+        if let Some(script) = &self.script {
+            docs = Some(script.run(row, types, nesting)?);
+        }
+        #[cfg(feature = "plugins")]
+        if let Some(plugin) = self.plugin.as_mut().filter(|p| p.is_transform()) {
+            let input = docs.unwrap_or_else(|| {
+                let mut json = String::new();
+                write_document(&mut json, row, types, nesting);
+                vec![Doc {
+                    json,
+                    id: None,
+                    index: None,
+                }]
+            });
+            let mut out = Vec::with_capacity(input.len());
+            for doc in &input {
+                out.extend(plugin.transform(doc)?);
+            }
+            docs = Some(out);
+        }
+        Ok(docs)
+    }
+}
+
+/// Bulk request body under construction: action/document line pairs
+/// written into one buffer that is cleared and reused for later batches
+#[derive(Default)]
+struct Batch {
+    body: String,
+    /// End offset in `body` of each action/document pair
+    ends: Vec<usize>,
+}
+
+impl Batch {
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.body.clear();
+        self.ends.clear();
+    }
+
+    fn push(
+        &mut self,
+        index: &str,
+        id: Option<&str>,
+        row: &[(String, String)],
+        types: &[Option<ColumnType>],
+        nesting: Option<&[(String, Nesting)]>,
+    ) {
+        write_action(&mut self.body, index, id);
+        self.body.push('\n');
+        write_document(&mut self.body, row, types, nesting);
+        self.body.push('\n');
+        self.ends.push(self.body.len());
+    }
+
+    /// Add a document that is already JSON
+    #[cfg(any(feature = "script", feature = "plugins"))]
+    fn push_json(&mut self, index: &str, id: Option<&str>, doc: &str) {
+        write_action(&mut self.body, index, id);
+        self.body.push('\n');
+        self.body.push_str(doc);
+        self.body.push('\n');
+        self.ends.push(self.body.len());
+    }
+
+    /// Action and document lines of item `i`
+    fn item(&self, i: usize) -> &str {
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+        &self.body[start..self.ends[i]]
+    }
+
+    /// Add an item of another batch
+    fn push_item(&mut self, item: &str) {
+        self.body.push_str(item);
+        self.ends.push(self.body.len());
+    }
+
+    /// Take out the items from `from` on that are over `max` bytes, and
+    /// return them
+    fn remove_over(&mut self, from: usize, max: usize) -> Vec<String> {
+        let removed: Vec<String> = (from..self.len())
+            .map(|i| self.item(i))
+            .filter(|item| item.len() > max)
+            .map(String::from)
+            .collect();
+        if !removed.is_empty() {
+            let kept: Vec<String> = (from..self.len())
+                .map(|i| self.item(i))
+                .filter(|item| item.len() <= max)
+                .map(String::from)
+                .collect();
+            self.body
+                .truncate(if from == 0 { 0 } else { self.ends[from - 1] });
+            self.ends.truncate(from);
+            for item in &kept {
+                self.push_item(item);
+            }
+        }
+        removed
+    }
+
+    /// Add a --format bulk item as it is, but for an action naming no
+    /// _index getting `index`
+    fn push_bulk(&mut self, index: &str, item: &str) {
+        let item = item.trim_end();
+        let (action, doc) = match item.split_once('\n') {
+            Some((action, doc)) => (action, Some(doc)),
+            None => (item, None),
+        };
+        match json::parse(action) {
+            Ok(Value::Object(mut ops))
+                if ops
+                    .first()
+                    .is_some_and(|(_, meta)| meta.get("_index").is_none()) =>
+            {
+                if let Some((_, Value::Object(meta))) = ops.first_mut() {
+                    meta.insert(0, ("_index".to_string(), Value::String(index.to_string())));
+                }
+                self.body.push_str(&Value::Object(ops).to_string());
+            }
+            _ => self.body.push_str(action),
+        }
+        self.body.push('\n');
+        if let Some(doc) = doc {
+            self.body.push_str(doc);
+            self.body.push('\n');
+        }
+        self.ends.push(self.body.len());
+    }
+}
+
+/// Parsed HTTP response
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl HttpResponse {
+    fn parse(raw: &[u8]) -> Result<Self, String> {
+        let split = raw
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or("malformed HTTP response")?;
+        let head = String::from_utf8_lossy(&raw[..split]);
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|l| l.split(' ').nth(1))
+            .and_then(|s| s.parse().ok())
+            .ok_or("malformed HTTP status line")?;
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+
+        let mut resp = Self {
+            status,
+            headers,
+            body: String::new(),
+        };
+        let body = &raw[split + 4..];
+        let body = if resp
+            .header("transfer-encoding")
+            .is_some_and(|te| te.eq_ignore_ascii_case("chunked"))
+        {
+            decode_chunked(body)?
+        } else {
+            body.to_vec()
+        };
+        resp.body = String::from_utf8_lossy(&body).into_owned();
+        Ok(resp)
+    }
+
+    /// Header value, case-insensitive
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Reassemble a `Transfer-Encoding: chunked` body
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let eol = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or("malformed chunked body")?;
+        let size_line = String::from_utf8_lossy(&data[..eol]);
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_hex, 16).map_err(|_| "malformed chunk size")?;
+        data = &data[eol + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        let chunk = data.get(..size).ok_or("truncated chunked body")?;
+        out.extend_from_slice(chunk);
+        data = data.get(size + 2..).unwrap_or_default();
+    }
+}
+
+trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+/// Open a connection to the target, with TLS for https://
+fn connect(target: &HttpTarget) -> Result<Box<dyn ReadWrite>, String> {
+    let addr = format!("{}:{}", target.host, target.port);
+    let tcp = match target.timeout {
+        Some(t) => addr.to_socket_addrs().and_then(|mut addrs| {
+            let a = addrs.next().ok_or(io::ErrorKind::AddrNotAvailable)?;
+            let tcp = TcpStream::connect_timeout(&a, t)?;
+            tcp.set_read_timeout(Some(t))?;
+            tcp.set_write_timeout(Some(t))?;
+            Ok(tcp)
+        }),
+        None => TcpStream::connect(&addr),
+    }
+    .map_err(|e| format!("connect error: {}", e))?;
+    // the request head and body go out in separate writes
+    let _ = tcp.set_nodelay(true);
+    let Some(config) = &target.tls else {
+        return Ok(Box::new(tcp));
+    };
+    let name = ServerName::try_from(target.host.clone())
+        .map_err(|_| format!("invalid TLS server name: {}", target.host))?;
+    let conn =
+        ClientConnection::new(config.clone(), name).map_err(|e| format!("TLS error: {}", e))?;
+    Ok(Box::new(StreamOwned::new(conn, tcp)))
+}
+
+/// How requests to ES authenticate
+#[derive(Clone)]
+enum Auth {
+    Basic(String, String),
+    /// Base64 `id:key`, as Elasticsearch hands it out
+    ApiKey(String),
+    SigV4(Arc<sigv4::Signer>),
+}
+
+/// Where a cluster is and how to log in to it
+#[derive(Clone)]
+struct Connection {
+    host: String,
+    user: Option<String>,
+    password: Option<String>,
+    api_key: Option<String>,
+    tls: TlsOptions,
+    /// (region, service) to sign requests for with AWS SigV4
+    aws_sigv4: Option<(String, String)>,
+    debug_http: bool,
+    user_agent: Option<String>,
+    opaque_id: Option<String>,
+}
+
+impl Connection {
+    fn target(&self) -> Result<HttpTarget, String> {
+        let mut target = parse_http_target(&self.host, &self.tls)?;
+        target.debug = self.debug_http;
+        if let Some(agent) = &self.user_agent {
+            target.user_agent = agent.clone();
+        }
+        target.opaque_id = self.opaque_id.clone();
+        Ok(target)
+    }
+
+    fn auth(&self) -> Result<Option<Auth>, String> {
+        if let Some(key) = &self.api_key {
+            return Ok(Some(Auth::ApiKey(key.clone())));
+        }
+        Ok(match (&self.user, &self.password, &self.aws_sigv4) {
+            (Some(u), Some(p), _) => Some(Auth::Basic(u.clone(), p.clone())),
+            (_, _, Some((region, service))) => {
+                Some(Auth::SigV4(Arc::new(sigv4::Signer::new(region, service)?)))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Send a request to ES
+fn http_request(
+    target: &HttpTarget,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &str,
+    auth: Option<&Auth>,
+) -> Result<HttpResponse, String> {
+    let mut headers = vec![("Content-Type".to_string(), content_type.to_string())];
+    match auth {
+        Some(Auth::Basic(user, pass)) => {
+            let token = general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+            headers.push(("Authorization".to_string(), format!("Basic {}", token)));
+        }
+        Some(Auth::ApiKey(key)) => {
+            headers.push(("Authorization".to_string(), format!("ApiKey {}", key)));
+        }
+        Some(Auth::SigV4(signer)) => {
+            headers.extend(signer.sign(method, &target.host, path, body)?);
+        }
+        None => {}
+    }
+    http_exchange(target, method, path, &headers, body)
+}
+
+/// Send one request with the given extra headers and read the response
+pub(crate) fn http_exchange(
+    target: &HttpTarget,
+    method: &str,
+    path: &str,
+    headers: &[(String, String)],
+    body: &str,
+) -> Result<HttpResponse, String> {
+    let mut stream = connect(target)?;
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: {}\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        target.host,
+        target.user_agent,
+        body.len()
+    );
+    if let Some(id) = &target.opaque_id {
+        request.push_str(&format!("X-Opaque-Id: {}\r\n", id));
+    }
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    request.push_str("\r\n");
+    if target.debug {
+        debug_request(&request);
+    }
+
+    stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.write_all(body.as_bytes()))
+        .map_err(|e| format!("write error: {}", e))?;
+    stream.flush().map_err(|e| format!("flush error: {}", e))?;
+    let mut resp = Vec::new();
+    if let Err(e) = stream.read_to_end(&mut resp) {
+        // servers often close TLS connections without close_notify once the
+        // response is complete
+        if e.kind() != io::ErrorKind::UnexpectedEof || resp.is_empty() {
+            return Err(format!("read error: {}", e));
+        }
+    }
+    let parsed = HttpResponse::parse(&resp);
+    if target.debug {
+        debug_response(&resp, parsed.as_ref().ok());
+    }
+    parsed
+}
+
+/// Response bodies are cut to this many bytes by --debug-http
+const DEBUG_BODY_BYTES: usize = 2048;
+
+/// Print the head of a request as sent, with credentials blanked out
+fn debug_request(head: &str) {
+    let mut out = String::new();
+    for line in head.trim_end().split("\r\n") {
+        let line = match line.split_once(':') {
+            Some((name, _))
+                if [
+                    "authorization",
+                    "proxy-authorization",
+                    "x-amz-security-token",
+                ]
+                .iter()
+                .any(|h| name.eq_ignore_ascii_case(h)) =>
+            {
+                format!("{}: [redacted]", name)
+            }
+            _ => line.to_string(),
+        };
+        out.push_str(&format!("> {}\n", line));
+    }
+    eprint!("{}", out);
+}
+
+/// Print a response's status line, headers and the start of its body
+fn debug_response(raw: &[u8], parsed: Option<&HttpResponse>) {
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n");
+    let head = String::from_utf8_lossy(&raw[..split.unwrap_or(raw.len())]);
+    let mut out = String::new();
+    for line in head.split("\r\n") {
+        out.push_str(&format!("< {}\n", line));
+    }
+    if let Some(resp) = parsed
+        && !resp.body.is_empty()
+    {
+        let mut end = resp.body.len().min(DEBUG_BODY_BYTES);
+        while !resp.body.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.push_str(&format!("< {}", &resp.body[..end]));
+        if end < resp.body.len() {
+            out.push_str(&format!("... ({} bytes)", resp.body.len()));
+        }
+        out.push('\n');
+    }
+    eprint!("{}", out);
+}
+
+/// Send bulk request to ES
+fn http_post_bulk(
+    target: &HttpTarget,
+    bulk_path: &str,
+    body: &str,
+    auth: Option<&Auth>,
+) -> Result<HttpResponse, String> {
+    http_request(
+        target,
+        "POST",
+        bulk_path,
+        "application/x-ndjson",
+        body,
+        auth,
+    )
+}
+
+/// Per-item outcome of a bulk request, by item position
+#[derive(Default)]
+struct BulkItems {
+    /// Rejected because the cluster was busy; worth sending again
+    retryable: Vec<usize>,
+    /// Failed for good, with the error reason
+    failed: Vec<(usize, String)>,
+}
+
+fn parse_bulk_items(body: &str) -> Result<BulkItems, String> {
+    let resp = json::parse(body).map_err(|e| format!("bad bulk response: {}", e))?;
+    let mut out = BulkItems::default();
+    if resp.get("errors").and_then(Value::as_bool) != Some(true) {
+        return Ok(out);
+    }
+    let items = resp
+        .get("items")
+        .and_then(Value::as_array)
+        .ok_or("bulk response has no items")?;
+    for (i, item) in items.iter().enumerate() {
+        // {"index": {"status": 429, "error": {...}}}
+        let Some((_, result)) = item.as_object().and_then(|f| f.first()) else {
+            continue;
+        };
+        let Some(error) = result.get("error") else {
+            continue;
+        };
+        let status = result.get("status").and_then(Value::as_u64).unwrap_or(0);
+        let kind = error
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown");
+        if status == 429 || kind == "es_rejected_execution_exception" {
+            out.retryable.push(i);
+        } else {
+            let reason = error.get("reason").and_then(Value::as_str).unwrap_or("");
+            out.failed.push((i, format!("{}: {}", kind, reason)));
+        }
+    }
+    Ok(out)
+}
+
+/// _index and _id of the documents a bulk response says were created, as
+/// opposed to overwritten or failed
+fn created_docs(body: &str) -> Result<Vec<(String, String)>, String> {
+    let resp = json::parse(body).map_err(|e| format!("bad bulk response: {}", e))?;
+    let items = resp
+        .get("items")
+        .and_then(Value::as_array)
+        .ok_or("bulk response has no items")?;
+    Ok(items
+        .iter()
+        .filter_map(|item| {
+            let (_, result) = item.as_object()?.first()?;
+            if result.get("result").and_then(Value::as_str) != Some("created") {
+                return None;
+            }
+            Some((
+                result.get("_index")?.as_str()?.to_string(),
+                result.get("_id")?.as_str()?.to_string(),
+            ))
+        })
+        .collect())
+}
+
+/// Why a bulk request failed
+enum BulkError {
+    /// Cluster unreachable or erroring (connection errors, 5xx); worth waiting
+    /// and sending the documents still pending again
+    Unavailable(String, Resume),
+    /// Anything else, e.g. a 4xx for a request ES will never accept
+    Fatal(String),
+}
+
+/// Documents indexed and failed in one batch
+#[derive(Default)]
+struct BatchStats {
+    indexed: usize,
+    failed: usize,
+    /// Left out by `--resume-from-index` as already in the index
+    skipped: usize,
+}
+
+/// Where to pick up a batch that `send_bulk` didn't get through
+struct Resume {
+    /// Positions of the documents not yet indexed or failed
+    pending: Vec<usize>,
+    /// What became of the others
+    stats: BatchStats,
+}
+
+impl Resume {
+    /// Nothing of `batch` sent yet
+    fn start(batch: &Batch) -> Self {
+        Self {
+            pending: (0..batch.len()).collect(),
+            stats: BatchStats::default(),
+        }
+    }
+}
+
+/// `--capture-dir`: failed bulk requests saved with the responses to them,
+/// to be sent again by hand or passed on
+struct Capture {
+    dir: PathBuf,
+    /// Tells apart requests failing within the same millisecond
+    seq: AtomicUsize,
+}
+
+impl Capture {
+    fn new(dir: &str) -> Result<Self, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir, e))?;
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            seq: AtomicUsize::new(0),
+        })
+    }
+
+    /// Write `body` and `response` next to each other, reporting where
+    fn save(&self, body: &str, response: &str, progress: &dashboard::Progress) {
+        let name = format!(
+            "{}-{}",
+            Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+            self.seq.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        let request = self.dir.join(format!("{}.request.ndjson", name));
+        let written = std::fs::write(&request, body).and_then(|_| {
+            std::fs::write(self.dir.join(format!("{}.response.txt", name)), response)
+        });
+        match written {
+            Ok(()) => progress.message(format!("Failed request saved as {}", request.display())),
+            Err(e) => progress.message(format!(
+                "cannot save failed request in {}: {}",
+                self.dir.display(),
+                e
+            )),
+        }
+    }
+}
+
+impl HttpResponse {
+    /// Status, headers and body as they came, near enough
+    fn to_text(&self) -> String {
+        let mut text = format!("HTTP {}\r\n", self.status);
+        for (name, value) in &self.headers {
+            text.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        text.push_str("\r\n");
+        text.push_str(&self.body);
+        text
+    }
+}
+
+/// How `send_bulk` retries and what it reports
+struct BulkPolicy<'a> {
+    max_retries: u32,
+    /// Warn about bulk requests taking longer than this
+    slow: Option<Duration>,
+    capture: Option<&'a Capture>,
+    /// Told how each bulk request went, with --adaptive-batch-size
+    sizer: Option<&'a BatchSizer>,
+    throttle: &'a Throttle,
+    /// Collects the _index and _id of the documents created, with
+    /// --rollback-on-failure
+    created: Option<&'a Mutex<Vec<(String, String)>>>,
+}
+
+/// Bulk requests allowed in flight at once: halved when the cluster's write
+/// thread pool starts rejecting documents and raised again one at a time
+/// while it doesn't, up to --concurrency
+struct Throttle {
+    max: usize,
+    state: Mutex<ThrottleState>,
+    changed: Condvar,
+}
+
+struct ThrottleState {
+    allowed: usize,
+    sending: usize,
+    /// Last rejection, or last step back up
+    since: Instant,
+    /// Last cut
+    cut: Option<Instant>,
+}
+
+impl Throttle {
+    /// Rejection-free time before one more request is let through
+    const COOL_DOWN: Duration = Duration::from_secs(30);
+    /// Rejections this soon after a cut come from requests sent before it
+    const SETTLE: Duration = Duration::from_secs(2);
+
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            state: Mutex::new(ThrottleState {
+                allowed: max,
+                sending: 0,
+                since: Instant::now(),
+                cut: None,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Wait for a turn to send; the request is done when the permit drops
+    fn acquire(&self, progress: &dashboard::Progress) -> Permit<'_> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.allowed < self.max && state.since.elapsed() >= Self::COOL_DOWN {
+                state.allowed += 1;
+                state.since = Instant::now();
+                progress.message(format!("Concurrency back up to {}", state.allowed));
+            }
+            if state.sending < state.allowed {
+                state.sending += 1;
+                return Permit(self);
+            }
+            state = self.changed.wait_timeout(state, Self::COOL_DOWN).unwrap().0;
+        }
+    }
+
+    /// `docs` documents were rejected as the cluster is overloaded
+    fn rejected(&self, docs: usize, progress: &dashboard::Progress) {
+        let mut state = self.state.lock().unwrap();
+        if state.allowed > 1 && state.cut.is_none_or(|cut| cut.elapsed() >= Self::SETTLE) {
+            state.allowed /= 2;
+            state.cut = Some(Instant::now());
+            progress.message(format!(
+                "{} documents rejected by the write thread pool, concurrency down to {}",
+                docs, state.allowed
+            ));
+        }
+        state.since = Instant::now();
+    }
+}
+
+struct Permit<'a>(&'a Throttle);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().sending -= 1;
+        self.0.changed.notify_one();
+    }
+}
+
+/// `--adaptive-batch-size`: additive increase, multiplicative decrease of
+/// the documents per batch, shared by all workers
+struct BatchSizer {
+    size: AtomicUsize,
+    /// Added after each request within `target`
+    step: usize,
+    target: Duration,
+}
+
+impl BatchSizer {
+    const MIN: usize = 10;
+    const MAX: usize = 100_000;
+    /// Batches this large don't grow, well below ES's default 100mb
+    /// http.max_content_length
+    const MAX_BYTES: usize = 20 << 20;
+
+    fn new(initial: usize, target: Duration) -> Self {
+        Self {
+            size: AtomicUsize::new(initial),
+            step: (initial / 4).max(1),
+            target,
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Grow after a request of `bytes` went through in time
+    fn grow(&self, bytes: usize) {
+        if bytes < Self::MAX_BYTES {
+            let _ = self
+                .size
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| {
+                    Some((size + self.step).min(Self::MAX))
+                });
+        }
+    }
+
+    /// Halve after rejections, failures and slow requests; the new size,
+    /// unless it was as small as it gets already
+    fn shrink(&self) -> Option<usize> {
+        let halve = |size: usize| (size > Self::MIN).then(|| (size / 2).max(Self::MIN));
+        let before = self
+            .size
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, halve)
+            .ok()?;
+        halve(before)
+    }
+}
+
+/// Send the documents of a batch still pending in `from`, as action/document
+/// line pairs. Items rejected because the cluster is busy are resent on
+/// their own with exponential backoff.
+fn send_bulk(
+    target: &HttpTarget,
+    bulk_path: &str,
+    batch: &Batch,
+    from: Resume,
+    auth: Option<&Auth>,
+    policy: &BulkPolicy,
+    progress: &dashboard::Progress,
+) -> Result<BatchStats, BulkError> {
+    let BulkPolicy {
+        max_retries,
+        slow,
+        capture,
+        sizer,
+        throttle,
+        created,
+    } = *policy;
+    let shrink = |why: &str| {
+        if let Some(size) = sizer.and_then(BatchSizer::shrink) {
+            progress.message(format!("{}, batch size down to {}", why, size));
+        }
+    };
+    let Resume {
+        mut pending,
+        mut stats,
+    } = from;
+    let mut attempt = 0;
+    let mut retry_body = String::new();
+    loop {
+        let body = if pending.len() == batch.len() {
+            &batch.body
+        } else {
+            retry_body.clear();
+            for &i in &pending {
+                retry_body.push_str(batch.item(i));
+            }
+            &retry_body
+        };
+        let permit = throttle.acquire(progress);
+        let started = Instant::now();
+        let sent = http_post_bulk(target, bulk_path, body, auth);
+        drop(permit);
+        let resp = match sent {
+            Ok(resp) => resp,
+            Err(e) => {
+                if let Some(capture) = capture {
+                    capture.save(body, &e, progress);
+                }
+                shrink("Bulk request failed");
+                return Err(BulkError::Unavailable(e, Resume { pending, stats }));
+            }
+        };
+        if let Some(slow) = slow
+            && started.elapsed() > slow
+        {
+            progress.message(slow_bulk_message(
+                started.elapsed(),
+                pending.len(),
+                &resp,
+                progress,
+            ));
+        }
+        let items = match resp.status {
+            200 => parse_bulk_items(&resp.body).map_err(BulkError::Fatal)?,
+            429 => BulkItems {
+                retryable: (0..pending.len()).collect(),
+                failed: Vec::new(),
+            },
+            status => {
+                if let Some(capture) = capture {
+                    capture.save(body, &resp.to_text(), progress);
+                }
+                let msg = format!("bulk request failed: HTTP {}: {}", status, resp.body);
+                if status >= 500 {
+                    shrink("Bulk request failed");
+                }
+                return Err(if status >= 500 {
+                    BulkError::Unavailable(msg, Resume { pending, stats })
+                } else {
+                    BulkError::Fatal(msg)
+                });
+            }
+        };
+        if let Some(created) = created
+            && resp.status == 200
+        {
+            created
+                .lock()
+                .unwrap()
+                .extend(created_docs(&resp.body).map_err(BulkError::Fatal)?);
+        }
+        if let Some(capture) = capture
+            && (resp.status != 200 || !items.failed.is_empty())
+        {
+            capture.save(body, &resp.to_text(), progress);
+        }
+
+        if !items.retryable.is_empty() {
+            throttle.rejected(items.retryable.len(), progress);
+        }
+        if let Some(sizer) = sizer {
+            if !items.retryable.is_empty() {
+                shrink("Documents rejected by busy cluster");
+            } else if started.elapsed() > sizer.target {
+                shrink("Bulk request slower than --target-bulk-ms");
+            } else {
+                sizer.grow(body.len());
+            }
+        }
+
+        if let Some((_, reason)) = items.failed.first() {
+            progress.message(format!(
+                "{} documents failed in bulk request, first error: {}",
+                items.failed.len(),
+                reason
+            ));
+        }
+        stats.failed += items.failed.len();
+        stats.indexed += pending.len() - items.failed.len() - items.retryable.len();
+        if items.retryable.is_empty() {
+            return Ok(stats);
+        }
+        if attempt >= max_retries {
+            progress.message(format!(
+                "{} documents still rejected after {} retries",
+                items.retryable.len(),
+                max_retries
+            ));
+            stats.failed += items.retryable.len();
+            return Ok(stats);
+        }
+
+        let backoff = Duration::from_millis(
+            1u64.checked_shl(attempt)
+                .map_or(u64::MAX, |n| n.saturating_mul(500)),
+        )
+        .min(Duration::from_secs(30));
+        progress.message(format!(
+            "{} documents rejected by busy cluster, retrying in {:?}",
+            items.retryable.len(),
+            backoff
+        ));
+        progress.retried(items.retryable.len());
+        thread::sleep(backoff);
+        pending = items.retryable.iter().map(|&i| pending[i]).collect();
+        attempt += 1;
+    }
+}
+
+/// `--slow-bulk-ms` warning: how long the cluster says it took, which node
+/// answered when a proxy says so, and how many other requests were out
+fn slow_bulk_message(
+    elapsed: Duration,
+    docs: usize,
+    resp: &HttpResponse,
+    progress: &dashboard::Progress,
+) -> String {
+    let mut msg = format!(
+        "Slow bulk request: {} documents took {}ms (HTTP {}",
+        docs,
+        elapsed.as_millis(),
+        resp.status
+    );
+    if let Ok(body) = json::parse(&resp.body) {
+        for (field, label) in [("took", "took"), ("ingest_took", "ingest took")] {
+            if let Some(ms) = body.get(field).and_then(Value::as_u64) {
+                msg.push_str(&format!(", {} {}ms", label, ms));
+            }
+        }
+    }
+    // set by Elastic Cloud's proxy
+    if let Some(node) = resp.header("x-found-handling-instance") {
+        msg.push_str(&format!(", node {}", node));
+    }
+    msg.push_str(&format!(
+        ", {} more in flight)",
+        progress.in_flight().saturating_sub(1)
+    ));
+    msg
+}
+
+/// `--es-lookup`: fields to copy from documents of another index, matched
+/// on a key field both have
+#[derive(Clone)]
+struct EsLookup {
+    index: String,
+    key: String,
+    fields: Vec<String>,
+}
+
+impl FromStr for EsLookup {
+    type Err = String;
+
+    /// "index=users,key=user_id,fields=email,plan"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut index, mut key, mut fields) = (None, None, Vec::new());
+        let mut in_fields = false;
+        for part in s.split(',').map(str::trim) {
+            match part.split_once('=') {
+                Some(("index", name)) => index = Some(name.to_string()),
+                Some(("key", field)) => key = Some(field.to_string()),
+                Some(("fields", field)) => fields.push(field.to_string()),
+                // the fields list goes on
+                None if in_fields => fields.push(part.to_string()),
+                _ => return Err(format!("expected index=, key= or fields=, found {}", part)),
+            }
+            in_fields = part.starts_with("fields=") || (in_fields && !part.contains('='));
+        }
+        let (Some(index), Some(key)) = (index, key) else {
+            return Err("expected index=INDEX,key=FIELD,fields=F[,F]".to_string());
+        };
+        if fields.is_empty() {
+            return Err("expected index=INDEX,key=FIELD,fields=F[,F]".to_string());
+        }
+        if fields.iter().chain([&index, &key]).any(String::is_empty) {
+            return Err("empty index, key or field name".to_string());
+        }
+        Ok(Self { index, key, fields })
+    }
+}
+
+/// Text of a key field, to match documents on
+fn key_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) | Value::Number(s) => Some(s.clone()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Keys looked up per search, as ES returns at most `max_result_window`
+/// (10000 by default) hits to one
+const LOOKUP_CHUNK: usize = 10_000;
+
+impl EsLookup {
+    /// `batch` with the fields of the lookup index added to the documents
+    /// whose key was found there, replacing fields of the same name
+    fn enrich(
+        &self,
+        target: &HttpTarget,
+        batch: &Batch,
+        auth: Option<&Auth>,
+    ) -> Result<Batch, String> {
+        let mut items = Vec::with_capacity(batch.len());
+        let mut keys = HashSet::new();
+        for i in 0..batch.len() {
+            let (action, doc) = batch
+                .item(i)
+                .trim_end()
+                .split_once('\n')
+                .ok_or("bulk item without a document")?;
+            let doc = json::parse(doc)?;
+            let key = doc.get(&self.key).and_then(key_text);
+            keys.extend(key.clone());
+            items.push((action, doc, key));
+        }
+        let found = self.search(target, &keys, auth)?;
+        let mut enriched = Batch::default();
+        for (action, mut doc, key) in items {
+            if let Some(values) = key.and_then(|k| found.get(&k))
+                && let Value::Object(fields) = &mut doc
+            {
+                for (name, value) in values {
+                    match fields.iter_mut().find(|(field, _)| field == name) {
+                        Some((_, old)) => *old = value.clone(),
+                        None => fields.push((name.clone(), value.clone())),
+                    }
+                }
+            }
+            enriched.push_item(&format!("{}\n{}\n", action, doc));
+        }
+        Ok(enriched)
+    }
+
+    /// The lookup fields of the documents with these keys, by key; of
+    /// several documents with a key one counts
+    fn search(
+        &self,
+        target: &HttpTarget,
+        keys: &HashSet<String>,
+        auth: Option<&Auth>,
+    ) -> Result<HashMap<String, Vec<(String, Value)>>, String> {
+        let mut found = HashMap::new();
+        let keys: Vec<&String> = keys.iter().collect();
+        for chunk in keys.chunks(LOOKUP_CHUNK) {
+            self.search_chunk(target, chunk, auth, &mut found)?;
+        }
+        Ok(found)
+    }
+
+    /// `search` for up to `LOOKUP_CHUNK` keys, adding to `found`. Hits are
+    /// collapsed on the key, so that more documents with one key can't take
+    /// up the places of other keys.
+    fn search_chunk(
+        &self,
+        target: &HttpTarget,
+        keys: &[&String],
+        auth: Option<&Auth>,
+        found: &mut HashMap<String, Vec<(String, Value)>>,
+    ) -> Result<(), String> {
+        let quoted = |s: &String| format!("\"{}\"", json_escape(s));
+        let source: Vec<String> = self.fields.iter().chain([&self.key]).map(quoted).collect();
+        let terms: Vec<String> = keys.iter().map(|k| quoted(k)).collect();
+        let body = format!(
+            "{{\"size\":{},\"_source\":[{}],\"query\":{{\"terms\":{{{}:[{}]}}}},\
+             \"collapse\":{{\"field\":{}}}}}",
+            keys.len(),
+            source.join(","),
+            quoted(&self.key),
+            terms.join(","),
+            quoted(&self.key)
+        );
+        let path = format!("{}/{}/_search", target.base_path, self.index);
+        let resp = http_request(target, "POST", &path, "application/json", &body, auth)?;
+        if resp.status != 200 {
+            return Err(format!(
+                "cannot look up documents in {}: HTTP {}: {}",
+                self.index, resp.status, resp.body
+            ));
+        }
+        let parsed = json::parse(&resp.body)?;
+        let hits = parsed
+            .get("hits")
+            .and_then(|h| h.get("hits"))
+            .and_then(Value::as_array)
+            .ok_or_else(|| format!("bad _search response: {}", resp.body))?;
+        for source in hits.iter().filter_map(|hit| hit.get("_source")) {
+            let Some(key) = source.get(&self.key).and_then(key_text) else {
+                continue;
+            };
+            let fields = self
+                .fields
+                .iter()
+                .filter_map(|f| Some((f.clone(), source.get(f)?.clone())))
+                .collect();
+            found.entry(key).or_insert(fields);
+        }
+        Ok(())
+    }
+}
+
+/// Rollover conditions, see `--rollover-after`
+#[derive(Clone, Default)]
+struct RolloverConditions {
+    max_docs: Option<u64>,
+    max_size: Option<String>,
+    max_age: Option<String>,
+}
+
+impl FromStr for RolloverConditions {
+    type Err = String;
+
+    /// "10000000", "50gb", "7d" or several of them comma-separated
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const SIZE_UNITS: &[&str] = &["kb", "mb", "gb", "tb", "pb", "b"];
+        let mut conditions = Self::default();
+        for cond in s.split(',').map(|c| c.trim().to_lowercase()) {
+            let split = cond
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(cond.len());
+            let (num, unit) = cond.split_at(split);
+            if num.is_empty() {
+                return Err(format!("invalid --rollover-after condition: {}", cond));
+            }
+            if unit.is_empty() {
+                conditions.max_docs = Some(
+                    num.parse()
+                        .map_err(|_| format!("invalid doc count: {}", num))?,
+                );
+            } else if SIZE_UNITS.contains(&unit) {
+                conditions.max_size = Some(cond.clone());
+            } else if is_time_value(&cond) {
+                conditions.max_age = Some(cond.clone());
+            } else {
+                return Err(format!("invalid --rollover-after condition: {}", cond));
+            }
+        }
+        Ok(conditions)
+    }
+}
+
+impl RolloverConditions {
+    fn to_json(&self) -> String {
+        let mut conds = Vec::new();
+        if let Some(n) = self.max_docs {
+            conds.push(format!("\"max_docs\":{}", n));
+        }
+        if let Some(s) = &self.max_size {
+            conds.push(format!("\"max_size\":\"{}\"", s));
+        }
+        if let Some(a) = &self.max_age {
+            conds.push(format!("\"max_age\":\"{}\"", a));
+        }
+        format!("{{\"conditions\":{{{}}}}}", conds.join(","))
+    }
+}
+
+/// Ask ES to roll the write alias over if any condition is met
+fn es_rollover(
+    target: &HttpTarget,
+    alias: &str,
+    conditions: &RolloverConditions,
+    auth: Option<&Auth>,
+) -> Result<Option<String>, String> {
+    let path = format!("{}/{}/_rollover", target.base_path, alias);
+    let resp = http_request(
+        target,
+        "POST",
+        &path,
+        "application/json",
+        &conditions.to_json(),
+        auth,
+    )?;
+    if resp.status != 200 {
+        return Err(format!("rollover of {} failed: {}", alias, resp.body));
+    }
+    let body = json::parse(&resp.body)?;
+    if body.get("rolled_over").and_then(Value::as_bool) != Some(true) {
+        return Ok(None);
+    }
+    let new_index = body.get("new_index").and_then(Value::as_str).unwrap_or("?");
+    Ok(Some(new_index.to_string()))
+}
+
+/// Set by the first SIGINT or SIGTERM, after which an import stops at the
+/// next batch; a second one ends the process at once
+fn watch_signals() -> Result<Arc<AtomicBool>, String> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::flag;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        // registered first so it sees the flag before the second signal sets it
+        flag::register_conditional_shutdown(signal, 130, Arc::clone(&stop))
+            .and_then(|_| flag::register(signal, Arc::clone(&stop)))
+            .map_err(|e| format!("cannot handle signal {}: {}", signal, e))?;
+    }
+    Ok(stop)
+}
+
+/// Resume point kept in the `--checkpoint` file
+struct Checkpoint {
+    path: String,
+    /// Input file the checkpoint belongs to
+    file: String,
+    /// Input rows fully processed so far
+    rows: usize,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint, starting from scratch if the file doesn't exist
+    fn load(path: &str, file: &str) -> Result<Self, String> {
+        let mut checkpoint = Self {
+            path: path.to_string(),
+            file: file.to_string(),
+            rows: 0,
+        };
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return Ok(checkpoint);
+        };
+        let v = json::parse(&text).map_err(|e| format!("bad checkpoint {}: {}", path, e))?;
+        if v.get("file").and_then(Value::as_str) != Some(file) {
+            return Err(format!(
+                "checkpoint {} belongs to a different input file",
+                path
+            ));
+        }
+        checkpoint.rows = v.get("rows").and_then(Value::as_u64).unwrap_or(0) as usize;
+        Ok(checkpoint)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let tmp = format!("{}.tmp", self.path);
+        let text = format!(
+            "{{\"file\":\"{}\",\"rows\":{}}}\n",
+            json_escape(&self.file),
+            self.rows
+        );
+        std::fs::write(&tmp, text)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| format!("cannot write checkpoint {}: {}", self.path, e))
+    }
+}
+
+/// Whether `index` (or an alias of that name) exists
+fn es_index_exists(target: &HttpTarget, index: &str, auth: Option<&Auth>) -> Result<bool, String> {
+    let path = format!("{}/{}", target.base_path, index);
+    let resp = http_request(target, "HEAD", &path, "application/json", "", auth)?;
+    match resp.status {
+        200 => Ok(true),
+        404 => Ok(false),
+        status => Err(format!("cannot check index {}: HTTP {}", index, status)),
+    }
+}
+
+/// An index setting changed for the import by `--tune-replicas` or
+/// `--tune-refresh`, set back to what it was on drop
+struct TunedSetting<'a> {
+    target: &'a HttpTarget,
+    index: String,
+    name: &'static str,
+    /// `None` if it wasn't set and the default applied
+    before: Option<String>,
+    /// Refresh the index once the setting is back
+    refresh: bool,
+    auth: Option<&'a Auth>,
+}
+
+impl<'a> TunedSetting<'a> {
+    /// Set `name` of `index` to `value`, creating the index if need be;
+    /// `None` if it has that value already
+    fn new(
+        target: &'a HttpTarget,
+        index: &str,
+        name: &'static str,
+        value: &str,
+        auth: Option<&'a Auth>,
+    ) -> Result<Option<Self>, String> {
+        if !es_index_exists(target, index, auth)? {
+            let path = format!("{}/{}", target.base_path, index);
+            let resp = http_request(target, "PUT", &path, "application/json", "", auth)?;
+            if resp.status != 200 {
+                return Err(format!("cannot create index {}: {}", index, resp.body));
+            }
+        }
+        let path = format!("{}/{}/_settings/{}", target.base_path, index, name);
+        let resp = http_request(target, "GET", &path, "application/json", "", auth)?;
+        if resp.status != 200 {
+            return Err(format!("cannot get settings of {}: {}", index, resp.body));
+        }
+        // {"<index>": {"settings": {"index": {"number_of_replicas": "1"}}}}
+        let key = name.trim_start_matches("index.");
+        let before = json::parse(&resp.body)?
+            .as_object()
+            .and_then(|indices| indices.first())
+            .and_then(|(_, s)| s.get("settings")?.get("index")?.get(key).cloned())
+            .and_then(|v| v.as_str().map(String::from));
+        if before.as_deref() == Some(value) {
+            return Ok(None);
+        }
+        es_put_setting(target, index, name, Some(value), auth)?;
+        log!(
+            "{} of {} set to {} for the import (was {})",
+            name,
+            index,
+            value,
+            before.as_deref().unwrap_or("the default")
+        );
+        Ok(Some(Self {
+            target,
+            index: index.to_string(),
+            name,
+            before,
+            refresh: false,
+            auth,
+        }))
+    }
+}
+
+impl Drop for TunedSetting<'_> {
+    fn drop(&mut self) {
+        let restored = es_put_setting(
+            self.target,
+            &self.index,
+            self.name,
+            self.before.as_deref(),
+            self.auth,
+        );
+        let before = self.before.as_deref().unwrap_or("the default");
+        match restored {
+            Ok(()) => log!("{} of {} back to {}", self.name, self.index, before),
+            Err(e) => log!(
+                "cannot set {} of {} back to {}: {}",
+                self.name,
+                self.index,
+                before,
+                e
+            ),
+        }
+        if self.refresh {
+            let path = format!("{}/{}/_refresh", self.target.base_path, self.index);
+            match http_request(
+                self.target,
+                "POST",
+                &path,
+                "application/json",
+                "",
+                self.auth,
+            ) {
+                Ok(resp) if resp.status == 200 => {}
+                Ok(resp) => log!("cannot refresh {}: {}", self.index, resp.body),
+                Err(e) => log!("cannot refresh {}: {}", self.index, e),
+            }
+        }
+    }
+}
+
+/// Set an index setting, or reset it to the default with `None`
+fn es_put_setting(
+    target: &HttpTarget,
+    index: &str,
+    name: &str,
+    value: Option<&str>,
+    auth: Option<&Auth>,
+) -> Result<(), String> {
+    let path = format!("{}/{}/_settings", target.base_path, index);
+    let value = value.map_or("null".to_string(), |v| format!("\"{}\"", json_escape(v)));
+    let body = format!("{{\"{}\":{}}}", name, value);
+    let resp = http_request(target, "PUT", &path, "application/json", &body, auth)?;
+    if resp.status != 200 {
+        return Err(format!("HTTP {}: {}", resp.status, resp.body));
+    }
+    Ok(())
+}
+
+/// How often `es_forcemerge` looks at the task
+const FORCEMERGE_POLL: Duration = Duration::from_secs(10);
+
+/// Force merge `index` to `segments` segments as a task, reporting the
+/// segment count while waiting for it to finish
+fn es_forcemerge(
+    target: &HttpTarget,
+    index: &str,
+    segments: u32,
+    auth: Option<&Auth>,
+) -> Result<(), String> {
+    let path = format!(
+        "{}/{}/_forcemerge?max_num_segments={}&wait_for_completion=false",
+        target.base_path, index, segments
+    );
+    let resp = http_request(target, "POST", &path, "application/json", "", auth)?;
+    let task = match resp.status {
+        200 => json::parse(&resp.body)?
+            .get("task")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .ok_or_else(|| format!("no task in _forcemerge response: {}", resp.body))?,
+        _ => return Err(format!("cannot force merge {}: {}", index, resp.body)),
+    };
+    log!("Force merging {} (max_num_segments={})", index, segments);
+    let started = Instant::now();
+    loop {
+        thread::sleep(FORCEMERGE_POLL);
+        let path = format!("{}/_tasks/{}", target.base_path, task);
+        let resp = http_request(target, "GET", &path, "application/json", "", auth)?;
+        let status = match resp.status {
+            200 => json::parse(&resp.body)?,
+            // finished and not kept
+            404 => break,
+            _ => {
+                return Err(format!(
+                    "cannot get force merge task {}: {}",
+                    task, resp.body
+                ));
+            }
+        };
+        if status.get("completed").and_then(Value::as_bool) == Some(true) {
+            if let Some(error) = status.get("error") {
+                return Err(format!("force merge of {} failed: {}", index, error));
+            }
+            break;
+        }
+        let path = format!("{}/{}/_stats/segments", target.base_path, index);
+        let count = http_request(target, "GET", &path, "application/json", "", auth)
+            .ok()
+            .and_then(|resp| json::parse(&resp.body).ok())
+            .and_then(|stats| {
+                stats
+                    .get("_all")?
+                    .get("total")?
+                    .get("segments")?
+                    .get("count")?
+                    .as_u64()
+            });
+        match count {
+            Some(n) => log!(
+                "Force merging {}: {} segments, {}s",
+                index,
+                n,
+                started.elapsed().as_secs()
+            ),
+            None => log!("Force merging {}: {}s", index, started.elapsed().as_secs()),
+        }
+    }
+    log!("Force merged {} in {}s", index, started.elapsed().as_secs());
+    Ok(())
+}
+
+/// Point `alias` at `index` and nothing else in one _aliases request,
+/// returning the other indices it was on
+fn es_move_alias(
+    target: &HttpTarget,
+    alias: &str,
+    index: &str,
+    auth: Option<&Auth>,
+) -> Result<Vec<String>, String> {
+    let path = format!("{}/_alias/{}", target.base_path, alias);
+    let resp = http_request(target, "GET", &path, "application/json", "", auth)?;
+    let old = match resp.status {
+        // {"products-1": {"aliases": {"products": {}}}}
+        200 => json::parse(&resp.body)?
+            .as_object()
+            .unwrap_or_default()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .filter(|name| name != index)
+            .collect(),
+        404 => Vec::new(),
+        _ => return Err(format!("cannot look up alias {}: {}", alias, resp.body)),
+    };
+    let mut actions: Vec<String> = old
+        .iter()
+        .map(|name| {
+            format!(
+                "{{\"remove\":{{\"index\":\"{}\",\"alias\":\"{}\"}}}}",
+                json_escape(name),
+                json_escape(alias)
+            )
+        })
+        .collect();
+    actions.push(format!(
+        "{{\"add\":{{\"index\":\"{}\",\"alias\":\"{}\"}}}}",
+        json_escape(index),
+        json_escape(alias)
+    ));
+    let path = format!("{}/_aliases", target.base_path);
+    let body = format!("{{\"actions\":[{}]}}", actions.join(","));
+    let resp = http_request(target, "POST", &path, "application/json", &body, auth)?;
+    if resp.status != 200 {
+        return Err(format!("cannot move alias {}: {}", alias, resp.body));
+    }
+    Ok(old)
+}
+
+/// Number of documents in `index` once the ones just indexed are searchable
+fn es_refreshed_count(
+    target: &HttpTarget,
+    index: &str,
+    auth: Option<&Auth>,
+) -> Result<u64, String> {
+    let path = format!("{}/{}/_refresh", target.base_path, index);
+    let resp = http_request(target, "POST", &path, "application/json", "", auth)?;
+    if resp.status != 200 {
+        return Err(format!("cannot refresh {}: {}", index, resp.body));
+    }
+    es_count(target, index, "{\"query\":{\"match_all\":{}}}", auth)
+}
+
+/// Mapping of `index`, or `None` if it doesn't exist yet
+fn es_mapping(
+    target: &HttpTarget,
+    index: &str,
+    auth: Option<&Auth>,
+) -> Result<Option<Value>, String> {
+    let path = format!("{}/{}/_mapping", target.base_path, index);
+    let resp = http_request(target, "GET", &path, "application/json", "", auth)?;
+    match resp.status {
+        200 => Ok(Some(json::parse(&resp.body)?)),
+        404 => Ok(None),
+        _ => Err(format!("cannot get mapping of {}: {}", index, resp.body)),
+    }
+}
+
+/// Field `--attach-column` files are written to, the attachment
+/// processor's default
+const ATTACHMENT_FIELD: &str = "data";
+
+/// Create ingest pipeline `name` with an attachment processor unless it
+/// exists; whether it was created
+fn es_ensure_attachment_pipeline(
+    target: &HttpTarget,
+    name: &str,
+    auth: Option<&Auth>,
+) -> Result<bool, String> {
+    let path = format!("{}/_ingest/pipeline/{}", target.base_path, name);
+    let resp = http_request(target, "GET", &path, "application/json", "", auth)?;
+    match resp.status {
+        200 => return Ok(false),
+        404 => {}
+        _ => {
+            return Err(format!(
+                "cannot get ingest pipeline {}: {}",
+                name, resp.body
+            ));
+        }
+    }
+    let body = format!(
+        "{{\"description\":\"Extract --attach-column files\",\"processors\":[{{\"attachment\":{{\"field\":\"{}\",\"remove_binary\":true}}}}]}}",
+        ATTACHMENT_FIELD
+    );
+    let resp = http_request(target, "PUT", &path, "application/json", &body, auth)?;
+    if resp.status != 200 {
+        return Err(format!(
+            "cannot create ingest pipeline {}: {}",
+            name, resp.body
+        ));
+    }
+    Ok(true)
+}
+
+/// Put the `--component-template`s and then the `--index-template`, which
+/// is made up of the former unless it lists its own `composed_of`
+fn install_templates(
+    target: &HttpTarget,
+    components: &[(String, String)],
+    index_template: Option<&(String, String)>,
+    auth: Option<&Auth>,
+) -> Result<(), String> {
+    let load = |path: &str, option: &str| {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {} {}: {}", option, path, e))?;
+        json::parse(&text).map_err(|e| format!("bad {} {}: {}", option, path, e))
+    };
+    let put = |kind: &str, name: &str, body: &Value| {
+        let path = format!("{}/_{}/{}", target.base_path, kind, name);
+        let resp = http_request(
+            target,
+            "PUT",
+            &path,
+            "application/json",
+            &body.to_string(),
+            auth,
+        )?;
+        if resp.status != 200 {
+            return Err(format!("cannot put {} {}: {}", kind, name, resp.body));
+        }
+        Ok(())
+    };
+    for (name, path) in components {
+        put(
+            "component_template",
+            name,
+            &load(path, "--component-template")?,
+        )?;
+        log!("Installed component template {}", name);
+    }
+    if let Some((name, path)) = index_template {
+        let mut body = load(path, "--index-template")?;
+        if let Value::Object(fields) = &mut body
+            && !components.is_empty()
+            && !fields.iter().any(|(k, _)| k == "composed_of")
+        {
+            let names = components
+                .iter()
+                .map(|(name, _)| Value::String(name.clone()))
+                .collect();
+            fields.push(("composed_of".to_string(), Value::Array(names)));
+        }
+        put("index_template", name, &body)?;
+        log!("Installed index template {}", name);
+    }
+    Ok(())
+}
+
+/// `--settings` or `--mapping` FILE: a JSON object, alone or as the `key`
+/// of an index creation body
+fn index_part(path: &str, option: &str, key: &str) -> Result<String, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {} {}: {}", option, path, e))?;
+    let value = json::parse(&text).map_err(|e| format!("bad {} {}: {}", option, path, e))?;
+    match value.as_object() {
+        Some([(k, part)]) if k == key => Ok(part.to_string()),
+        Some(_) => Ok(value.to_string()),
+        None => Err(format!("{} {} isn't a JSON object", option, path)),
+    }
+}
+
+/// Snapshot `index` alone to `repo` and wait for it to finish
+fn es_snapshot(
+    target: &HttpTarget,
+    repo: &str,
+    index: &str,
+    auth: Option<&Auth>,
+) -> Result<(), String> {
+    let name = format!(
+        "{}-before-import-{}",
+        index,
+        Utc::now().format("%Y.%m.%d-%H%M%S")
+    );
+    let path = format!(
+        "{}/_snapshot/{}/{}?wait_for_completion=true",
+        target.base_path, repo, name
+    );
+    let body = format!(
+        "{{\"indices\":\"{}\",\"include_global_state\":false}}",
+        json_escape(index)
+    );
+    log!("Taking snapshot {}/{}", repo, name);
+    let resp = http_request(target, "PUT", &path, "application/json", &body, auth)?;
+    if resp.status != 200 {
+        return Err(format!(
+            "cannot snapshot {} to {}: {}",
+            index, repo, resp.body
+        ));
+    }
+    // {"snapshot": {"state": "SUCCESS", ...}}
+    let state = json::parse(&resp.body)?
+        .get("snapshot")
+        .and_then(|s| s.get("state"))
+        .and_then(Value::as_str)
+        .map(String::from);
+    match state.as_deref() {
+        Some("SUCCESS") => Ok(()),
+        state => Err(format!(
+            "snapshot {}/{} of {} ended {}, nothing deleted",
+            repo,
+            name,
+            index,
+            state.unwrap_or("without a state")
+        )),
+    }
+}
+
+/// Delete `index` if it exists and create it with `body`
+fn es_recreate_index(
+    target: &HttpTarget,
+    index: &str,
+    body: &str,
+    auth: Option<&Auth>,
+) -> Result<(), String> {
+    es_delete_index(target, index, auth)?;
+    es_create_index(target, index, body, auth)
+}
+
+/// Delete `index` if it's there
+fn es_delete_index(target: &HttpTarget, index: &str, auth: Option<&Auth>) -> Result<(), String> {
+    let path = format!("{}/{}", target.base_path, index);
+    let resp = http_request(target, "DELETE", &path, "application/json", "", auth)?;
+    if resp.status != 200 && resp.status != 404 {
+        return Err(format!("cannot delete index {}: {}", index, resp.body));
+    }
+    Ok(())
+}
+
+/// Delete documents by _index and _id, in bulk requests of up to 1000
+fn es_delete_docs(
+    target: &HttpTarget,
+    docs: &[(String, String)],
+    auth: Option<&Auth>,
+) -> Result<(), String> {
+    let path = format!("{}/_bulk?refresh=true", target.base_path);
+    for chunk in docs.chunks(1000) {
+        let mut body = String::new();
+        for (index, id) in chunk {
+            body.push_str(&format!(
+                "{{\"delete\":{{\"_index\":\"{}\",\"_id\":\"{}\"}}}}\n",
+                json_escape(index),
+                json_escape(id)
+            ));
+        }
+        let resp = http_post_bulk(target, &path, &body, auth)?;
+        if resp.status != 200 {
+            return Err(format!(
+                "cannot delete documents: HTTP {}: {}",
+                resp.status, resp.body
+            ));
+        }
+        if let Some((_, reason)) = parse_bulk_items(&resp.body)?.failed.first() {
+            return Err(format!("cannot delete documents: {}", reason));
+        }
+    }
+    Ok(())
+}
+
+/// Create `index` with `body`, its settings and mappings
+fn es_create_index(
+    target: &HttpTarget,
+    index: &str,
+    body: &str,
+    auth: Option<&Auth>,
+) -> Result<(), String> {
+    let path = format!("{}/{}", target.base_path, index);
+    let resp = http_request(target, "PUT", &path, "application/json", body, auth)?;
+    if resp.status != 200 {
+        return Err(format!("cannot create index {}: {}", index, resp.body));
+    }
+    Ok(())
+}
+
+/// Delete the documents of `index` matching `query`, a `_delete_by_query`
+/// body, and return how many there were
+fn es_delete_by_query(
+    target: &HttpTarget,
+    index: &str,
+    query: &str,
+    auth: Option<&Auth>,
+) -> Result<u64, String> {
+    let path = format!(
+        "{}/{}/_delete_by_query?conflicts=proceed&refresh=true",
+        target.base_path, index
+    );
+    let resp = http_request(target, "POST", &path, "application/json", query, auth)?;
+    match resp.status {
+        200 => {}
+        // nothing to delete yet
+        404 => return Ok(0),
+        _ => return Err(format!("cannot delete from index {}: {}", index, resp.body)),
+    }
+    let body = json::parse(&resp.body)?;
+    if let Some(failures) = body.get("failures").and_then(Value::as_array)
+        && !failures.is_empty()
+    {
+        return Err(format!(
+            "deleting from index {} failed for {} documents: {}",
+            index,
+            failures.len(),
+            failures[0]
+        ));
+    }
+    Ok(body.get("deleted").and_then(Value::as_u64).unwrap_or(0))
+}
+
+/// Number of documents in `index` matching the search body `query`
+fn es_count(
+    target: &HttpTarget,
+    index: &str,
+    query: &str,
+    auth: Option<&Auth>,
+) -> Result<u64, String> {
+    let path = format!("{}/{}/_count", target.base_path, index);
+    let resp = http_request(target, "POST", &path, "application/json", query, auth)?;
+    if resp.status != 200 {
+        return Err(format!(
+            "cannot count documents in {}: {}",
+            index, resp.body
+        ));
+    }
+    json::parse(&resp.body)?
+        .get("count")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| format!("bad _count response: {}", resp.body))
+}
+
+/// Documents in `index`, and about how many distinct values each of
+/// `fields` has there, from one cardinality aggregation per field
+fn es_cardinalities(
+    target: &HttpTarget,
+    index: &str,
+    fields: &[String],
+    auth: Option<&Auth>,
+) -> Result<(u64, HashMap<String, u64>), String> {
+    let aggs: Vec<String> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            format!(
+                "\"f{}\":{{\"cardinality\":{{\"field\":\"{}\"}}}}",
+                i,
+                json_escape(f)
+            )
+        })
+        .collect();
+    let body = format!(
+        "{{\"size\":0,\"track_total_hits\":true,\"aggs\":{{{}}}}}",
+        aggs.join(",")
+    );
+    let path = format!("{}/{}/_search", target.base_path, index);
+    let resp = http_request(target, "POST", &path, "application/json", &body, auth)?;
+    if resp.status != 200 {
+        return Err(format!(
+            "cannot get field statistics of {}: {}",
+            index, resp.body
+        ));
+    }
+    let v = json::parse(&resp.body)?;
+    let documents = v
+        .get("hits")
+        .and_then(|h| h.get("total"))
+        .and_then(|t| t.get("value"))
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let aggregations = v.get("aggregations");
+    let distinct = fields
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| {
+            let agg = aggregations?.get(&format!("f{}", i))?;
+            Some((f.clone(), agg.get("value")?.as_u64()?))
+        })
+        .collect();
+    Ok((documents, distinct))
+}
+
+/// Which items of `batch` are already in the index of their action, by
+/// _id; items without one are taken to be new
+fn es_existing(
+    target: &HttpTarget,
+    batch: &Batch,
+    auth: Option<&Auth>,
+) -> Result<Vec<bool>, String> {
+    let mut docs = Vec::new();
+    let mut asked = Vec::new();
+    for i in 0..batch.len() {
+        let action = batch.item(i).lines().next().unwrap_or("");
+        // {"index":{"_index":"x","_id":"y"}}
+        let meta = json::parse(action)?
+            .as_object()
+            .and_then(|f| f.first())
+            .map(|(_, meta)| meta.clone())
+            .unwrap_or(Value::Null);
+        if let (Some(index), Some(id)) = (
+            meta.get("_index").and_then(Value::as_str),
+            meta.get("_id").and_then(Value::as_str),
+        ) {
+            docs.push(format!(
+                "{{\"_index\":\"{}\",\"_id\":\"{}\"}}",
+                json_escape(index),
+                json_escape(id)
+            ));
+            asked.push(i);
+        }
+    }
+    let mut existing = vec![false; batch.len()];
+    if docs.is_empty() {
+        return Ok(existing);
+    }
+    let path = format!("{}/_mget?_source=false", target.base_path);
+    let body = format!("{{\"docs\":[{}]}}", docs.join(","));
+    let resp = http_request(target, "POST", &path, "application/json", &body, auth)?;
+    match resp.status {
+        200 => {}
+        // nothing can be there yet
+        404 => return Ok(existing),
+        _ => {
+            return Err(format!(
+                "cannot look up imported documents: HTTP {}: {}",
+                resp.status, resp.body
+            ));
+        }
+    }
+    let found = json::parse(&resp.body)?
+        .get("docs")
+        .and_then(Value::as_array)
+        .map(|docs| {
+            docs.iter()
+                .map(|d| d.get("found").and_then(Value::as_bool) == Some(true))
+                .collect::<Vec<_>>()
+        })
+        .ok_or_else(|| format!("bad _mget response: {}", resp.body))?;
+    for (&i, found) in asked.iter().zip(found) {
+        existing[i] = found;
+    }
+    Ok(existing)
+}
+
+/// `_cat/indices` rows of the indices `index` names, sizes in bytes
+fn es_cat_indices(
+    target: &HttpTarget,
+    index: &str,
+    auth: Option<&Auth>,
+) -> Result<Vec<Value>, String> {
+    let path = format!(
+        "{}/_cat/indices/{}?format=json&bytes=b",
+        target.base_path, index
+    );
+    let resp = http_request(target, "GET", &path, "application/json", "", auth)?;
+    match resp.status {
+        200 => {}
+        404 => return Err(format!("index {} not found", index)),
+        _ => return Err(format!("cannot get stats of {}: {}", index, resp.body)),
+    }
+    match json::parse(&resp.body)? {
+        Value::Array(rows) => Ok(rows),
+        other => Err(format!("bad _cat/indices response: {}", other)),
+    }
+}
+
+/// Ask a yes/no question on the terminal; without one the answer is no
+fn confirm(question: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+    eprint!("{} [y/N] ", question);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Poll _cluster/health until the cluster is at least `status`, through
+/// connection errors while it comes up, for up to `timeout`
+fn es_wait_for_status(
+    target: &HttpTarget,
+    status: ClusterStatus,
+    timeout: Duration,
+    auth: Option<&Auth>,
+) -> Result<(), String> {
+    let deadline = Instant::now()
+        .checked_add(timeout)
+        .ok_or("--wait-timeout too large")?;
+    // the status or error last reported
+    let mut last = String::new();
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        // ES holds the request for up to this long itself
+        let wait = left.min(Duration::from_secs(30)).as_secs().max(1);
+        let path = format!(
+            "{}/_cluster/health?wait_for_status={}&timeout={}s",
+            target.base_path,
+            status.as_str(),
+            wait
+        );
+        let started = Instant::now();
+        let seen = match http_request(target, "GET", &path, "application/json", "", auth) {
+            Ok(resp) => {
+                let health = json::parse(&resp.body).ok();
+                let field = |k: &str| health.as_ref().and_then(|h| h.get(k).cloned());
+                if resp.status == 200 && field("timed_out").and_then(|t| t.as_bool()) == Some(false)
+                {
+                    return Ok(());
+                }
+                field("status")
+                    .and_then(|s| s.as_str().map(String::from))
+                    .unwrap_or_else(|| format!("HTTP {}", resp.status))
+            }
+            Err(e) => e,
+        };
+        if seen != last {
+            log!("Waiting for cluster status {} ({})", status.as_str(), seen);
+            last = seen;
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "cluster not {} after {}s: {}",
+                status.as_str(),
+                timeout.as_secs(),
+                last
+            ));
+        }
+        // not held by ES, e.g. refused while it starts
+        if started.elapsed() < Duration::from_secs(1) {
+            thread::sleep(left.min(Duration::from_secs(5)));
+        }
+    }
+}
+
+/// Ping ES
+fn es_ping(target: &HttpTarget, auth: Option<&Auth>) -> Result<(), String> {
+    let path = if target.base_path.is_empty() {
+        "/"
+    } else {
+        &target.base_path
+    };
+    let resp = http_request(target, "GET", path, "application/json", "", auth)?;
+    if resp.status != 200 {
+        return Err(format!("HTTP {}", resp.status));
+    }
+    Ok(())
+}
+
+/// The input file opened, with a type for each column
+struct Opened {
+    csv: CsvIter,
+    types: Vec<Option<ColumnType>>,
+    /// Columns with a declared type, checked row by row with --strict
+    declared: Vec<(usize, ColumnType)>,
+    #[cfg(feature = "plugins")]
+    plugin: Option<plugin::Plugin>,
+}
+
+/// Open the input file, or read `documents` in its place, and decide the
+/// column types from a sample and the declarations
+fn open_input(
+    input: &InputOptions,
+    documents: Option<Vec<u8>>,
+) -> Result<Opened, Box<dyn std::error::Error>> {
+    #[cfg(feature = "plugins")]
+    let mut plugin = input
+        .plugin
+        .as_deref()
+        .map(plugin::Plugin::load)
+        .transpose()?;
+    #[cfg(not(feature = "plugins"))]
+    if input.plugin.is_some() {
+        return Err("--plugin needs a build with the plugins feature".into());
+    }
+
+    let mut csv = if let Some(documents) = documents {
+        CsvIter::from_input(Input::Owned(documents), input.csv.clone())?
+    } else if input.file == "-" {
+        CsvIter::from_reader(io::stdin().lock(), input.csv.clone())?
+    } else {
+        let file =
+            File::open(&input.file).map_err(|e| format!("cannot open {}: {}", input.file, e))?;
+        match () {
+            #[cfg(feature = "plugins")]
+            () if let Some(p) = plugin.as_mut().filter(|p| p.is_source()) => {
+                CsvIter::from_plugin(p, BufReader::new(file), input.csv.clone())?
+            }
+            () if input.mmap => CsvIter::from_mmap(&file, input.csv.clone())?,
+            () => CsvIter::from_reader(BufReader::new(file), input.csv.clone())?,
+        }
+    };
+    let mut types = if input.infer_sample > 0 {
+        let sample = csv.sample(input.infer_sample, input.infer_random);
+        schema::infer(sample.iter().map(Vec::as_slice), csv.headers.len())
+    } else {
+        vec![None; csv.headers.len()]
+    };
+    if let RecordFormat::Ndjson { types: native, .. } = &csv.rdr.format {
+        for (ty, native) in types.iter_mut().zip(native) {
+            if native.is_some() {
+                *ty = *native;
+            }
+        }
+    }
+    let mut declared: Vec<(usize, ColumnType)> = Vec::new();
+    let mut declarations = Vec::new();
+    if let Some(path) = &input.schema {
+        declarations.extend(
+            schema::load(path)?
+                .into_iter()
+                .map(|(col, ty)| (format!("schema {}", path), col, ty)),
+        );
+    }
+    declarations.extend(
+        input
+            .types
+            .iter()
+            .map(|(col, ty)| ("--type".to_string(), col.clone(), *ty)),
+    );
+    for (source, col, ty) in declarations {
+        let i = csv
+            .headers
+            .iter()
+            .position(|h| *h == col)
+            .ok_or_else(|| format!("{}: no column named {}", source, col))?;
+        types[i] = Some(ty);
+        declared.retain(|&(j, _)| j != i);
+        declared.push((i, ty));
+    }
+    Ok(Opened {
+        csv,
+        types,
+        declared,
+        #[cfg(feature = "plugins")]
+        plugin,
+    })
+}
+
+fn import(mut args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let _ = RUN_ID.set(args.run_id.clone());
+    let target = args.connection.target()?;
+    // --bench, --preview and --output never talk to ES
+    let offline = args.bench || args.preview.is_some() || args.output.is_some();
+    // --format bulk items are sent as they are
+    let passthrough = matches!(args.input.csv.format, RecordFormat::Bulk);
+    if passthrough {
+        let shaping = [
+            ("--id-column and --id-template", args.id_template.is_some()),
+            ("--id-hash", args.id_hash.is_some()),
+            ("--group-by", args.group_by.is_some()),
+            ("--merge-by", args.merge_by.is_some()),
+            ("--attach-column", args.attach_column.is_some()),
+            ("--timestamp-field", args.timestamp_field.is_some()),
+            ("--run-id-field", args.run_id_field.is_some()),
+            ("--dedupe-by", !args.dedupe_by.is_empty()),
+            ("--es-lookup", args.es_lookup.is_some()),
+            ("--truncate-field", !args.input.csv.truncate.is_empty()),
+            (
+                "--tz-column and --input-tz",
+                !args.input.csv.tz_columns.is_empty() || args.input.csv.input_tz.is_some(),
+            ),
+            ("--unflatten", args.unflatten),
+            ("--script", args.script.is_some()),
+            ("--plugin", args.input.plugin.is_some()),
+        ];
+        if let Some((option, _)) = shaping.iter().find(|(_, given)| *given) {
+            return Err(format!(
+                "{} can't be used with --format bulk, which sends documents as they are",
+                option
+            )
+            .into());
+        }
+    }
+    #[cfg(not(feature = "tui"))]
+    if args.tui {
+        return Err("--tui needs a build with the tui feature".into());
+    }
+    if args.tui && !io::stdout().is_terminal() {
+        return Err("--tui needs a terminal".into());
+    }
+    let (documents, source_ids) = match args.source.take() {
+        Some(source) => (Some(source.ndjson), source.ids),
+        None => (None, Vec::new()),
+    };
+
+    if documents.is_none() && args.input.file != "-" && !Path::new(&args.input.file).exists() {
+        return Err(format!("CSV file not found: {}", args.input.file).into());
+    }
+
+    let auth = if offline {
+        None
+    } else {
+        args.connection.auth()?
+    };
+
+    let load_hooks = |specs: &[String]| {
+        specs
+            .iter()
+            .map(|s| hook::Hook::load(s))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let pre_hooks = load_hooks(&args.pre_hooks)?;
+    let post_hooks = load_hooks(&args.post_hooks)?;
+
+    if !offline && let Some((status, timeout)) = args.wait_for_status {
+        es_wait_for_status(&target, status, timeout, auth.as_ref())?;
+    }
+    if !offline && let Err(e) = es_ping(&target, auth.as_ref()) {
+        return Err(format!("Cannot connect to ES at {}: {}", args.connection.host, e).into());
+    }
+    let run_hooks = |stage: &str, hooks: &[hook::Hook]| {
+        hook::run_all(
+            stage,
+            hooks,
+            args.on_hook_error,
+            &target,
+            auth.as_ref(),
+            &args.connection.host,
+            &args.index_name,
+        )
+    };
+    run_hooks("--pre-hook", &pre_hooks)?;
+    if !offline {
+        install_templates(
+            &target,
+            &args.component_templates,
+            args.index_template.as_ref(),
+            auth.as_ref(),
+        )?;
+    }
+
+    let started = Instant::now();
+    let bench_bytes = AtomicU64::new(0);
+
+    let Opened {
+        mut csv,
+        types,
+        declared,
+        #[cfg(feature = "plugins")]
+        plugin,
+    } = open_input(&args.input, documents)?;
+    let mut column_stats: Vec<ColumnStats> = csv
+        .headers
+        .iter()
+        .zip(&types)
+        .map(|(name, &ty)| ColumnStats::new(name, ty))
+        .collect();
+    let mut grouping = match &args.group_by {
+        Some(key) => Some(Grouping::new(key, &args.nests, &csv.headers, &types)?),
+        None => None,
+    };
+    let mut merging = match &args.merge_by {
+        Some(key) => Some(Merging::new(
+            key,
+            args.merge_strategy,
+            &csv.headers,
+            &types,
+        )?),
+        None => None,
+    };
+    // documents have the fields of a group rather than those of a row
+    let (mut headers, mut types) = match (&grouping, &merging) {
+        (Some(g), _) => (g.headers(&csv.headers), g.types(&types)),
+        (None, Some(m)) => (csv.headers.clone(), m.types()),
+        (None, None) => (csv.headers.clone(), types),
+    };
+    let attach = match &args.attach_column {
+        Some(col) => {
+            let i = headers
+                .iter()
+                .position(|h| h == col)
+                .ok_or_else(|| format!("--attach-column: no column named {}", col))?;
+            if headers.iter().any(|h| h == ATTACHMENT_FIELD) {
+                return Err(format!(
+                    "--attach-column: the input already has a {} column",
+                    ATTACHMENT_FIELD
+                )
+                .into());
+            }
+            headers.push(ATTACHMENT_FIELD.to_string());
+            types.push(Some(ColumnType::Keyword));
+            // relative paths are from the input file's directory
+            let dir = Path::new(&args.input.file)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            Some((i, dir))
+        }
+        None => None,
+    };
+    let id_generator = match (&args.id_template, args.id_hash) {
+        (Some(t), _) => Some(IdGenerator::Template(IdTemplate::new(t, &headers)?)),
+        (None, Some(algorithm)) => Some(IdGenerator::Hash(IdHash::new(
+            algorithm,
+            &args.id_hash_columns,
+            &headers,
+        )?)),
+        (None, None) => None,
+    };
+    let index_template = IndexTemplate::new(&args.index_name, &headers, Utc::now())?;
+    let mut rejected = 0;
+    let mut sampler = args.sample.map(|s| Sampler::new(s, &mut csv));
+    let mut unsampled = 0;
+    let dedupe_columns = args
+        .dedupe_by
+        .iter()
+        .map(|col| {
+            csv.headers
+                .iter()
+                .position(|h| h == col)
+                .ok_or_else(|| format!("--dedupe-by: no column named {}", col))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut dedupe =
+        (!dedupe_columns.is_empty()).then(|| Dedupe::new(dedupe_columns, args.keep, &mut csv));
+    let mut deduped = 0;
+    let mut oversized = 0;
+    // fields added to every document
+    let stamps: Vec<(&str, ColumnType)> = [
+        (&args.timestamp_field, ColumnType::Date),
+        (&args.run_id_field, ColumnType::Keyword),
+    ]
+    .into_iter()
+    .filter_map(|(field, ty)| Some((field.as_deref()?, ty)))
+    .collect();
+    if let Some(field) = &args.timestamp_field
+        && headers.contains(field)
+    {
+        return Err(format!("--timestamp-field {} collides with a CSV column", field).into());
+    }
+    if let Some(field) = &args.run_id_field
+        && (headers.contains(field) || args.timestamp_field.as_ref() == Some(field))
+    {
+        return Err(format!("--run-id-field {} collides with another field", field).into());
+    }
+    #[cfg(any(feature = "script", feature = "plugins"))]
+    let mut transforms = Transforms {
+        #[cfg(feature = "script")]
+        script: args
+            .script
+            .as_deref()
+            .map(script::Script::load)
+            .transpose()?,
+        #[cfg(feature = "plugins")]
+        plugin,
+    };
+    #[cfg(any(feature = "script", feature = "plugins"))]
+    let mut dropped = 0;
+    #[cfg(not(feature = "script"))]
+    if args.script.is_some() {
+        return Err("--script needs a build with the script feature".into());
+    }
+    let nesting = if args.unflatten {
+        let mut names = headers.clone();
+        names.extend(stamps.iter().map(|&(field, _)| field.to_string()));
+        Some(Nesting::new(&names)?)
+    } else {
+        None
+    };
+
+    if args.forcemerge.is_some() && !index_template.is_fixed() {
+        return Err("--forcemerge needs a fixed index name".into());
+    }
+    if args.alias.is_some() && !index_template.is_fixed() {
+        return Err("--alias needs a fixed index name".into());
+    }
+    if args.rollover_after.is_some() && !index_template.is_fixed() {
+        return Err("--rollover-after needs a fixed write alias as the index name".into());
+    }
+    if (args.settings.is_some() || args.mapping.is_some()) && !index_template.is_fixed() {
+        return Err("--settings and --mapping need a fixed index name".into());
+    }
+    let settings = match &args.settings {
+        Some(path) => Some(index_part(path, "--settings", "settings")?),
+        None => None,
+    };
+    let columns = || {
+        headers
+            .iter()
+            .map(String::as_str)
+            .zip(types.iter().copied())
+            .chain(stamps.iter().map(|&(field, ty)| (field, Some(ty))))
+            // --format bulk documents aren't made of columns
+            .filter(|_| !passthrough)
+    };
+    let mapping = match &args.mapping {
+        Some(path) => {
+            let mapping = index_part(path, "--mapping", "mappings")?;
+            let index = index_template.render(&[])?;
+            // checked as the mapping of an index would be
+            let as_index = Value::Object(vec![(
+                index,
+                Value::Object(vec![("mappings".to_string(), json::parse(&mapping)?)]),
+            )]);
+            let conflicts = schema::mapping_conflicts(&as_index, columns());
+            for c in &conflicts {
+                log!("Mapping conflict: {}", c);
+            }
+            if args.strict_mapping && !conflicts.is_empty() {
+                return Err(format!(
+                    "{} conflicts with --mapping {} (--strict-mapping)",
+                    conflicts.len(),
+                    path
+                )
+                .into());
+            }
+            Some(mapping)
+        }
+        None => None,
+    };
+    // the mapping of the column types unless --mapping has one, and the
+    // --settings
+    let creation_body = || {
+        let mapping = match &mapping {
+            Some(mapping) => format!("{{\"mappings\":{}}}", mapping),
+            None => schema::mapping(columns()),
+        };
+        match &settings {
+            Some(settings) => format!("{{\"settings\":{},{}", settings, &mapping[1..]),
+            None => mapping,
+        }
+    };
+    // --rollback-on-failure deletes the index if it's this run's own, else
+    // only the documents created
+    let rollback_index = if args.rollback_on_failure && index_template.is_fixed() {
+        let index = index_template.render(&[])?;
+        (args.recreate || !es_index_exists(&target, &index, auth.as_ref())?).then_some(index)
+    } else {
+        None
+    };
+    if args.recreate {
+        if !index_template.is_fixed() {
+            return Err("--recreate needs a fixed index name".into());
+        }
+        let index = index_template.render(&[])?;
+        if !args.yes && !confirm(&format!("Delete index {} and all its documents?", index)) {
+            return Err(
+                format!("Index {} left as it is (pass --yes to recreate it)", index).into(),
+            );
+        }
+        if let Some(repo) = &args.snapshot_repo
+            && es_index_exists(&target, &index, auth.as_ref())?
+        {
+            es_snapshot(&target, repo, &index, auth.as_ref())?;
+        }
+        es_recreate_index(&target, &index, &creation_body(), auth.as_ref())?;
+        log!("Recreated index {}", index);
+    }
+    // a new index created for --settings or --mapping isn't one that was
+    // there before
+    let mut created = false;
+    if (settings.is_some() || mapping.is_some()) && !args.recreate {
+        let index = index_template.render(&[])?;
+        if !es_index_exists(&target, &index, auth.as_ref())? {
+            es_create_index(&target, &index, &creation_body(), auth.as_ref())?;
+            log!("Created index {}", index);
+            created = true;
+        }
+    }
+    if args.truncate {
+        if !index_template.is_fixed() {
+            return Err("--truncate needs a fixed index name".into());
+        }
+        let index = index_template.render(&[])?;
+        let query = match &args.truncate_query {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| format!("cannot read --truncate-query {}: {}", path, e))?;
+                json::parse(&text).map_err(|e| format!("bad --truncate-query {}: {}", path, e))?;
+                text
+            }
+            None => "{\"query\":{\"match_all\":{}}}".to_string(),
+        };
+        let what = if args.truncate_query.is_some() {
+            "matching documents"
+        } else {
+            "all documents"
+        };
+        if !args.yes && !confirm(&format!("Delete {} in index {}?", what, index)) {
+            return Err(
+                format!("Index {} left as it is (pass --yes to truncate it)", index).into(),
+            );
+        }
+        if let Some(repo) = &args.snapshot_repo {
+            es_snapshot(&target, repo, &index, auth.as_ref())?;
+        }
+        let deleted = es_delete_by_query(&target, &index, &query, auth.as_ref())?;
+        log!("Deleted {} documents from index {}", deleted, index);
+    }
+
+    let mut bulk_params = Vec::new();
+    if let Some(n) = &args.wait_for_active_shards {
+        bulk_params.push(format!("wait_for_active_shards={}", n));
+    }
+    if let Some(t) = &args.bulk_timeout {
+        bulk_params.push(format!("timeout={}", t));
+    }
+    if args.attach_column.is_some() {
+        if !offline && es_ensure_attachment_pipeline(&target, &args.attach_pipeline, auth.as_ref())?
+        {
+            log!("Created ingest pipeline {}", args.attach_pipeline);
+        }
+        bulk_params.push(format!("pipeline={}", args.attach_pipeline));
+    }
+    let mut bulk_path = format!("{}/_bulk", target.base_path);
+    if !bulk_params.is_empty() {
+        bulk_path.push('?');
+        bulk_path.push_str(&bulk_params.join("&"));
+    }
+    let mut total_docs = 0;
+    let mut failed_docs = 0;
+    let mut skipped_docs = 0;
+
+    let mut checkpoint = match &args.checkpoint {
+        Some(path) => Some(Checkpoint::load(path, &args.input.file)?),
+        None => None,
+    };
+    let resume_after = checkpoint.as_ref().map_or(0, |c| c.rows);
+    if resume_after > 0 {
+        log!("Resuming after row {} from checkpoint", resume_after);
+    }
+    // --recreate and --truncate have already dealt with what was there, a
+    // resumed import wrote it and a rollover alias is meant to be added to
+    if args.on_existing_index != ExistingIndexPolicy::Append
+        && !(offline || args.recreate || created || args.truncate || args.resume_from_index)
+        && resume_after == 0
+        && args.rollover_after.is_none()
+        && index_template.is_fixed()
+    {
+        let index = index_template.render(&[])?;
+        if es_index_exists(&target, &index, auth.as_ref())? {
+            let go_on = args.on_existing_index == ExistingIndexPolicy::Prompt
+                && confirm(&format!(
+                    "Index {} already exists. Add the documents to it?",
+                    index
+                ));
+            if !go_on {
+                return Err(format!(
+                    "Index {} already exists (pass --append to add to it)",
+                    index
+                )
+                .into());
+            }
+        }
+    }
+    // a recreated index has the mapping of these very types
+    if !(offline || args.recreate || created) && index_template.is_fixed() {
+        let index = index_template.render(&[])?;
+        if let Some(mapping) = es_mapping(&target, &index, auth.as_ref())? {
+            let conflicts = schema::mapping_conflicts(&mapping, columns());
+            for c in &conflicts {
+                log!("Mapping conflict: {}", c);
+            }
+            if args.strict_mapping && !conflicts.is_empty() {
+                return Err(format!(
+                    "{} mapping conflicts with index {} (--strict-mapping)",
+                    conflicts.len(),
+                    index
+                )
+                .into());
+            }
+        }
+    }
+    // put back when these go out of scope, however the import ends
+    let mut tuned = Vec::new();
+    for (on, name, value) in [
+        (args.tune_replicas, "index.number_of_replicas", "0"),
+        (args.tune_refresh, "index.refresh_interval", "-1"),
+    ] {
+        if !on {
+            continue;
+        }
+        if !index_template.is_fixed() {
+            return Err("--tune-replicas and --tune-refresh need a fixed index name".into());
+        }
+        let index = index_template.render(&[])?;
+        if let Some(mut setting) = TunedSetting::new(&target, &index, name, value, auth.as_ref())? {
+            setting.refresh = name == "index.refresh_interval";
+            tuned.push(setting);
+        }
+    }
+    // rows before this one are safely in ES
+    let mut rows_done = resume_after;
+
+    let progress = dashboard::Progress::new(args.concurrency, csv.rdr.buf.len());
+    let stop = watch_signals()?;
+    // Rows are sent in order, so once a batch has none of its documents in
+    // the index the rest are new too. Should one not be after all, sending
+    // it again only overwrites it under the same _id.
+    let caught_up = AtomicBool::new(!args.resume_from_index);
+    let capture = args.capture_dir.as_deref().map(Capture::new).transpose()?;
+    let sizer = args
+        .adaptive_target
+        .map(|target| BatchSizer::new(args.batch_size, target));
+    let throttle = Throttle::new(args.concurrency);
+    let created_ids = Mutex::new(Vec::new());
+    let policy = BulkPolicy {
+        max_retries: args.max_retries,
+        slow: args.slow_bulk.map(Duration::from_millis),
+        capture: capture.as_ref(),
+        sizer: sizer.as_ref(),
+        throttle: &throttle,
+        created: (args.rollback_on_failure && rollback_index.is_none()).then_some(&created_ids),
+    };
+
+    let output = match &args.output {
+        Some(path) if path == "-" => Some(Box::new(io::stdout()) as Box<dyn Write + Send>),
+        Some(path) => Some(Box::new(
+            File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?,
+        ) as Box<dyn Write + Send>),
+        None => None,
+    }
+    .map(|out| Mutex::new(io::BufWriter::new(out)));
+    let mut dead_letter = match &args.dead_letter {
+        Some(path) => Some((
+            path,
+            io::BufWriter::new(
+                File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?,
+            ),
+        )),
+        None => None,
+    };
+
+    // Retries a batch while the cluster is unavailable, pausing longer each
+    // time, until `max_failures` consecutive attempts have failed
+    let send_batch = |worker: usize, batch: &Batch| -> Result<BatchStats, String> {
+        if let Some(out) = &output {
+            out.lock()
+                .unwrap()
+                .write_all(batch.body.as_bytes())
+                .map_err(|e| format!("cannot write the bulk requests: {}", e))?;
+            return Ok(BatchStats {
+                indexed: batch.len(),
+                ..BatchStats::default()
+            });
+        }
+        if args.preview.is_some() {
+            print!("{}", batch.body);
+            return Ok(BatchStats {
+                indexed: batch.len(),
+                ..BatchStats::default()
+            });
+        }
+        if args.bench {
+            bench_bytes.fetch_add(batch.body.len() as u64, Ordering::Relaxed);
+            return Ok(BatchStats {
+                indexed: batch.len(),
+                ..BatchStats::default()
+            });
+        }
+        let mut skipped = 0;
+        let remaining;
+        let batch = if caught_up.load(Ordering::Relaxed) {
+            batch
+        } else {
+            let existing = es_existing(&target, batch, auth.as_ref())?;
+            skipped = existing.iter().filter(|&&e| e).count();
+            if skipped == 0 {
+                caught_up.store(true, Ordering::Relaxed);
+                batch
+            } else {
+                let mut rest = Batch::default();
+                for (i, _) in existing.iter().enumerate().filter(|&(_, &e)| !e) {
+                    rest.push_item(batch.item(i));
+                }
+                remaining = rest;
+                &remaining
+            }
+        };
+        if batch.is_empty() {
+            return Ok(BatchStats {
+                skipped,
+                ..BatchStats::default()
+            });
+        }
+        let enriched;
+        let batch = match &args.es_lookup {
+            Some(lookup) => {
+                enriched = lookup.enrich(&target, batch, auth.as_ref())?;
+                &enriched
+            }
+            None => batch,
+        };
+        let mut failures = 0;
+        // documents already indexed aren't sent again, which would index
+        // those without an _id twice
+        let mut from = Resume::start(batch);
+        let mut stats = loop {
+            match send_bulk(
+                &target,
+                &bulk_path,
+                batch,
+                from,
+                auth.as_ref(),
+                &policy,
+                &progress,
+            ) {
+                Ok(stats) => break stats,
+                Err(BulkError::Fatal(e)) => return Err(e),
+                Err(BulkError::Unavailable(e, resume)) => {
+                    from = resume;
+                    failures += 1;
+                    if failures >= args.max_failures {
+                        return Err(format!(
+                            "giving up after {} consecutive failures: {}",
+                            failures, e
+                        ));
+                    }
+                    let pause =
+                        Duration::from_secs(1u64.checked_shl(failures - 1).unwrap_or(u64::MAX))
+                            .min(Duration::from_secs(60));
+                    progress.message(format!(
+                        "{} (failure {}/{}), pausing {:?}",
+                        e, failures, args.max_failures, pause
+                    ));
+                    let until = Instant::now() + pause;
+                    progress.worker(worker, dashboard::Worker::Pausing { until });
+                    while let Some(left) = until.checked_duration_since(Instant::now()) {
+                        if stop.load(Ordering::Relaxed) {
+                            return Err("interrupted".to_string());
+                        }
+                        thread::sleep(left.min(Duration::from_millis(100)));
+                    }
+                }
+            }
+        };
+        if let Some(conditions) = &args.rollover_after
+            && let Some(new_index) =
+                es_rollover(&target, &args.index_name, conditions, auth.as_ref())?
+        {
+            progress.message(format!("Rolled over {} to {}", args.index_name, new_index));
+        }
+        stats.skipped = skipped;
+        Ok(stats)
+    };
+
+    // The main thread reads and serializes rows while worker threads send
+    // the finished batches. Each job carries its sequence number and the
+    // input row it ends at, so the checkpoint only advances past batches
+    // whose predecessors are done too.
+    let (job_tx, job_rx) = mpsc::sync_channel::<(usize, usize, Batch)>(args.concurrency);
+    let job_rx = Mutex::new(job_rx);
+    let (result_tx, result_rx) = mpsc::channel();
+    // sent batches come back to be refilled
+    let (spare_tx, spare_rx) = mpsc::channel::<Batch>();
+    let abort = AtomicBool::new(false);
+    // set once everything is sent, to take the dashboard down
+    let all_sent = AtomicBool::new(false);
+    // reading stopped for a signal; the batches already made are still sent
+    let mut interrupted = false;
+
+    let mut error: Option<String> = None;
+    let mut finished: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut next_seq = 0;
+    let mut record = |(seq, rows_end, result): (usize, usize, Result<BatchStats, String>)| {
+        match result {
+            Ok(stats) => {
+                progress.sent(stats.indexed, stats.failed);
+                total_docs += stats.indexed;
+                failed_docs += stats.failed;
+                skipped_docs += stats.skipped;
+                finished.insert(seq, rows_end);
+            }
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+        let mut advanced = false;
+        while let Some(rows_end) = finished.remove(&next_seq) {
+            rows_done = rows_end;
+            next_seq += 1;
+            advanced = true;
+        }
+        match &mut checkpoint {
+            Some(c) if advanced => {
+                c.rows = rows_done;
+                c.save()
+            }
+            _ => Ok(()),
+        }
+    };
+
+    thread::scope(|s| -> Result<(), Box<dyn std::error::Error>> {
+        #[cfg(feature = "tui")]
+        if args.tui {
+            let title = format!("{} -> {}", args.input.file, args.index_name);
+            let (progress, all_sent, stop) = (&progress, &all_sent, &stop);
+            s.spawn(move || dashboard::run(progress, all_sent, stop, &title));
+        }
+        for worker in 0..args.concurrency {
+            let (job_rx, result_tx, spare_tx) = (&job_rx, result_tx.clone(), spare_tx.clone());
+            let (abort, send_batch, progress) = (&abort, &send_batch, &progress);
+            s.spawn(move || {
+                loop {
+                    let job = job_rx.lock().unwrap().recv();
+                    let Ok((seq, rows_end, mut batch)) = job else {
+                        break;
+                    };
+                    if abort.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    let since = Instant::now();
+                    let docs = batch.len();
+                    progress.worker(worker, dashboard::Worker::Sending { docs, since });
+                    let result = send_batch(worker, &batch);
+                    progress.worker(worker, dashboard::Worker::Idle);
+                    if result.is_err() {
+                        abort.store(true, Ordering::Relaxed);
+                    }
+                    let _ = result_tx.send((seq, rows_end, result));
+                    batch.clear();
+                    let _ = spare_tx.send(batch);
+                }
+            });
+        }
+        drop(result_tx);
+        drop(spare_tx);
+
+        // results still in flight are recorded even if reading fails
+        let read = (|| -> Result<(), Box<dyn std::error::Error>> {
+            let mut batch = Batch::default();
+            let mut rows_read = 0;
+            let mut seq = 0;
+            while !abort.load(Ordering::Relaxed) {
+                if stop.load(Ordering::Relaxed) {
+                    progress.message("Stopping once the batches being sent are done");
+                    interrupted = true;
+                    break;
+                }
+                let next = csv.next();
+                let mut done = next.is_none();
+                let row = match next {
+                    Some(row) => {
+                        let row = row?;
+                        rows_read += 1;
+                        progress.read(rows_read, csv.rdr.idx);
+                        // rows sent before a resume still claim their key
+                        let duplicate = dedupe
+                            .as_mut()
+                            .is_some_and(|d| d.is_duplicate(rows_read - 1, &row));
+                        if rows_read <= resume_after {
+                            continue;
+                        }
+                        if duplicate {
+                            deduped += 1;
+                            continue;
+                        }
+                        if let Some(sampler) = &mut sampler
+                            && !sampler.keep()
+                        {
+                            unsampled += 1;
+                            continue;
+                        }
+                        if args.input.strict
+                            && let Some(&(i, ty)) =
+                                declared.iter().find(|&&(i, ty)| !ty.fits(&row[i].1))
+                        {
+                            progress.message(format!(
+                                "line {}: column {}: {:?} is not a valid {} (skipped)",
+                                csv.rdr.record_line, row[i].0, row[i].1, ty
+                            ));
+                            rejected += 1;
+                            continue;
+                        }
+                        if !passthrough {
+                            for (stats, (_, value)) in column_stats.iter_mut().zip(&row) {
+                                stats.add(value);
+                            }
+                        }
+                        if let Some(m) = &mut merging {
+                            m.push(row);
+                            continue;
+                        }
+                        match &mut grouping {
+                            Some(g) => {
+                                let Some(doc) = g.push(row) else {
+                                    continue;
+                                };
+                                Some(doc)
+                            }
+                            None => Some(row),
+                        }
+                    }
+                    // merged documents go out one by one once the input ends
+                    None if merging.is_some() => {
+                        let doc = merging.as_mut().and_then(Merging::finish);
+                        done = merging.as_ref().is_some_and(|m| m.docs.is_empty());
+                        doc
+                    }
+                    // the last group ends with the input
+                    None => grouping.as_mut().and_then(Grouping::finish),
+                };
+                if let Some(mut row) = row {
+                    if let Some((i, dir)) = &attach {
+                        let data = match row[*i].1.as_str() {
+                            "" => String::new(),
+                            path => {
+                                let file = dir.join(path);
+                                let bytes = std::fs::read(&file).map_err(|e| {
+                                    format!(
+                                        "line {}: cannot read {}: {}",
+                                        csv.rdr.record_line,
+                                        file.display(),
+                                        e
+                                    )
+                                })?;
+                                general_purpose::STANDARD.encode(bytes)
+                            }
+                        };
+                        row.push((ATTACHMENT_FIELD.to_string(), data));
+                    }
+                    let id = match &id_generator {
+                        Some(g) => Some(g.render(&row)),
+                        // reindexed documents keep their _id unless given a new one
+                        None => source_ids.get(rows_read - 1).cloned(),
+                    };
+                    if id.as_deref() == Some("") {
+                        return Err(
+                            format!("line {}: document _id is empty", csv.rdr.record_line).into(),
+                        );
+                    }
+                    let index = index_template
+                        .render(&row)
+                        .map_err(|e| format!("line {}: {}", csv.rdr.record_line, e))?;
+                    let pushed = batch.len();
+                    if let Some(field) = &args.timestamp_field {
+                        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+                        row.push((field.clone(), now));
+                    }
+                    if let Some(field) = &args.run_id_field {
+                        row.push((field.clone(), args.run_id.clone()));
+                    }
+                    #[cfg(any(feature = "script", feature = "plugins"))]
+                    if let Some(docs) = transforms
+                        .apply(&row, &types, nesting.as_deref())
+                        .map_err(|e| format!("line {}: {}", csv.rdr.record_line, e))?
+                    {
+                        if docs.is_empty() {
+                            dropped += 1;
+                        }
+                        // split documents only keep the row's _id if they set one
+                        let row_id = if docs.len() == 1 { id.as_deref() } else { None };
+                        for doc in &docs {
+                            batch.push_json(
+                                doc.index.as_deref().unwrap_or(&index),
+                                doc.id.as_deref().or(row_id),
+                                &doc.json,
+                            );
+                        }
+                    } else if passthrough {
+                        batch.push_bulk(&index, &row[0].1);
+                    } else {
+                        batch.push(&index, id.as_deref(), &row, &types, nesting.as_deref());
+                    }
+                    #[cfg(not(any(feature = "script", feature = "plugins")))]
+                    if passthrough {
+                        batch.push_bulk(&index, &row[0].1);
+                    } else {
+                        batch.push(&index, id.as_deref(), &row, &types, nesting.as_deref());
+                    }
+                    if let Some(max) = args.max_doc_bytes {
+                        for item in batch.remove_over(pushed, max) {
+                            progress.message(format!(
+                                "line {}: document of {} bytes is over --max-doc-bytes (skipped)",
+                                csv.rdr.record_line,
+                                item.len()
+                            ));
+                            oversized += 1;
+                            if let Some((path, out)) = &mut dead_letter {
+                                out.write_all(item.as_bytes())
+                                    .map_err(|e| format!("cannot write {}: {}", path, e))?;
+                            }
+                        }
+                    }
+                    let batch_size = sizer.as_ref().map_or(args.batch_size, BatchSizer::size);
+                    if batch.len() < batch_size {
+                        continue;
+                    }
+                    // the preview is this one batch
+                    done |= args.preview.is_some();
+                }
+
+                if !batch.is_empty() {
+                    let next = spare_rx.try_recv().unwrap_or_default();
+                    // rows of an unfinished group are in a later batch
+                    let rows_end = rows_read
+                        - grouping.as_ref().map_or(0, Grouping::pending)
+                        - merging.as_ref().map_or(0, Merging::pending);
+                    let job = (seq, rows_end, std::mem::replace(&mut batch, next));
+                    if job_tx.send(job).is_err() {
+                        break;
+                    }
+                    seq += 1;
+                }
+                while let Ok(result) = result_rx.try_recv() {
+                    record(result)?;
+                }
+                if done {
+                    break;
+                }
+            }
+            Ok(())
+        })();
+        drop(job_tx);
+        let recorded = result_rx.iter().try_for_each(&mut record);
+        all_sent.store(true, Ordering::Relaxed);
+        recorded?;
+        read
+    })?;
+
+    if error.is_some() || interrupted {
+        log!(
+            "{}: {} documents indexed, first {} rows of {} done",
+            if stop.load(Ordering::Relaxed) {
+                "Interrupted"
+            } else {
+                "Aborted"
+            },
+            total_docs,
+            rows_done,
+            args.input.file
+        );
+        if failed_docs > 0 {
+            log!("{} documents failed to index", failed_docs);
+        }
+        if checkpoint.is_some() {
+            log!("Rerun the same command to resume from the checkpoint");
+        }
+        if args.rollback_on_failure {
+            let rolled_back = match &rollback_index {
+                Some(index) => es_delete_index(&target, index, auth.as_ref())
+                    .map(|()| format!("Rolled back: deleted index {}", index)),
+                None => {
+                    let docs = created_ids.into_inner().unwrap();
+                    es_delete_docs(&target, &docs, auth.as_ref()).map(|()| {
+                        format!("Rolled back: deleted the {} documents created", docs.len())
+                    })
+                }
+            };
+            match rolled_back {
+                Ok(msg) => log!("{}", msg),
+                Err(e) => log!("Rollback failed, clean up by hand: {}", e),
+            }
+        }
+        return Err(error.unwrap_or_else(|| "interrupted".to_string()).into());
+    }
+    if let Some(c) = &checkpoint {
+        // the import is complete, a rerun should start over
+        let _ = std::fs::remove_file(&c.path);
+    }
+
+    if !csv.invalid_utf8_lines.is_empty() {
+        log!(
+            "{} records with invalid UTF-8 skipped on lines: {}",
+            csv.invalid_utf8_lines.len(),
+            csv.invalid_utf8_lines
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    if !csv.ragged_lines.is_empty() {
+        log!(
+            "{} ragged records on lines: {}",
+            csv.ragged_lines.len(),
+            csv.ragged_lines
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    if !csv.rdr.unmatched.is_empty() {
+        log!(
+            "{} unrecognized lines skipped: {}",
+            csv.rdr.unmatched.len(),
+            csv.rdr
+                .unmatched
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    if unsampled > 0 {
+        log!("{} rows left out by --sample", unsampled);
+    }
+
+    if rejected > 0 {
+        log!("{} rows rejected by --strict", rejected);
+    }
+
+    if deduped > 0 {
+        log!("{} duplicate rows removed by --dedupe-by", deduped);
+    }
+
+    if let Some((path, out)) = &mut dead_letter {
+        out.flush()
+            .map_err(|e| format!("cannot write {}: {}", path, e))?;
+    }
+    if oversized > 0 {
+        match &args.dead_letter {
+            Some(path) => log!(
+                "{} documents over --max-doc-bytes skipped, written to {}",
+                oversized,
+                path
+            ),
+            None => log!("{} documents over --max-doc-bytes skipped", oversized),
+        }
+    }
+
+    if skipped_docs > 0 {
+        log!(
+            "{} documents already in the index skipped by --resume-from-index",
+            skipped_docs
+        );
+    }
+
+    #[cfg(any(feature = "script", feature = "plugins"))]
+    if dropped > 0 {
+        log!("{} rows dropped by {}", dropped, transforms.describe());
+    }
+
+    if failed_docs > 0 {
+        log!("{} documents failed to index", failed_docs);
+    }
+
+    if !passthrough && args.preview.is_none() {
+        log!("Column statistics:");
+        for stats in &column_stats {
+            log!("{}", stats.describe());
         }
-        total_docs += batch.len() / 2;
+    }
+
+    if args.bench {
+        let secs = started.elapsed().as_secs_f64();
+        let input_mb = std::fs::metadata(&args.input.file).map_or(0, |m| m.len()) as f64 / 1e6;
+        let output_mb = bench_bytes.into_inner() as f64 / 1e6;
+        println!(
+            "Benchmark: {} rows in {:.2}s, {:.0} rows/s, {:.1} MB/s input ({:.1} MB), {:.1} MB/s bulk output ({:.1} MB)",
+            total_docs,
+            secs,
+            total_docs as f64 / secs,
+            input_mb / secs,
+            input_mb,
+            output_mb / secs,
+            output_mb
+        );
+        return Ok(());
+    }
+    if args.preview.is_some() {
+        return Ok(());
+    }
+    if let Some(out) = output {
+        out.into_inner()
+            .unwrap()
+            .flush()
+            .map_err(|e| format!("cannot write the bulk requests: {}", e))?;
+        log!(
+            "Wrote bulk requests for {} documents to {}",
+            total_docs,
+            args.output.as_deref().unwrap_or_default()
+        );
+        return Ok(());
     }
 
     println!(
         "Successfully uploaded {} documents to index: {}",
         total_docs, args.index_name
     );
+    if let Some(segments) = args.forcemerge {
+        es_forcemerge(
+            &target,
+            &index_template.render(&[])?,
+            segments,
+            auth.as_ref(),
+        )?;
+    }
+    if let Some(alias) = &args.alias {
+        let index = index_template.render(&[])?;
+        if args.staged {
+            let count = es_refreshed_count(&target, &index, auth.as_ref())?;
+            if count != total_docs as u64 || failed_docs > 0 {
+                return Err(format!(
+                    "Alias {} not moved: index {} has {} documents, {} imported and {} failed \
+                     (duplicate _ids count once)",
+                    alias, index, count, total_docs, failed_docs
+                )
+                .into());
+            }
+        }
+        let old = es_move_alias(&target, alias, &index, auth.as_ref())?;
+        if old.is_empty() {
+            log!("Alias {} now points at {}", alias, index);
+        } else {
+            log!("Alias {} moved from {} to {}", alias, old.join(", "), index);
+        }
+        if args.delete_old {
+            for old in &old {
+                es_delete_index(&target, old, auth.as_ref())?;
+                log!("Deleted index {}", old);
+            }
+        }
+    }
+    run_hooks("--post-hook", &post_hooks)?;
+    Ok(())
+}
+
+/// `export`: write the documents of an index to a file
+fn export(args: cli::ExportArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(format) = &args.date_format {
+        validate_strftime(format).unwrap_or_else(|e| usage_error("export", &e));
+    }
+    let connection =
+        resolve_connection(args.connection, true).unwrap_or_else(|e| usage_error("export", &e));
+    let target = connection.target()?;
+    let auth = connection.auth()?;
+    if let Err(e) = es_ping(&target, auth.as_ref()) {
+        return Err(format!("Cannot connect to ES at {}: {}", connection.host, e).into());
+    }
+    let export = export::Export {
+        index: args.index,
+        format: args.format,
+        query: args.query.as_deref().map(export::load_query).transpose()?,
+        page_size: args.batch_size,
+        slices: args.slices,
+        id_field: args.id_field,
+        fields: args.fields,
+        null_value: args.null_value,
+        date_format: args.date_format,
+        max_depth: args.max_depth,
+    };
+    let out: Box<dyn Write> = if args.file == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(
+            File::create(&args.file).map_err(|e| format!("cannot create {}: {}", args.file, e))?,
+        )
+    };
+    let mut out = io::BufWriter::new(out);
+    let total = export::run(&export, &target, auth.as_ref(), &mut out)?;
+    out.flush()?;
+    log!(
+        "Exported {} documents from {} to {}",
+        total,
+        export.index,
+        args.file
+    );
+    Ok(())
+}
+
+/// `reindex`: read the documents of the source index and import them
+fn reindex(args: cli::ReindexArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let query = args.query.as_deref().map(export::load_query).transpose()?;
+    let slices = args.slices;
+    let (mut import_args, source) =
+        resolve_reindex(args).unwrap_or_else(|e| usage_error("reindex", &e));
+    let _ = RUN_ID.set(import_args.run_id.clone());
+    let target = source.target()?;
+    let auth = source.auth()?;
+    if let Err(e) = es_ping(&target, auth.as_ref()) {
+        return Err(format!("Cannot connect to ES at {}: {}", source.host, e).into());
+    }
+
+    let index = &import_args.input.file;
+    let mut ndjson = Vec::new();
+    let mut ids = Vec::new();
+    let search = export::Search {
+        index,
+        query: query.as_ref(),
+        page_size: import_args.batch_size,
+        slices,
+        fields: &[],
+    };
+    export::read_pages(&target, auth.as_ref(), &search, |hits| {
+        for hit in hits {
+            match hit.get("_source") {
+                Some(source) => writeln!(ndjson, "{}", source),
+                // _source is disabled in the mapping
+                None => writeln!(ndjson, "{{}}"),
+            }
+            .map_err(|e| e.to_string())?;
+            ids.push(
+                hit.get("_id")
+                    .and_then(Value::as_str)
+                    .unwrap_or("")
+                    .to_string(),
+            );
+        }
+        Ok(())
+    })?;
+    log!("Read {} documents from {}", ids.len(), index);
+    import_args.source = Some(Source { ndjson, ids });
+    import(import_args)
+}
+
+/// `delete`: count the documents matching the condition, then delete them
+fn delete(args: cli::DeleteArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let connection =
+        resolve_connection(args.connection, true).unwrap_or_else(|e| usage_error("delete", &e));
+    // clap has checked that exactly one of these is given
+    let query = match (&args.query, args.where_) {
+        (Some(path), _) => export::load_query(path)?.to_string(),
+        (None, Some(query)) => query,
+        (None, None) => unreachable!(),
+    };
+    let body = format!("{{\"query\":{}}}", query);
+
+    let target = connection.target()?;
+    let auth = connection.auth()?;
+    if let Err(e) = es_ping(&target, auth.as_ref()) {
+        return Err(format!("Cannot connect to ES at {}: {}", connection.host, e).into());
+    }
+    let count = es_count(&target, &args.index, &body, auth.as_ref())?;
+    log!("{} documents in {} match", count, args.index);
+    if args.dry_run || count == 0 {
+        return Ok(());
+    }
+    if !args.yes && !confirm(&format!("Delete {} documents from {}?", count, args.index)) {
+        return Err(format!(
+            "Index {} left as it is (pass --yes to delete the documents)",
+            args.index
+        )
+        .into());
+    }
+    let deleted = es_delete_by_query(&target, &args.index, &body, auth.as_ref())?;
+    log!("Deleted {} documents from index {}", deleted, args.index);
+    Ok(())
+}
+
+/// `inspect`: print what an operator checks before and after an import
+fn inspect(args: cli::InspectArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let connection =
+        resolve_connection(args.connection, true).unwrap_or_else(|e| usage_error("inspect", &e));
+    let target = connection.target()?;
+    let auth = connection.auth()?;
+    if let Err(e) = es_ping(&target, auth.as_ref()) {
+        return Err(format!("Cannot connect to ES at {}: {}", connection.host, e).into());
+    }
+    let mut rows = es_cat_indices(&target, &args.index, auth.as_ref())?;
+    if rows.is_empty() {
+        return Err(format!("index {} not found", args.index).into());
+    }
+    let field = |row: &Value, key: &str| {
+        row.get(key)
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_string()
+    };
+    rows.sort_by_key(|row| field(row, "index"));
+    let fields = match es_mapping(&target, &args.index, auth.as_ref())? {
+        Some(mapping) => schema::field_counts(&mapping),
+        None => Vec::new(),
+    };
+
+    for row in &rows {
+        let index = field(row, "index");
+        let store = field(row, "store.size").parse::<u64>().unwrap_or(0);
+        println!("{}", index);
+        println!(
+            "  health:  {} ({}, {} primaries, {} replicas)",
+            field(row, "health"),
+            field(row, "status"),
+            field(row, "pri"),
+            field(row, "rep")
+        );
+        println!(
+            "  docs:    {} ({} deleted)",
+            field(row, "docs.count"),
+            field(row, "docs.deleted")
+        );
+        println!("  store:   {:.1} MB", store as f64 / 1e6);
+        if let Some((_, n)) = fields.iter().find(|(i, _)| *i == index) {
+            println!("  fields:  {}", n);
+        }
+    }
+    Ok(())
+}
+
+/// `validate`: read the whole input as `import` would and report every
+/// record that wouldn't be indexed as it is
+fn validate(input: InputOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let Opened {
+        mut csv, declared, ..
+    } = open_input(&input, None)?;
+    let mut rows = 0;
+    let mut misfits = 0;
+    while let Some(row) = csv.next() {
+        let row = row?;
+        rows += 1;
+        for &(i, ty) in &declared {
+            if !ty.fits(&row[i].1) {
+                println!(
+                    "line {}: column {}: {:?} is not a valid {}",
+                    csv.rdr.record_line, row[i].0, row[i].1, ty
+                );
+                misfits += 1;
+            }
+        }
+    }
+    for line in &csv.ragged_lines {
+        println!("line {}: ragged record", line);
+    }
+    for line in &csv.rdr.unmatched {
+        println!("line {}: unrecognized line", line);
+    }
+    for line in &csv.invalid_utf8_lines {
+        println!("line {}: invalid UTF-8", line);
+    }
+
+    let (ragged, unmatched) = (csv.ragged_lines.len(), csv.rdr.unmatched.len());
+    let invalid = csv.invalid_utf8_lines.len();
+    log!(
+        "{} rows read: {} ragged records, {} unrecognized lines, {} with invalid UTF-8, {} values not fitting their declared type",
+        rows,
+        ragged,
+        unmatched,
+        invalid,
+        misfits
+    );
+    let problems = ragged + unmatched + invalid + misfits;
+    if problems > 0 {
+        return Err(format!("{} problems found in {}", problems, input.file).into());
+    }
+    Ok(())
+}
+
+/// `analyze`: profile every column of the whole input, as a table or as
+/// JSON, and with `destination` check the columns against that index
+fn analyze(
+    input: InputOptions,
+    json: bool,
+    destination: Option<(String, Connection)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Opened {
+        mut csv, declared, ..
+    } = open_input(&input, None)?;
+    let mut profiles: Vec<analyze::Profile> = csv
+        .headers
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let ty = declared.iter().find(|&&(j, _)| j == i).map(|&(_, ty)| ty);
+            analyze::Profile::new(name, ty)
+        })
+        .collect();
+    let mut rows = 0;
+    for row in csv.by_ref() {
+        rows += 1;
+        for (profile, (_, value)) in profiles.iter_mut().zip(row?) {
+            profile.add(&value);
+        }
+    }
+    let checks = match &destination {
+        Some((index, connection)) => {
+            let target = connection.target()?;
+            let auth = connection.auth()?;
+            if let Err(e) = es_ping(&target, auth.as_ref()) {
+                return Err(format!("Cannot connect to ES at {}: {}", connection.host, e).into());
+            }
+            let mapping = es_mapping(&target, index, auth.as_ref())?;
+            let mut fields: Vec<String> = Vec::new();
+            for (name, es_type) in mapping.iter().flat_map(schema::field_types) {
+                if analyze::AGGREGATABLE.contains(&es_type.as_str())
+                    && csv.headers.contains(&name)
+                    && !fields.contains(&name)
+                {
+                    fields.push(name);
+                }
+            }
+            let (documents, distinct) = match mapping {
+                Some(_) => es_cardinalities(&target, index, &fields, auth.as_ref())?,
+                None => (0, HashMap::new()),
+            };
+            let checks = analyze::compare(&profiles, mapping.as_ref(), &distinct);
+            Some((index, mapping.is_some(), documents, checks))
+        }
+        None => None,
+    };
+    if json {
+        let mut report = vec![
+            ("file".to_string(), Value::String(input.file.clone())),
+            ("rows".to_string(), Value::Number(rows.to_string())),
+            (
+                "columns".to_string(),
+                Value::Array(profiles.iter().map(|p| p.to_json(rows)).collect()),
+            ),
+        ];
+        if let Some((index, exists, documents, checks)) = &checks {
+            report.push((
+                "index".to_string(),
+                Value::Object(vec![
+                    ("name".to_string(), Value::String(index.to_string())),
+                    ("exists".to_string(), Value::Bool(*exists)),
+                    (
+                        "documents".to_string(),
+                        Value::Number(documents.to_string()),
+                    ),
+                    (
+                        "columns".to_string(),
+                        Value::Array(checks.iter().map(analyze::Check::to_json).collect()),
+                    ),
+                ]),
+            ));
+        }
+        println!("{}", Value::Object(report));
+    } else {
+        print!("{}", analyze::table(&profiles, rows));
+        if let Some((index, exists, documents, checks)) = &checks {
+            println!();
+            match exists {
+                true => println!("Against index {} ({} documents):", index, documents),
+                false => println!("Against index {}, which doesn't exist yet:", index),
+            }
+            let findings = analyze::findings(checks, &profiles);
+            if findings.is_empty() {
+                println!("  every column fits");
+            }
+            for line in findings {
+                println!("  {}", line);
+            }
+        }
+    }
+    log!(
+        "{} rows read: {} ragged records, {} unrecognized lines, {} with invalid UTF-8",
+        rows,
+        csv.ragged_lines.len(),
+        csv.rdr.unmatched.len(),
+        csv.invalid_utf8_lines.len()
+    );
     Ok(())
 }
+
+/// `mapping`: print the mapping `import --recreate` would create
+fn mapping(input: InputOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let Opened { csv, types, .. } = open_input(&input, None)?;
+    let columns = csv.headers.iter().map(String::as_str).zip(types);
+    println!("{}", schema::mapping(columns));
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = cli::Cli::parse_from(cli::default_to_import(env::args_os()));
+    match cli.command {
+        cli::Command::Import(args) => {
+            let args = resolve_import(*args).unwrap_or_else(|e| usage_error("import", &e));
+            import(args)
+        }
+        cli::Command::Export(args) => export(args),
+        cli::Command::Reindex(args) => reindex(*args),
+        cli::Command::Delete(args) => delete(args),
+        cli::Command::Inspect(args) => inspect(args),
+        cli::Command::Validate(args) => {
+            validate(resolve_input(args).unwrap_or_else(|e| usage_error("validate", &e)))
+        }
+        cli::Command::Analyze(args) => {
+            let args = *args;
+            let input = resolve_input(args.input).unwrap_or_else(|e| usage_error("analyze", &e));
+            let destination = args.index.map(|index| {
+                let connection = resolve_connection(args.connection, true)
+                    .unwrap_or_else(|e| usage_error("analyze", &e));
+                (index, connection)
+            });
+            analyze(input, args.json, destination)
+        }
+        cli::Command::Mapping(args) => {
+            mapping(resolve_input(args).unwrap_or_else(|e| usage_error("mapping", &e)))
+        }
+        cli::Command::Completions { shell } => {
+            let mut cmd = cli::Cli::command();
+            clap_complete::generate(shell, &mut cmd, "elastic_importer", &mut io::stdout());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Newline-delimited JSON objects for `--format ndjson`
+
+use crate::json::{self, Value};
+use crate::schema::ColumnType;
+
+/// JSON kinds seen in a column, to keep strings strings and objects
+/// objects rather than re-inferring them from text
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    String,
+    Bool,
+    Number,
+    /// Array or object, kept as JSON
+    Raw,
+    Mixed,
+}
+
+/// Column names and types found by a pass over `input`: keys in order of
+/// first appearance, typed by the JSON values under them
+pub fn scan(
+    input: &[u8],
+    max_depth: Option<usize>,
+) -> Result<(Vec<String>, Vec<Option<ColumnType>>), String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut kinds: Vec<Option<Kind>> = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    while let Some(fields) = next(input, &mut pos, &mut line, max_depth)? {
+        for (name, value) in fields {
+            let i = match names.iter().position(|n| *n == name) {
+                Some(i) => i,
+                None => {
+                    names.push(name);
+                    kinds.push(None);
+                    kinds.len() - 1
+                }
+            };
+            let kind = match value {
+                Value::Null => continue,
+                Value::String(_) => Kind::String,
+                Value::Bool(_) => Kind::Bool,
+                Value::Number(_) => Kind::Number,
+                Value::Array(_) | Value::Object(_) => Kind::Raw,
+            };
+            kinds[i] = match kinds[i] {
+                None => Some(kind),
+                Some(k) if k == kind => Some(k),
+                Some(Kind::Raw) => Some(Kind::Raw),
+                Some(_) if kind == Kind::Raw => Some(Kind::Raw),
+                Some(_) => Some(Kind::Mixed),
+            };
+        }
+    }
+    let types = kinds
+        .into_iter()
+        .map(|k| match k? {
+            Kind::String => Some(ColumnType::Keyword),
+            Kind::Bool => Some(ColumnType::Bool),
+            Kind::Raw => Some(ColumnType::Object),
+            Kind::Number | Kind::Mixed => None,
+        })
+        .collect();
+    Ok((names, types))
+}
+
+/// Fields of the next non-blank line at or after `*pos`; `*line` counts
+/// lines as they are passed. With `max_depth`, nested objects are flattened
+/// to dot-joined keys of at most that many parts, else only top-level keys
+/// are fields.
+pub fn next(
+    input: &[u8],
+    pos: &mut usize,
+    line: &mut usize,
+    max_depth: Option<usize>,
+) -> Result<Option<Vec<(String, Value)>>, String> {
+    while *pos < input.len() {
+        let end = input[*pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(input.len(), |p| *pos + p);
+        let text = String::from_utf8_lossy(&input[*pos..end]);
+        *pos = (end + 1).min(input.len());
+        *line += 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let at = *line - 1;
+        let Value::Object(fields) = json::parse(text).map_err(|e| format!("line {}: {}", at, e))?
+        else {
+            return Err(format!("line {}: not a JSON object", at));
+        };
+        let mut out = Vec::with_capacity(fields.len());
+        flatten(fields, "", max_depth.unwrap_or(1), &mut out);
+        return Ok(Some(out));
+    }
+    Ok(None)
+}
+
+fn flatten(
+    fields: Vec<(String, Value)>,
+    prefix: &str,
+    depth: usize,
+    out: &mut Vec<(String, Value)>,
+) {
+    for (key, value) in fields {
+        let name = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            Value::Object(inner) if depth > 1 && !inner.is_empty() => {
+                flatten(inner, &name, depth - 1, out);
+            }
+            value => out.push((name, value)),
+        }
+    }
+}
+
+/// Text of a field value as the rest of the pipeline sees it: strings
+/// unquoted, null empty, arrays and objects as compact JSON
+pub fn text(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) | Value::String(n) => n,
+        raw => raw.to_string(),
+    }
+}
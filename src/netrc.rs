@@ -0,0 +1,109 @@
+//! Basic auth credentials from `~/.netrc` (or `$NETRC`), matched by host
+//! as curl does
+
+use std::env;
+use std::path::PathBuf;
+
+/// Login and password for `host`, from the first `machine` entry for it or
+/// else the `default` one; with `user` only an entry with that login counts
+pub fn lookup(host: &str, user: Option<&str>) -> Option<(String, String)> {
+    let path = match env::var_os("NETRC") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(env::var_os("HOME")?).join(".netrc"),
+    };
+    let text = std::fs::read_to_string(path).ok()?;
+    let entries = parse(&text);
+    let usable = || {
+        entries
+            .iter()
+            .filter(|e| e.password.is_some() && user.is_none_or(|u| e.login.as_deref() == Some(u)))
+    };
+    let entry = usable()
+        .find(|e| {
+            e.machine
+                .as_deref()
+                .is_some_and(|m| m.eq_ignore_ascii_case(host))
+        })
+        .or_else(|| usable().find(|e| e.machine.is_none()))?;
+    let login = entry.login.clone().or_else(|| user.map(String::from))?;
+    Some((login, entry.password.clone()?))
+}
+
+/// A `machine` entry, or the `default` one when `machine` is `None`
+struct Entry {
+    machine: Option<String>,
+    login: Option<String>,
+    password: Option<String>,
+}
+
+fn parse(text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut tokens = tokens(text).into_iter();
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "machine" | "default" => entries.push(Entry {
+                machine: match token.as_str() {
+                    "machine" => Some(tokens.next().unwrap_or_default()),
+                    _ => None,
+                },
+                login: None,
+                password: None,
+            }),
+            "login" | "password" | "account" => {
+                let value = tokens.next();
+                if let Some(entry) = entries.last_mut() {
+                    match token.as_str() {
+                        "login" => entry.login = value,
+                        "password" => entry.password = value,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// Whitespace separated tokens, `"quoted"` ones included, without comments
+/// and `macdef` bodies (which run to the next blank line)
+fn tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+    for line in text.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+        let mut chars = line.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let Some(&first) = chars.peek() else {
+                break;
+            };
+            if first == '#' {
+                break;
+            }
+            let mut token = String::new();
+            if first == '"' {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => token.extend(chars.next()),
+                        c => token.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    token.push(c);
+                }
+            }
+            tokens.push(token);
+        }
+        if tokens.len() >= 2 && tokens[tokens.len() - 2] == "macdef" {
+            in_macro = true;
+        }
+    }
+    tokens
+}
@@ -0,0 +1,170 @@
+//! WebAssembly plugins for `--plugin`, run with wasmtime
+//!
+//! ABI version 1. A plugin module exports its `memory`, `abi_version() ->
+//! i32` returning 1, and `alloc(len: i32) -> i32` returning space in that
+//! memory for the host to write `len` bytes of input into. It then exports
+//! one or both of:
+//!
+//! - `read(ptr: i32, len: i32) -> i64`, a source: given the raw input file,
+//!   returns it as NDJSON, one JSON object per record, which is imported as
+//!   with `--format ndjson`.
+//! - `transform(ptr: i32, len: i32) -> i64`: given one document as JSON,
+//!   returns the JSON to index in its place: an object, an array of objects
+//!   to split it into several, or `null` to drop it. `_id` and `_index`
+//!   string fields of a returned object set its metadata.
+//!
+//! Both return their output as `ptr << 32 | len` in the plugin's memory.
+//! Plugins may import `env.log(ptr: i32, len: i32)` to print a line of UTF-8
+//! to stderr.
+
+use wasmtime::{Caller, Engine, Instance, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::Doc;
+use crate::json::{self, Value};
+
+const ABI_VERSION: i32 = 1;
+
+pub struct Plugin {
+    path: String,
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    read: Option<TypedFunc<(i32, i32), i64>>,
+    transform: Option<TypedFunc<(i32, i32), i64>>,
+}
+
+impl Plugin {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let err = |e: wasmtime::Error| format!("--plugin {}: {:#}", path, e);
+        let engine = Engine::default();
+        let module = Module::from_file(&engine, path).map_err(err)?;
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap(
+                "env",
+                "log",
+                |mut caller: Caller<'_, ()>, ptr: i32, len: i32| {
+                    let Some(memory) = caller.get_export("memory").and_then(|e| e.into_memory())
+                    else {
+                        return;
+                    };
+                    let data = memory.data(&caller);
+                    if let Some(bytes) = data
+                        .get(ptr as u32 as usize..)
+                        .and_then(|d| d.get(..len as u32 as usize))
+                    {
+                        log!("plugin: {}", String::from_utf8_lossy(bytes));
+                    }
+                },
+            )
+            .map_err(err)?;
+        let mut store = Store::new(&engine, ());
+        let instance: Instance = linker.instantiate(&mut store, &module).map_err(err)?;
+
+        let version = instance
+            .get_typed_func::<(), i32>(&mut store, "abi_version")
+            .and_then(|f| f.call(&mut store, ()))
+            .map_err(err)?;
+        if version != ABI_VERSION {
+            return Err(format!(
+                "--plugin {}: ABI version {} isn't supported (expected {})",
+                path, version, ABI_VERSION
+            ));
+        }
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| format!("--plugin {}: doesn't export its memory", path))?;
+        let alloc = instance.get_typed_func(&mut store, "alloc").map_err(err)?;
+        let read = instance.get_typed_func(&mut store, "read").ok();
+        let transform = instance.get_typed_func(&mut store, "transform").ok();
+        if read.is_none() && transform.is_none() {
+            return Err(format!(
+                "--plugin {}: exports neither read nor transform",
+                path
+            ));
+        }
+        Ok(Self {
+            path: path.to_string(),
+            store,
+            memory,
+            alloc,
+            read,
+            transform,
+        })
+    }
+
+    pub fn is_source(&self) -> bool {
+        self.read.is_some()
+    }
+
+    pub fn is_transform(&self) -> bool {
+        self.transform.is_some()
+    }
+
+    /// NDJSON records of a raw input file
+    pub fn read(&mut self, input: &[u8]) -> Result<Vec<u8>, String> {
+        let read = self.read.clone().ok_or("plugin has no read export")?;
+        self.call(&read, input)
+    }
+
+    /// Documents to index in place of `doc`
+    pub fn transform(&mut self, doc: &Doc) -> Result<Vec<Doc>, String> {
+        let transform = self
+            .transform
+            .clone()
+            .ok_or("plugin has no transform export")?;
+        let out = self.call(&transform, doc.json.as_bytes())?;
+        let text = String::from_utf8(out)
+            .map_err(|_| format!("--plugin {}: transform output isn't UTF-8", self.path))?;
+        let value = json::parse(&text)
+            .map_err(|e| format!("--plugin {}: transform output: {}", self.path, e))?;
+        let objects = match value {
+            Value::Null => Vec::new(),
+            Value::Array(items) => items,
+            object => vec![object],
+        };
+        objects
+            .into_iter()
+            .map(|v| {
+                let Value::Object(mut fields) = v else {
+                    return Err(format!(
+                        "--plugin {}: transform must return objects, got {}",
+                        self.path, v
+                    ));
+                };
+                let mut meta = |key: &str| {
+                    let at = fields.iter().position(|(k, _)| k == key)?;
+                    match fields.remove(at).1 {
+                        Value::String(s) => Some(s),
+                        other => Some(other.to_string()),
+                    }
+                };
+                let (id, index) = (meta("_id"), meta("_index"));
+                Ok(Doc {
+                    json: Value::Object(fields).to_string(),
+                    id: id.or_else(|| doc.id.clone()),
+                    index: index.or_else(|| doc.index.clone()),
+                })
+            })
+            .collect()
+    }
+
+    /// Copy `input` into the plugin, call `func` on it and copy its output
+    /// back out
+    fn call(&mut self, func: &TypedFunc<(i32, i32), i64>, input: &[u8]) -> Result<Vec<u8>, String> {
+        let err = |e: wasmtime::Error| format!("--plugin {}: {:#}", self.path, e);
+        let len = i32::try_from(input.len())
+            .map_err(|_| format!("--plugin {}: input too large", self.path))?;
+        let ptr = self.alloc.call(&mut self.store, len).map_err(err)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, input)
+            .map_err(|e| format!("--plugin {}: alloc returned bad memory: {}", self.path, e))?;
+        let packed = func.call(&mut self.store, (ptr, len)).map_err(err)? as u64;
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        let mut out = vec![0; out_len];
+        self.memory
+            .read(&self.store, out_ptr, &mut out)
+            .map_err(|e| format!("--plugin {}: output out of bounds: {}", self.path, e))?;
+        Ok(out)
+    }
+}
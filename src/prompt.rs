@@ -0,0 +1,93 @@
+//! Passwords typed at the terminal, for `--user` without `--pass`
+
+/// Ask for a password on the controlling terminal with echo turned off;
+/// /dev/tty rather than stdin, which may be the input
+#[cfg(unix)]
+pub fn password(question: &str) -> Result<String, String> {
+    use std::fs::OpenOptions;
+    use std::io::{BufRead, BufReader, Write};
+    use std::mem::MaybeUninit;
+    use std::os::fd::AsRawFd;
+
+    let no_tty = "--user requires --pass, --credential-profile or a terminal to ask on";
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|_| no_tty)?;
+    let fd = tty.as_raw_fd();
+    let mut term = MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: fd stays open for the whole function and term is only read
+    // once tcgetattr has filled it in
+    let saved = unsafe {
+        if libc::tcgetattr(fd, term.as_mut_ptr()) != 0 {
+            return Err(no_tty.into());
+        }
+        term.assume_init()
+    };
+    let mut quiet = saved;
+    quiet.c_lflag &= !libc::ECHO;
+    quiet.c_lflag |= libc::ECHONL;
+    let _ = write!(&tty, "{}", question);
+    let echo = Echo::off(fd, saved, quiet)?;
+    let mut line = String::new();
+    let read = BufReader::new(&tty).read_line(&mut line);
+    drop(echo);
+    read.map_err(|e| format!("cannot read the password: {}", e))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Terminal echo turned off until this is dropped, or the process is
+/// interrupted while it is, so a Ctrl-C at the prompt doesn't leave the
+/// shell without echo
+#[cfg(unix)]
+struct Echo {
+    fd: i32,
+    saved: libc::termios,
+    handlers: Vec<signal_hook::SigId>,
+}
+
+#[cfg(unix)]
+impl Echo {
+    fn off(fd: i32, saved: libc::termios, quiet: libc::termios) -> Result<Self, String> {
+        use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+
+        let mut echo = Self {
+            fd,
+            saved,
+            handlers: Vec::new(),
+        };
+        for signal in [SIGINT, SIGTERM, SIGHUP] {
+            // SAFETY: the handler only makes async-signal-safe calls, on an
+            // fd that stays open until the handler is unregistered
+            let id = unsafe {
+                signal_hook::low_level::register(signal, move || {
+                    libc::tcsetattr(fd, libc::TCSAFLUSH, &saved);
+                    libc::write(fd, b"\n".as_ptr().cast(), 1);
+                    signal_hook::low_level::exit(128 + signal);
+                })
+            }
+            .map_err(|e| format!("cannot handle signal {}: {}", signal, e))?;
+            echo.handlers.push(id);
+        }
+        // SAFETY: termios structs of our own, on the fd of the open tty
+        unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &quiet) };
+        Ok(echo)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Echo {
+    fn drop(&mut self) {
+        // SAFETY: as in `off`
+        unsafe { libc::tcsetattr(self.fd, libc::TCSAFLUSH, &self.saved) };
+        for id in self.handlers.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn password(_question: &str) -> Result<String, String> {
+    Err("--user requires --pass or --credential-profile".into())
+}
@@ -0,0 +1,797 @@
+//! Column type inference and typed JSON output
+
+use std::fmt::{self, Write};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use chrono::SecondsFormat;
+
+use crate::json::{self, Value, write_escaped};
+use crate::{parse_datetime, wkt};
+
+/// JSON type a column's values are written as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Bool,
+    Long,
+    /// 0 to 2^64-1; only ever declared, never inferred
+    UnsignedLong,
+    Double,
+    /// Date or date-time in any format `parse_datetime` knows, written as
+    /// ISO 8601 UTC; only ever declared, never inferred
+    Date,
+    /// Exact decimal such as `19.90`, written as a string so no digits are
+    /// lost to floating point; only ever declared, never inferred
+    Decimal,
+    /// Array or object kept as JSON, as NDJSON input's nested values are;
+    /// values that aren't JSON are written as strings
+    Object,
+    /// WKT geometry like `POLYGON((...))`, written as a GeoJSON object;
+    /// values that aren't WKT are written as strings
+    GeoShape,
+    /// Latitude and longitude as `lat,lon`, written as a string; only ever
+    /// declared, never inferred
+    GeoPoint,
+    /// IPv4 or IPv6 address, written as a string
+    Ip,
+    /// Length of time like `1h30m`, `00:02:35` or `450ms`, written as a
+    /// number of `unit`s; plain numbers are taken as `unit`s already. Only
+    /// ever declared, never inferred
+    Duration(TimeUnit),
+    /// Embedding given as a JSON array or `;`-separated numbers, written as
+    /// an array of numbers; only ever declared, never inferred
+    DenseVector {
+        dims: Option<u32>,
+        similarity: Option<Similarity>,
+    },
+    /// Always a JSON string
+    Keyword,
+}
+
+impl ColumnType {
+    /// Type of a single non-empty value. Integers too big for a long are
+    /// keywords rather than doubles, which would lose digits.
+    pub fn detect(s: &str) -> Self {
+        if i64::from_str(s).is_ok() {
+            ColumnType::Long
+        } else if is_integer(s) {
+            ColumnType::Keyword
+        } else if f64::from_str(s).is_ok_and(f64::is_finite) {
+            ColumnType::Double
+        } else if s.eq_ignore_ascii_case("true") || s.eq_ignore_ascii_case("false") {
+            ColumnType::Bool
+        } else if IpAddr::from_str(s).is_ok() {
+            ColumnType::Ip
+        } else if wkt::looks_like(s) && wkt::to_geojson(s).is_some() {
+            ColumnType::GeoShape
+        } else {
+            ColumnType::Keyword
+        }
+    }
+
+    /// Narrowest type holding values of both: long and double widen to
+    /// double, any other mix to keyword
+    pub fn widen(self, other: Self) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Long, ColumnType::Double) | (ColumnType::Double, ColumnType::Long) => {
+                ColumnType::Double
+            }
+            _ => ColumnType::Keyword,
+        }
+    }
+
+    /// Whether `s` can be written as this type; empty values always can
+    pub fn fits(self, s: &str) -> bool {
+        s.is_empty()
+            || match self {
+                ColumnType::Bool => {
+                    s.eq_ignore_ascii_case("true") || s.eq_ignore_ascii_case("false")
+                }
+                ColumnType::Long => i64::from_str(s).is_ok(),
+                ColumnType::UnsignedLong => u64::from_str(s).is_ok(),
+                ColumnType::Double => f64::from_str(s).is_ok_and(f64::is_finite),
+                ColumnType::Date => parse_datetime(s).is_some(),
+                ColumnType::Decimal => is_decimal(s),
+                ColumnType::Object => json::parse(s).is_ok(),
+                ColumnType::GeoShape => wkt::to_geojson(s).is_some(),
+                ColumnType::GeoPoint => geo_point(s).is_some(),
+                ColumnType::Ip => IpAddr::from_str(s).is_ok(),
+                ColumnType::Duration(unit) => unit.of(s).is_some(),
+                ColumnType::DenseVector { dims, .. } => {
+                    vector(s).is_some_and(|v| dims.is_none_or(|d| v.len() == d as usize))
+                }
+                ColumnType::Keyword => true,
+            }
+    }
+}
+
+impl ColumnType {
+    /// Field type in an index mapping; objects are left to dynamic mapping
+    pub fn es_type(self) -> Option<&'static str> {
+        match self {
+            ColumnType::Bool => Some("boolean"),
+            ColumnType::Long => Some("long"),
+            ColumnType::UnsignedLong => Some("unsigned_long"),
+            ColumnType::Double | ColumnType::Duration(_) => Some("double"),
+            ColumnType::Date => Some("date"),
+            ColumnType::Decimal | ColumnType::Keyword => Some("keyword"),
+            ColumnType::GeoShape => Some("geo_shape"),
+            ColumnType::GeoPoint => Some("geo_point"),
+            ColumnType::Ip => Some("ip"),
+            ColumnType::DenseVector { .. } => Some("dense_vector"),
+            ColumnType::Object => None,
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "boolean" | "bool" => Ok(ColumnType::Bool),
+            "long" | "integer" => Ok(ColumnType::Long),
+            "unsigned_long" => Ok(ColumnType::UnsignedLong),
+            "double" | "float" => Ok(ColumnType::Double),
+            "date" => Ok(ColumnType::Date),
+            "decimal" => Ok(ColumnType::Decimal),
+            "object" => Ok(ColumnType::Object),
+            "geo_shape" => Ok(ColumnType::GeoShape),
+            "geo_point" => Ok(ColumnType::GeoPoint),
+            "ip" => Ok(ColumnType::Ip),
+            "keyword" | "string" | "text" => Ok(ColumnType::Keyword),
+            _ if let Some(options) = s.strip_prefix("dense_vector") => dense_vector(options),
+            _ if let Some(unit) = s.strip_prefix("duration") => duration_type(unit),
+            _ => Err(format!(
+                "unknown column type {} (expected boolean, long, unsigned_long, double, date, duration, decimal, object, geo_point, geo_shape, ip, dense_vector or keyword)",
+                s
+            )),
+        }
+    }
+}
+
+/// `dense_vector` options: `(384)`, `(cosine)` or `(384,cosine)`
+fn dense_vector(options: &str) -> Result<ColumnType, String> {
+    let mut dims = None;
+    let mut similarity = None;
+    if !options.is_empty() {
+        let inner = options
+            .strip_prefix('(')
+            .and_then(|o| o.strip_suffix(')'))
+            .ok_or("expected dense_vector(DIMS,SIMILARITY)")?;
+        for option in inner.split(',').map(str::trim) {
+            match option.parse::<u32>() {
+                Ok(n) if n > 0 => dims = Some(n),
+                _ => similarity = Some(option.parse()?),
+            }
+        }
+    }
+    Ok(ColumnType::DenseVector { dims, similarity })
+}
+
+/// `duration` unit: `(s)` and so on, milliseconds when not given
+fn duration_type(unit: &str) -> Result<ColumnType, String> {
+    if unit.is_empty() {
+        return Ok(ColumnType::Duration(TimeUnit::Millis));
+    }
+    let unit = unit
+        .strip_prefix('(')
+        .and_then(|u| u.strip_suffix(')'))
+        .ok_or("expected duration(UNIT)")?;
+    Ok(ColumnType::Duration(unit.trim().parse()?))
+}
+
+/// Unit `duration` columns are written in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeUnit {
+    Nanos,
+    Micros,
+    Millis,
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+}
+
+impl TimeUnit {
+    fn as_str(self) -> &'static str {
+        match self {
+            TimeUnit::Nanos => "ns",
+            TimeUnit::Micros => "us",
+            TimeUnit::Millis => "ms",
+            TimeUnit::Seconds => "s",
+            TimeUnit::Minutes => "m",
+            TimeUnit::Hours => "h",
+            TimeUnit::Days => "d",
+        }
+    }
+
+    fn nanos(self) -> f64 {
+        match self {
+            TimeUnit::Nanos => 1.0,
+            TimeUnit::Micros => 1e3,
+            TimeUnit::Millis => 1e6,
+            TimeUnit::Seconds => 1e9,
+            TimeUnit::Minutes => 60e9,
+            TimeUnit::Hours => 3600e9,
+            TimeUnit::Days => 86400e9,
+        }
+    }
+
+    /// How many of this unit the duration `s` is
+    pub fn of(self, s: &str) -> Option<f64> {
+        let s = s.trim();
+        if let Ok(n) = f64::from_str(s) {
+            return n.is_finite().then_some(n);
+        }
+        let (sign, s) = match s.strip_prefix('-') {
+            Some(rest) => (-1.0, rest),
+            None => (1.0, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let nanos = if s.contains(':') {
+            clock_nanos(s)?
+        } else if let Some(iso) = s.strip_prefix('P') {
+            // ISO 8601: P1DT2H30M
+            let (days, time) = iso.split_once('T').unwrap_or((iso, ""));
+            let date_unit = |u: &str| match u {
+                "W" => Some(7.0 * 86400e9),
+                "D" => Some(86400e9),
+                _ => None,
+            };
+            let time_unit = |u: &str| match u {
+                "H" => Some(3600e9),
+                "M" => Some(60e9),
+                "S" => Some(1e9),
+                _ => None,
+            };
+            if days.is_empty() && time.is_empty() {
+                return None;
+            }
+            let days = if days.is_empty() {
+                0.0
+            } else {
+                sum_parts(days, date_unit)?
+            };
+            let time = if time.is_empty() {
+                0.0
+            } else {
+                sum_parts(time, time_unit)?
+            };
+            days + time
+        } else {
+            sum_parts(s, |u: &str| match u {
+                "ns" => Some(1.0),
+                "us" | "µs" => Some(1e3),
+                "ms" => Some(1e6),
+                "s" | "sec" => Some(1e9),
+                "m" | "min" => Some(60e9),
+                "h" | "hr" => Some(3600e9),
+                "d" => Some(86400e9),
+                _ => None,
+            })?
+        };
+        Some(sign * nanos / self.nanos())
+    }
+}
+
+impl FromStr for TimeUnit {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "ns" => Ok(TimeUnit::Nanos),
+            "us" => Ok(TimeUnit::Micros),
+            "ms" => Ok(TimeUnit::Millis),
+            "s" => Ok(TimeUnit::Seconds),
+            "m" => Ok(TimeUnit::Minutes),
+            "h" => Ok(TimeUnit::Hours),
+            "d" => Ok(TimeUnit::Days),
+            _ => Err(format!(
+                "unknown duration unit {} (expected ns, us, ms, s, m, h or d)",
+                s
+            )),
+        }
+    }
+}
+
+/// Nanoseconds of `[[h:]m]m:ss[.f]`
+fn clock_nanos(s: &str) -> Option<f64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let (last, rest) = parts.split_last()?;
+    let seconds = f64::from_str(last)
+        .ok()
+        .filter(|&n| (0.0..60.0).contains(&n))?;
+    if !last.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut total = 0.0;
+    for (i, part) in rest.iter().enumerate() {
+        let n: u64 = part.parse().ok()?;
+        // only the leading part may run past 59
+        if i > 0 && n >= 60 {
+            return None;
+        }
+        total = total * 60.0 + n as f64;
+    }
+    Some((total * 60.0 + seconds) * 1e9)
+}
+
+/// Nanoseconds of numbers each followed by a unit, `1h 30m` or `1.5s`
+fn sum_parts(s: &str, unit: impl Fn(&str) -> Option<f64>) -> Option<f64> {
+    let mut rest = s.trim_start();
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let n: f64 = rest[..end].parse().ok()?;
+        rest = rest[end..].trim_start();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+            .unwrap_or(rest.len());
+        total += n * unit(&rest[..end])?;
+        rest = rest[end..].trim_start();
+    }
+    (total.is_finite() && !s.trim().is_empty()).then_some(total)
+}
+
+/// How `dense_vector` kNN search compares vectors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Similarity {
+    L2Norm,
+    DotProduct,
+    Cosine,
+    MaxInnerProduct,
+}
+
+impl Similarity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Similarity::L2Norm => "l2_norm",
+            Similarity::DotProduct => "dot_product",
+            Similarity::Cosine => "cosine",
+            Similarity::MaxInnerProduct => "max_inner_product",
+        }
+    }
+}
+
+impl FromStr for Similarity {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "l2_norm" => Ok(Similarity::L2Norm),
+            "dot_product" => Ok(Similarity::DotProduct),
+            "cosine" => Ok(Similarity::Cosine),
+            "max_inner_product" => Ok(Similarity::MaxInnerProduct),
+            _ => Err(format!(
+                "unknown dense_vector option {} (expected dims or l2_norm, dot_product, cosine or max_inner_product)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColumnType::Bool => "boolean",
+            ColumnType::Long => "long",
+            ColumnType::UnsignedLong => "unsigned_long",
+            ColumnType::Double => "double",
+            ColumnType::Date => "date",
+            ColumnType::Decimal => "decimal",
+            ColumnType::Object => "object",
+            ColumnType::GeoShape => "geo_shape",
+            ColumnType::GeoPoint => "geo_point",
+            ColumnType::Ip => "ip",
+            ColumnType::Keyword => "keyword",
+            ColumnType::Duration(TimeUnit::Millis) => "duration",
+            ColumnType::Duration(unit) => return write!(f, "duration({})", unit.as_str()),
+            &ColumnType::DenseVector { dims, similarity } => {
+                let options: Vec<String> = dims
+                    .map(|d| d.to_string())
+                    .into_iter()
+                    .chain(similarity.map(|s| s.as_str().to_string()))
+                    .collect();
+                if options.is_empty() {
+                    "dense_vector"
+                } else {
+                    return write!(f, "dense_vector({})", options.join(","));
+                }
+            }
+        })
+    }
+}
+
+/// Numbers of a `[0.1, 0.2]` or `0.1;0.2` vector
+pub fn vector(s: &str) -> Option<Vec<f64>> {
+    let s = s.trim();
+    let items: Vec<&str> = match s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        Some(inner) => inner.split(',').collect(),
+        None => s.split(';').collect(),
+    };
+    items
+        .iter()
+        .map(|i| f64::from_str(i.trim()).ok().filter(|f| f.is_finite()))
+        .collect()
+}
+
+/// Optional sign and digits only
+fn is_integer(s: &str) -> bool {
+    let digits = s.strip_prefix(['-', '+']).unwrap_or(s);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Optional sign, digits, and an optional fraction: `-12`, `19.90`, `.5`
+fn is_decimal(s: &str) -> bool {
+    let s = s.strip_prefix(['-', '+']).unwrap_or(s);
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    (!int.is_empty() || !frac.is_empty())
+        && int.bytes().all(|b| b.is_ascii_digit())
+        && frac.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Declared column types from a schema file: `{"price": "double", ...}`
+pub fn load(path: &str) -> Result<Vec<(String, ColumnType)>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("cannot read schema {}: {}", path, e))?;
+    let v = json::parse(&text).map_err(|e| format!("bad schema {}: {}", path, e))?;
+    let fields = v
+        .as_object()
+        .ok_or_else(|| format!("schema {} must be an object of column types", path))?;
+    fields
+        .iter()
+        .map(|(col, ty)| {
+            let ty = ty
+                .as_str()
+                .ok_or_else(|| format!("schema {}: type of {} must be a string", path, col))?
+                .parse()
+                .map_err(|e| format!("schema {}: {}: {}", path, col, e))?;
+            Ok((col.clone(), ty))
+        })
+        .collect()
+}
+
+/// Index creation body mapping every typed column; untyped ones are left to
+/// dynamic mapping
+pub fn mapping<'a>(columns: impl IntoIterator<Item = (&'a str, Option<ColumnType>)>) -> String {
+    let mut out = String::from("{\"mappings\":{\"properties\":{");
+    let mut first = true;
+    for (name, ty) in columns {
+        let Some(es_type) = ty.and_then(ColumnType::es_type) else {
+            continue;
+        };
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        out.push('"');
+        write_escaped(&mut out, name);
+        let _ = write!(out, "\":{{\"type\":\"{}\"", es_type);
+        if let Some(ColumnType::DenseVector { dims, similarity }) = ty {
+            if let Some(dims) = dims {
+                let _ = write!(out, ",\"dims\":{}", dims);
+            }
+            if let Some(similarity) = similarity {
+                let _ = write!(out, ",\"similarity\":\"{}\"", similarity.as_str());
+            }
+        }
+        out.push('}');
+    }
+    out.push_str("}}}");
+    out
+}
+
+/// Columns that an existing index can't take as they would be written,
+/// described for a warning. `mapping` is a `GET <index>/_mapping` response,
+/// covering every index behind an alias; columns without a type are left
+/// out, as they are typed value by value.
+pub fn mapping_conflicts<'a>(
+    mapping: &Value,
+    columns: impl IntoIterator<Item = (&'a str, Option<ColumnType>)>,
+) -> Vec<String> {
+    let mut fields = Vec::new();
+    for (index, def) in mapping.as_object().unwrap_or_default() {
+        if let Some(properties) = def.get("mappings").and_then(|m| m.get("properties")) {
+            mapped_fields(index, properties, "", &mut fields);
+        }
+    }
+    let mut conflicts = Vec::new();
+    for (name, ty) in columns {
+        let Some(ty) = ty else {
+            continue;
+        };
+        for (index, field, es_type) in &fields {
+            if field == name && !accepts(es_type, ty) {
+                conflicts.push(format!(
+                    "column {} is written as {}, but index {} maps it as {}",
+                    name, ty, index, es_type
+                ));
+            } else if name
+                .strip_prefix(field.as_str())
+                .is_some_and(|rest| rest.starts_with('.'))
+                && es_type != "object"
+                && es_type != "nested"
+            {
+                conflicts.push(format!(
+                    "column {} goes under {}, but index {} maps that as {}, not an object",
+                    name, field, index, es_type
+                ));
+            }
+        }
+    }
+    conflicts
+}
+
+/// Dotted names of the fields that hold values in a `GET _mapping`
+/// response, in mapping order and once each across indices
+pub fn leaf_fields(mapping: &Value) -> Vec<String> {
+    let mut fields = Vec::new();
+    for (index, def) in mapping.as_object().unwrap_or_default() {
+        if let Some(properties) = def.get("mappings").and_then(|m| m.get("properties")) {
+            mapped_fields(index, properties, "", &mut fields);
+        }
+    }
+    let mut names: Vec<String> = Vec::new();
+    for (_, name, es_type) in fields {
+        if es_type != "object" && es_type != "nested" && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Dotted name and type of each field of a `GET _mapping` response, in
+/// mapping order; a field mapped differently across indices comes once
+/// per type
+pub fn field_types(mapping: &Value) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    for (index, def) in mapping.as_object().unwrap_or_default() {
+        if let Some(properties) = def.get("mappings").and_then(|m| m.get("properties")) {
+            mapped_fields(index, properties, "", &mut fields);
+        }
+    }
+    let mut types: Vec<(String, String)> = Vec::new();
+    for (_, name, es_type) in fields {
+        if !types.iter().any(|(n, t)| *n == name && *t == es_type) {
+            types.push((name, es_type));
+        }
+    }
+    types
+}
+
+/// Dotted names of the fields mapped as dates in a `GET _mapping` response
+pub fn date_fields(mapping: &Value) -> Vec<String> {
+    let mut fields = Vec::new();
+    for (index, def) in mapping.as_object().unwrap_or_default() {
+        if let Some(properties) = def.get("mappings").and_then(|m| m.get("properties")) {
+            mapped_fields(index, properties, "", &mut fields);
+        }
+    }
+    fields
+        .into_iter()
+        .filter(|(_, _, es_type)| es_type == "date" || es_type == "date_nanos")
+        .map(|(_, name, _)| name)
+        .collect()
+}
+
+/// How many fields each index of a `GET _mapping` response maps, objects
+/// included and multi-fields not
+pub fn field_counts(mapping: &Value) -> Vec<(String, usize)> {
+    mapping
+        .as_object()
+        .unwrap_or_default()
+        .iter()
+        .map(|(index, def)| {
+            let mut fields = Vec::new();
+            if let Some(properties) = def.get("mappings").and_then(|m| m.get("properties")) {
+                mapped_fields(index, properties, "", &mut fields);
+            }
+            (index.clone(), fields.len())
+        })
+        .collect()
+}
+
+/// `(index, dotted field name, type)` of every field under `properties`,
+/// objects included; multi-fields are left out
+fn mapped_fields(
+    index: &str,
+    properties: &Value,
+    prefix: &str,
+    out: &mut Vec<(String, String, String)>,
+) {
+    for (name, def) in properties.as_object().unwrap_or_default() {
+        let full = format!("{}{}", prefix, name);
+        let inner = def.get("properties");
+        let es_type = match def.get("type").and_then(Value::as_str) {
+            Some(t) => t,
+            None if inner.is_some() => "object",
+            None => continue,
+        };
+        out.push((index.to_string(), full.clone(), es_type.to_string()));
+        if let Some(inner) = inner {
+            mapped_fields(index, inner, &format!("{}.", full), out);
+        }
+    }
+}
+
+/// Whether a field mapped as `es_type` takes values written as `ty`.
+/// String fields take anything, numbers go into numeric fields as ES
+/// coerces them, and keywords may well be dates; only mismatches that are
+/// sure to be rejected count.
+fn accepts(es_type: &str, ty: ColumnType) -> bool {
+    match es_type {
+        "long" | "integer" | "short" | "byte" | "double" | "float" | "half_float"
+        | "scaled_float" | "unsigned_long" => matches!(
+            ty,
+            ColumnType::Long
+                | ColumnType::UnsignedLong
+                | ColumnType::Double
+                | ColumnType::Duration(_)
+                | ColumnType::Decimal
+                | ColumnType::Object
+        ),
+        "date" | "date_nanos" => matches!(
+            ty,
+            ColumnType::Date | ColumnType::Long | ColumnType::Keyword | ColumnType::Object
+        ),
+        "boolean" => matches!(ty, ColumnType::Bool | ColumnType::Object),
+        "object" | "nested" => ty == ColumnType::Object,
+        "dense_vector" => matches!(ty, ColumnType::DenseVector { .. } | ColumnType::Object),
+        "ip" => matches!(
+            ty,
+            ColumnType::Ip | ColumnType::Keyword | ColumnType::Object
+        ),
+        "geo_point" => matches!(
+            ty,
+            ColumnType::GeoPoint | ColumnType::Keyword | ColumnType::Object
+        ),
+        // WKT strings are taken as they are
+        "geo_shape" | "shape" => matches!(
+            ty,
+            ColumnType::GeoShape | ColumnType::Keyword | ColumnType::Object
+        ),
+        _ => true,
+    }
+}
+
+/// Latitude and longitude of `lat,lon`, if in range
+fn geo_point(s: &str) -> Option<(f64, f64)> {
+    let (lat, lon) = s.split_once(',')?;
+    let lat = f64::from_str(lat.trim()).ok()?;
+    let lon = f64::from_str(lon.trim()).ok()?;
+    ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)).then_some((lat, lon))
+}
+
+/// `$1,299.00` -> `1299.00`: drop commas, whitespace and currency symbols
+pub fn clean_numeric(s: &str) -> String {
+    s.chars()
+        .filter(|&c| {
+            !(c == ','
+                || c.is_whitespace()
+                || matches!(c, '$' | '¢' | '£' | '¤' | '¥' | '\u{20A0}'..='\u{20CF}'))
+        })
+        .collect()
+}
+
+/// `1.5GB` -> `1610612736`, `2.3k` -> `2300`: a number with a size or
+/// count suffix as a plain number, worked out exactly; `None` for values
+/// without one
+pub fn expand_units(s: &str) -> Option<String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let scale: u128 = match unit.trim_start() {
+        "k" | "K" => 1_000,
+        "M" => 1_000_000,
+        "G" => 1_000_000_000,
+        "T" => 1_000_000_000_000,
+        u => match u.to_ascii_lowercase().as_str() {
+            "b" => 1,
+            "kb" | "kib" => 1 << 10,
+            "mb" | "mib" => 1 << 20,
+            "gb" | "gib" => 1 << 30,
+            "tb" | "tib" => 1 << 40,
+            "pb" | "pib" => 1 << 50,
+            _ => return None,
+        },
+    };
+    let (negative, num) = match num.strip_prefix('-') {
+        Some(n) => (true, n),
+        None => (false, num.strip_prefix('+').unwrap_or(num)),
+    };
+    let (int, frac) = num.split_once('.').unwrap_or((num, ""));
+    if !is_decimal(num) || num.starts_with(['+', '-']) || frac.len() > 18 {
+        return None;
+    }
+    // digits of the number as one integer, scaled, then the point put back
+    let digits: u128 = format!("{}{}", int, frac).parse().ok()?;
+    let value = digits.checked_mul(scale)?;
+    let denominator = 10u128.pow(frac.len() as u32);
+    let (whole, rest) = (value / denominator, value % denominator);
+    let mut out = String::new();
+    if negative && value != 0 {
+        out.push('-');
+    }
+    let _ = write!(out, "{}", whole);
+    if rest != 0 {
+        let fraction = format!("{:0width$}", rest, width = frac.len());
+        let _ = write!(out, ".{}", fraction.trim_end_matches('0'));
+    }
+    Some(out)
+}
+
+/// One type per column from sample rows. Columns empty in every sampled row
+/// are `None` and keep per-value detection.
+pub fn infer<'a>(
+    rows: impl IntoIterator<Item = &'a [String]>,
+    width: usize,
+) -> Vec<Option<ColumnType>> {
+    let mut types = vec![None; width];
+    for row in rows {
+        for (ty, value) in types.iter_mut().zip(row) {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            let detected = ColumnType::detect(value);
+            *ty = Some(ty.map_or(detected, |t: ColumnType| t.widen(detected)));
+        }
+    }
+    types
+}
+
+/// Append `s` as JSON of type `ty`, detected from the value itself when
+/// `ty` is `None`. Empty values are null; values that don't fit the type
+/// are written as strings.
+pub fn write_value(out: &mut String, s: &str, ty: Option<ColumnType>) {
+    if s.is_empty() {
+        out.push_str("null");
+        return;
+    }
+    match ty.unwrap_or_else(|| ColumnType::detect(s)) {
+        ColumnType::Long if let Ok(i) = i64::from_str(s) => {
+            let _ = write!(out, "{}", i);
+        }
+        ColumnType::UnsignedLong if let Ok(u) = u64::from_str(s) => {
+            let _ = write!(out, "{}", u);
+        }
+        ColumnType::Double
+            if let Ok(f) = f64::from_str(s)
+                && f.is_finite() =>
+        {
+            let _ = write!(out, "{}", f);
+        }
+        ColumnType::Duration(unit) if let Some(n) = unit.of(s) => {
+            let _ = write!(out, "{}", n);
+        }
+        ColumnType::Date if let Some(dt) = parse_datetime(s) => {
+            out.push('"');
+            out.push_str(&dt.to_rfc3339_opts(SecondsFormat::AutoSi, true));
+            out.push('"');
+        }
+        ColumnType::Object if json::parse(s).is_ok() => out.push_str(s),
+        ColumnType::GeoShape if let Some(geojson) = wkt::to_geojson(s) => out.push_str(&geojson),
+        ColumnType::DenseVector { .. } if let Some(v) = vector(s) => {
+            out.push('[');
+            for (i, f) in v.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let _ = write!(out, "{}", f);
+            }
+            out.push(']');
+        }
+        ColumnType::Bool if s.eq_ignore_ascii_case("true") => out.push_str("true"),
+        ColumnType::Bool if s.eq_ignore_ascii_case("false") => out.push_str("false"),
+        _ => {
+            out.push('"');
+            write_escaped(out, s);
+            out.push('"');
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Rhai scripts for `--script`, run on every document before it is sent
+
+use std::fmt::Write;
+
+use chrono::SecondsFormat;
+use rhai::{AST, Array, Dynamic, Engine, Map, Scope};
+
+use crate::json::{self, Value, write_escaped};
+use crate::schema::{self, ColumnType};
+use crate::{Doc, Nesting, parse_datetime, wkt};
+
+/// A compiled transform. The script sees the document as the map `doc`
+/// and may change it in place or set it to `()` to drop it. If the script
+/// ends in a map or an array of maps, those are indexed instead, which
+/// splits one row into several documents.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Script {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| format!("--script {}: {}", path, e))?;
+        Ok(Self { engine, ast })
+    }
+
+    pub fn run(
+        &self,
+        row: &[(String, String)],
+        types: &[Option<ColumnType>],
+        nesting: Option<&[(String, Nesting)]>,
+    ) -> Result<Vec<Doc>, String> {
+        let doc = match nesting {
+            Some(nesting) => nested_map(nesting, row, types),
+            None => row
+                .iter()
+                .enumerate()
+                .map(|(i, (k, v))| (k.into(), typed(v, types.get(i).copied().flatten())))
+                .collect(),
+        };
+        let mut scope = Scope::new();
+        scope.push("doc", doc);
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| format!("--script: {}", e))?;
+
+        let maps = if result.is_map() {
+            vec![result]
+        } else if result.is_array() {
+            result.cast::<Array>()
+        } else {
+            let doc: Dynamic = scope.get_value("doc").unwrap_or(Dynamic::UNIT);
+            if doc.is_unit() { Vec::new() } else { vec![doc] }
+        };
+        maps.into_iter()
+            .map(|d| {
+                let type_name = d.type_name();
+                let mut map = d
+                    .try_cast::<Map>()
+                    .ok_or_else(|| format!("--script: expected a map, got {}", type_name))?;
+                let mut meta = |key: &str| {
+                    map.remove(key)
+                        .filter(|v| !v.is_unit())
+                        .map(|v| v.to_string())
+                };
+                let (id, index) = (meta("_id"), meta("_index"));
+                let mut json = String::new();
+                write_map(&mut json, &map);
+                Ok(Doc { json, id, index })
+            })
+            .collect()
+    }
+}
+
+fn nested_map(
+    nesting: &[(String, Nesting)],
+    row: &[(String, String)],
+    types: &[Option<ColumnType>],
+) -> Map {
+    nesting
+        .iter()
+        .map(|(k, node)| {
+            let v = match node {
+                Nesting::Field(i) => {
+                    let v = row.get(*i).map_or("", |(_, v)| v.as_str());
+                    typed(v, types.get(*i).copied().flatten())
+                }
+                Nesting::Object(children) => Dynamic::from_map(nested_map(children, row, types)),
+            };
+            (k.into(), v)
+        })
+        .collect()
+}
+
+/// A cell as the script sees it: typed the way it would be written
+fn typed(s: &str, ty: Option<ColumnType>) -> Dynamic {
+    if s.is_empty() {
+        return Dynamic::UNIT;
+    }
+    match ty.unwrap_or_else(|| ColumnType::detect(s)) {
+        ColumnType::Long | ColumnType::UnsignedLong if let Ok(i) = s.parse::<i64>() => {
+            Dynamic::from(i)
+        }
+        ColumnType::Double if let Ok(f) = s.parse::<f64>() => Dynamic::from(f),
+        ColumnType::Duration(unit) if let Some(n) = unit.of(s) => Dynamic::from(n),
+        ColumnType::Bool if s.eq_ignore_ascii_case("true") => Dynamic::from(true),
+        ColumnType::Bool if s.eq_ignore_ascii_case("false") => Dynamic::from(false),
+        ColumnType::Date if let Some(dt) = parse_datetime(s) => {
+            Dynamic::from(dt.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        }
+        ColumnType::Object if let Ok(v) = json::parse(s) => from_json(v),
+        ColumnType::DenseVector { .. } if let Some(v) = schema::vector(s) => {
+            Dynamic::from_array(v.into_iter().map(Dynamic::from).collect())
+        }
+        ColumnType::GeoShape
+            if let Some(v) = wkt::to_geojson(s).and_then(|g| json::parse(&g).ok()) =>
+        {
+            from_json(v)
+        }
+        _ => Dynamic::from(s.to_string()),
+    }
+}
+
+fn from_json(v: Value) -> Dynamic {
+    match v {
+        Value::Null => Dynamic::UNIT,
+        Value::Bool(b) => Dynamic::from(b),
+        Value::Number(n) => match n.parse::<i64>() {
+            Ok(i) => Dynamic::from(i),
+            Err(_) => n
+                .parse::<f64>()
+                .map_or_else(|_| Dynamic::from(n), Dynamic::from),
+        },
+        Value::String(s) => Dynamic::from(s),
+        Value::Array(items) => Dynamic::from_array(items.into_iter().map(from_json).collect()),
+        Value::Object(fields) => Dynamic::from_map(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k.into(), from_json(v)))
+                .collect(),
+        ),
+    }
+}
+
+fn write_map(out: &mut String, map: &Map) {
+    out.push('{');
+    for (i, (k, v)) in map.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push('"');
+        write_escaped(out, k);
+        out.push_str("\":");
+        write_dynamic(out, v);
+    }
+    out.push('}');
+}
+
+fn write_dynamic(out: &mut String, v: &Dynamic) {
+    if v.is_unit() {
+        out.push_str("null");
+    } else if let Ok(b) = v.as_bool() {
+        let _ = write!(out, "{}", b);
+    } else if let Ok(i) = v.as_int() {
+        let _ = write!(out, "{}", i);
+    } else if let Ok(f) = v.as_float() {
+        if f.is_finite() {
+            let _ = write!(out, "{}", f);
+        } else {
+            out.push_str("null");
+        }
+    } else if v.is_map() {
+        if let Some(map) = v.read_lock::<Map>() {
+            write_map(out, &map);
+        }
+    } else if v.is_array() {
+        if let Some(items) = v.read_lock::<Array>() {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_dynamic(out, item);
+            }
+            out.push(']');
+        }
+    } else {
+        out.push('"');
+        write_escaped(out, &v.to_string());
+        out.push('"');
+    }
+}
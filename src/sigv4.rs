@@ -0,0 +1,515 @@
+//! AWS Signature Version 4 request signing for Amazon OpenSearch Service
+
+use std::env;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::json::{self, Value};
+use crate::{HttpTarget, http_exchange};
+
+/// AWS access key, possibly temporary
+#[derive(Clone)]
+struct Credentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    /// Set for temporary credentials from the container or instance role
+    expires: Option<DateTime<Utc>>,
+}
+
+/// Signs requests for one region and service, refreshing temporary
+/// credentials before they expire
+pub struct Signer {
+    region: String,
+    service: String,
+    credentials: Mutex<Credentials>,
+}
+
+impl Signer {
+    pub fn new(region: &str, service: &str) -> Result<Self, String> {
+        Ok(Self {
+            region: region.to_string(),
+            service: service.to_string(),
+            credentials: Mutex::new(load_credentials()?),
+        })
+    }
+
+    /// Headers to add to a request: X-Amz-Date, X-Amz-Content-Sha256,
+    /// X-Amz-Security-Token and Authorization
+    pub fn sign(
+        &self,
+        method: &str,
+        host: &str,
+        path_and_query: &str,
+        body: &str,
+    ) -> Result<Vec<(String, String)>, String> {
+        let creds = {
+            let mut creds = self.credentials.lock().unwrap();
+            if creds
+                .expires
+                .is_some_and(|e| e - Utc::now() < TimeDelta::minutes(5))
+            {
+                *creds = load_credentials()?;
+            }
+            creds.clone()
+        };
+        Ok(self.sign_with(&creds, Utc::now(), method, host, path_and_query, body))
+    }
+
+    fn sign_with(
+        &self,
+        creds: &Credentials,
+        now: DateTime<Utc>,
+        method: &str,
+        host: &str,
+        path_and_query: &str,
+        body: &str,
+    ) -> Vec<(String, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+        let payload_hash = hex(&Sha256::digest(body.as_bytes()));
+
+        let mut headers = vec![
+            ("host".to_string(), host.to_string()),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ];
+        if let Some(token) = &creds.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        let (canonical_request, signed_headers) =
+            canonical_request(method, path_and_query, &headers, &payload_hash);
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = string_to_sign(&amz_date, &scope, &canonical_request);
+        let signature = signature(
+            &creds.secret_key,
+            date,
+            &self.region,
+            &self.service,
+            &string_to_sign,
+        );
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            creds.access_key, scope, signed_headers, signature
+        );
+        // host is sent separately by the caller
+        let mut out: Vec<(String, String)> = headers.into_iter().skip(1).collect();
+        out.push(("authorization".to_string(), authorization));
+        out
+    }
+}
+
+/// The canonical form of a request with `headers` (lowercase names, in
+/// order), and the list of their names
+fn canonical_request(
+    method: &str,
+    path_and_query: &str,
+    headers: &[(String, String)],
+    payload_hash: &str,
+) -> (String, String) {
+    let (path, query) = path_and_query
+        .split_once('?')
+        .unwrap_or((path_and_query, ""));
+    let path = if path.is_empty() { "/" } else { path };
+    // non-S3 services expect each path segment encoded twice
+    let canonical_uri = path
+        .split('/')
+        .map(|seg| uri_encode(&uri_encode(seg)))
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut params: Vec<(String, String)> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=').unwrap_or((p, ""));
+            (uri_encode(k), uri_encode(v))
+        })
+        .collect();
+    params.sort();
+    let canonical_query = params
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, canonical_uri, canonical_query, canonical_headers, signed_headers, payload_hash
+    );
+    (canonical_request, signed_headers)
+}
+
+fn string_to_sign(amz_date: &str, scope: &str, canonical_request: &str) -> String {
+    format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    )
+}
+
+/// Signature of `string_to_sign`, with the key derived for the date, region
+/// and service
+fn signature(
+    secret_key: &str,
+    date: &str,
+    region: &str,
+    service: &str,
+    string_to_sign: &str,
+) -> String {
+    let k_date = hmac(format!("AWS4{}", secret_key).as_bytes(), date);
+    let k_region = hmac(&k_date, region);
+    let k_service = hmac(&k_region, service);
+    let k_signing = hmac(&k_service, "aws4_request");
+    hex(&hmac(&k_signing, string_to_sign))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encode everything except unreserved characters
+fn uri_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Standard credential chain: environment, shared credentials file,
+/// container credentials, then the EC2 instance role
+fn load_credentials() -> Result<Credentials, String> {
+    if let (Ok(access_key), Ok(secret_key)) = (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        return Ok(Credentials {
+            access_key,
+            secret_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            expires: None,
+        });
+    }
+    if let Some(creds) = from_credentials_file()? {
+        return Ok(creds);
+    }
+    if let Some(creds) = from_container()? {
+        return Ok(creds);
+    }
+    from_instance_metadata().map_err(|e| format!("no AWS credentials found ({})", e))
+}
+
+/// `[profile]` section of ~/.aws/credentials
+fn from_credentials_file() -> Result<Option<Credentials>, String> {
+    let path = match env::var("AWS_SHARED_CREDENTIALS_FILE") {
+        Ok(p) => p,
+        Err(_) => match env::var("HOME") {
+            Ok(home) => format!("{}/.aws/credentials", home),
+            Err(_) => return Ok(None),
+        },
+    };
+    let Ok(text) = std::fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| "default".into());
+    match parse_credentials_file(&text, &profile) {
+        Some(creds) => Ok(Some(creds)),
+        None if env::var("AWS_PROFILE").is_ok() => {
+            Err(format!("profile {} not found in {}", profile, path))
+        }
+        None => Ok(None),
+    }
+}
+
+/// The keys of section `[profile]` of a credentials file
+fn parse_credentials_file(text: &str, profile: &str) -> Option<Credentials> {
+    let mut section = String::new();
+    let (mut access_key, mut secret_key, mut session_token) = (None, None, None);
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+        if section != profile {
+            continue;
+        }
+        if let Some((k, v)) = line.split_once('=') {
+            let v = Some(v.trim().to_string());
+            match k.trim() {
+                "aws_access_key_id" => access_key = v,
+                "aws_secret_access_key" => secret_key = v,
+                "aws_session_token" => session_token = v,
+                _ => {}
+            }
+        }
+    }
+    Some(Credentials {
+        access_key: access_key?,
+        secret_key: secret_key?,
+        session_token,
+        expires: None,
+    })
+}
+
+/// ECS/EKS container credentials endpoint
+fn from_container() -> Result<Option<Credentials>, String> {
+    let (target, path) = if let Ok(uri) = env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+        (metadata_target("169.254.170.2"), uri)
+    } else if let Ok(url) = env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI") {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or("AWS_CONTAINER_CREDENTIALS_FULL_URI must be http://")?;
+        let (host_port, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (host, port) = match host_port.split_once(':') {
+            Some((h, p)) => (
+                h,
+                p.parse()
+                    .map_err(|_| "invalid container credentials port")?,
+            ),
+            None => (host_port, 80),
+        };
+        let mut target = metadata_target(host);
+        target.port = port;
+        (target, format!("/{}", path))
+    } else {
+        return Ok(None);
+    };
+    let mut headers = Vec::new();
+    if let Ok(token) = env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN") {
+        headers.push(("Authorization".to_string(), token));
+    }
+    let resp = http_exchange(&target, "GET", &path, &headers, "")?;
+    if resp.status != 200 {
+        return Err(format!("container credentials: HTTP {}", resp.status));
+    }
+    parse_temporary(&resp.body).map(Some)
+}
+
+/// EC2 instance role via IMDSv2
+fn from_instance_metadata() -> Result<Credentials, String> {
+    let target = metadata_target("169.254.169.254");
+    let ttl = [(
+        "X-aws-ec2-metadata-token-ttl-seconds".to_string(),
+        "21600".to_string(),
+    )];
+    let resp = http_exchange(&target, "PUT", "/latest/api/token", &ttl, "")?;
+    if resp.status != 200 {
+        return Err(format!("instance metadata token: HTTP {}", resp.status));
+    }
+    let token = [("X-aws-ec2-metadata-token".to_string(), resp.body)];
+
+    let base = "/latest/meta-data/iam/security-credentials/";
+    let resp = http_exchange(&target, "GET", base, &token, "")?;
+    let role = resp.body.lines().next().unwrap_or("").trim().to_string();
+    if resp.status != 200 || role.is_empty() {
+        return Err("instance has no IAM role".into());
+    }
+    let resp = http_exchange(&target, "GET", &format!("{}{}", base, role), &token, "")?;
+    if resp.status != 200 {
+        return Err(format!("instance role credentials: HTTP {}", resp.status));
+    }
+    parse_temporary(&resp.body)
+}
+
+fn metadata_target(host: &str) -> HttpTarget {
+    HttpTarget {
+        host: host.to_string(),
+        port: 80,
+        base_path: String::new(),
+        tls: None,
+        timeout: Some(Duration::from_secs(2)),
+        debug: false,
+        user_agent: crate::USER_AGENT.to_string(),
+        opaque_id: None,
+    }
+}
+
+/// {"AccessKeyId": ..., "SecretAccessKey": ..., "Token": ..., "Expiration": ...}
+fn parse_temporary(body: &str) -> Result<Credentials, String> {
+    let v = json::parse(body)?;
+    let field = |k: &str| v.get(k).and_then(Value::as_str).map(str::to_string);
+    Ok(Credentials {
+        access_key: field("AccessKeyId").ok_or("credentials response lacks AccessKeyId")?,
+        secret_key: field("SecretAccessKey").ok_or("credentials response lacks SecretAccessKey")?,
+        session_token: field("Token"),
+        expires: field("Expiration")
+            .and_then(|e| DateTime::parse_from_rfc3339(&e).ok())
+            .map(|e| e.with_timezone(&Utc)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from the AWS Signature Version 4 test suite
+    const SECRET: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+    const AMZ_DATE: &str = "20150830T123600Z";
+    const SCOPE: &str = "20150830/us-east-1/service/aws4_request";
+    const EMPTY_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn suite_headers() -> Vec<(String, String)> {
+        vec![
+            ("host".to_string(), "example.amazonaws.com".to_string()),
+            ("x-amz-date".to_string(), AMZ_DATE.to_string()),
+        ]
+    }
+
+    /// Canonical request, string to sign and signature of a suite request
+    fn sign_suite(method: &str, path_and_query: &str) -> (String, String, String) {
+        let (canonical, signed) =
+            canonical_request(method, path_and_query, &suite_headers(), EMPTY_HASH);
+        assert_eq!(signed, "host;x-amz-date");
+        let to_sign = string_to_sign(AMZ_DATE, SCOPE, &canonical);
+        let sig = signature(SECRET, "20150830", "us-east-1", "service", &to_sign);
+        (canonical, to_sign, sig)
+    }
+
+    #[test]
+    fn get_vanilla() {
+        let (canonical, to_sign, sig) = sign_suite("GET", "/");
+        assert_eq!(
+            canonical,
+            "GET\n/\n\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\n\
+             host;x-amz-date\n\
+             e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            to_sign,
+            "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/service/aws4_request\n\
+             bb579772317eb040ac9ed261061d46c1f17a8133879d6129b6e1c25292927e63"
+        );
+        assert_eq!(
+            sig,
+            "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn get_vanilla_empty_path() {
+        assert_eq!(sign_suite("GET", ""), sign_suite("GET", "/"));
+    }
+
+    #[test]
+    fn get_vanilla_query_order_key_case() {
+        let (canonical, _, sig) = sign_suite("GET", "/?Param2=value2&Param1=value1");
+        assert!(canonical.starts_with("GET\n/\nParam1=value1&Param2=value2\n"));
+        assert_eq!(
+            sig,
+            "b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+        );
+    }
+
+    #[test]
+    fn post_vanilla() {
+        let (canonical, to_sign, sig) = sign_suite("POST", "/");
+        assert!(canonical.starts_with("POST\n/\n\n"));
+        assert!(
+            to_sign.ends_with("\n553f88c9e4d10fc9e109e2aeb65f030801b70c2f6468faca261d401ae622fc87")
+        );
+        assert_eq!(
+            sig,
+            "5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b"
+        );
+    }
+
+    #[test]
+    fn signs_the_payload_hash_and_session_token() {
+        let signer = Signer {
+            region: "us-east-1".to_string(),
+            service: "es".to_string(),
+            credentials: Mutex::new(Credentials {
+                access_key: "AKIDEXAMPLE".to_string(),
+                secret_key: SECRET.to_string(),
+                session_token: None,
+                expires: None,
+            }),
+        };
+        let mut creds = signer.credentials.lock().unwrap().clone();
+        creds.session_token = Some("token".to_string());
+        let now = DateTime::parse_from_rfc3339("2015-08-30T12:36:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let headers = signer.sign_with(&creds, now, "POST", "search.example.com", "/_bulk", "{}\n");
+        let names: Vec<&str> = headers.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "x-amz-content-sha256",
+                "x-amz-date",
+                "x-amz-security-token",
+                "authorization"
+            ]
+        );
+        assert_eq!(headers[0].1, hex(&Sha256::digest(b"{}\n")));
+        assert_eq!(headers[1].1, AMZ_DATE);
+        assert!(headers[3].1.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/es/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token, \
+             Signature="
+        ));
+    }
+
+    #[test]
+    fn path_segments_are_encoded_twice() {
+        let (canonical, _) = canonical_request("GET", "/my index/_doc/a:b", &[], EMPTY_HASH);
+        assert!(canonical.starts_with("GET\n/my%2520index/_doc/a%253Ab\n"));
+    }
+
+    #[test]
+    fn reads_a_profile_from_the_credentials_file() {
+        let text = "\
+[default]
+aws_access_key_id = AKIDDEFAULT
+aws_secret_access_key = default-secret
+
+[ work ]
+aws_access_key_id=AKIDWORK
+aws_secret_access_key =work-secret
+aws_session_token = work-token
+region = eu-west-1
+
+[incomplete]
+aws_access_key_id = AKIDHALF
+";
+        let default = parse_credentials_file(text, "default").unwrap();
+        assert_eq!(default.access_key, "AKIDDEFAULT");
+        assert_eq!(default.secret_key, "default-secret");
+        assert_eq!(default.session_token, None);
+        let work = parse_credentials_file(text, "work").unwrap();
+        assert_eq!(
+            (work.access_key.as_str(), work.secret_key.as_str()),
+            ("AKIDWORK", "work-secret")
+        );
+        assert_eq!(work.session_token.as_deref(), Some("work-token"));
+        assert!(parse_credentials_file(text, "incomplete").is_none());
+        assert!(parse_credentials_file(text, "missing").is_none());
+    }
+}
@@ -0,0 +1,162 @@
+//! `--sniff`: the delimiter, quoting, header and line endings of a CSV
+//! file, guessed from its first few kilobytes
+
+use std::fmt;
+
+use crate::schema::{self, ColumnType};
+use crate::{CsvReader, Input, RecordFormat};
+
+/// How much of the input is looked at
+const SAMPLE_BYTES: usize = 16 * 1024;
+/// How many records of it are compared
+const SAMPLE_RECORDS: usize = 50;
+const DELIMITERS: &[u8] = b",;\t|";
+const QUOTES: &[u8] = b"\"'";
+
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    /// Whether the first record names the columns
+    pub header: bool,
+    pub terminator: &'static str,
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |b: u8| match b {
+            b'\t' => "tab".to_string(),
+            b => format!("'{}'", b as char),
+        };
+        write!(
+            f,
+            "delimiter {}, quote {}, ",
+            name(self.delimiter),
+            name(self.quote)
+        )?;
+        let terminator = match self.terminator {
+            "\r\n" => "CRLF",
+            "\r" => "CR",
+            _ => "LF",
+        };
+        let header = match self.header {
+            true => "a header line",
+            false => "no header line",
+        };
+        write!(f, "{} line endings, {}", terminator, header)
+    }
+}
+
+/// The dialect of the CSV `input` seems to be written in
+pub fn sniff(input: &[u8], comment: Option<u8>) -> Dialect {
+    let mut sample = &input[..input.len().min(SAMPLE_BYTES)];
+    // a cut-off last line would look ragged, and could split a character
+    if sample.len() < input.len()
+        && let Some(end) = sample.iter().rposition(|&b| b == b'\n')
+    {
+        sample = &sample[..end + 1];
+    }
+    let terminator = match sample.iter().position(|&b| b == b'\n' || b == b'\r') {
+        Some(i) if sample[i] == b'\r' && sample.get(i + 1) == Some(&b'\n') => "\r\n",
+        Some(i) if sample[i] == b'\r' => "\r",
+        _ => "\n",
+    };
+    let quote = guess_quote(sample);
+    let delimiter = DELIMITERS
+        .iter()
+        .map(|&d| (d, consistency(&records(sample, comment, d, quote))))
+        // the most records with the usual field count, then the most
+        // fields; ties go to the earlier delimiter
+        .fold((b',', (0, 1)), |best, (d, score)| match score > best.1 {
+            true => (d, score),
+            false => best,
+        })
+        .0;
+    let rows = records(sample, comment, delimiter, quote);
+    Dialect {
+        delimiter,
+        quote,
+        header: has_header(&rows),
+        terminator,
+    }
+}
+
+/// The quote character found opening and closing the most fields
+fn guess_quote(sample: &[u8]) -> u8 {
+    let edge =
+        |b: Option<&u8>| b.is_none_or(|b| DELIMITERS.contains(b) || matches!(b, b'\n' | b'\r'));
+    let opened = |q: u8| {
+        (0..sample.len())
+            .filter(|&i| sample[i] == q && edge(i.checked_sub(1).map(|j| &sample[j])))
+            .count()
+    };
+    let closed = |q: u8| {
+        (0..sample.len())
+            .filter(|&i| sample[i] == q && edge(sample.get(i + 1)))
+            .count()
+    };
+    // ties go to the first, as max_by_key keeps the last
+    let counts = QUOTES.iter().rev().map(|&q| (q, opened(q).min(closed(q))));
+    match counts.max_by_key(|&(_, n)| n) {
+        Some((q, n)) if n > 0 => q,
+        // nothing quoted, so the usual quote does no harm
+        _ => b'"',
+    }
+}
+
+fn records(sample: &[u8], comment: Option<u8>, delimiter: u8, quote: u8) -> Vec<Vec<String>> {
+    let Ok(mut rdr) = CsvReader::new(Input::Owned(sample.to_vec()), comment, RecordFormat::Csv)
+    else {
+        return Vec::new();
+    };
+    rdr.delimiter = delimiter;
+    rdr.quote = quote;
+    let mut rows = Vec::new();
+    while rows.len() < SAMPLE_RECORDS
+        && let Ok(Some(rec)) = rdr.next_record()
+    {
+        if rec.len() > 1 || rec.first().is_some_and(|f| !f.is_empty()) {
+            rows.push(rec);
+        }
+    }
+    rows
+}
+
+/// (records with the most common field count, that count); one field
+/// scores nothing, as any delimiter not in the data gives that
+fn consistency(rows: &[Vec<String>]) -> (usize, usize) {
+    let mut counts: Vec<(usize, usize)> = Vec::new();
+    for row in rows {
+        match counts.iter_mut().find(|(n, _)| *n == row.len()) {
+            Some((_, seen)) => *seen += 1,
+            None => counts.push((row.len(), 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|&(n, _)| n > 1)
+        .map(|(n, seen)| (seen, n))
+        .max()
+        .unwrap_or((0, 1))
+}
+
+/// Whether the first row reads as column names: it doesn't fit the type
+/// the rows under it share, in more columns than it does. Files of text
+/// alone are taken to have a header.
+fn has_header(rows: &[Vec<String>]) -> bool {
+    let Some((first, rest)) = rows.split_first() else {
+        return true;
+    };
+    if rest.is_empty() {
+        return true;
+    }
+    let types = schema::infer(rest.iter().map(Vec::as_slice), first.len());
+    let mut votes = 0i64;
+    for (name, ty) in first.iter().zip(types) {
+        match ty {
+            Some(ColumnType::Keyword) | None => {}
+            Some(ty) if ty.fits(name.trim()) && !name.trim().is_empty() => votes -= 1,
+            Some(_) => votes += 1,
+        }
+    }
+    votes >= 0
+}
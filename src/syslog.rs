@@ -0,0 +1,204 @@
+//! RFC 3164 and RFC 5424 syslog lines for `--format syslog`
+
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeDelta, Utc};
+
+/// Fields of every parsed line, in column order
+pub const FIELDS: &[&str] = &[
+    "timestamp",
+    "host",
+    "facility",
+    "severity",
+    "appname",
+    "procid",
+    "msgid",
+    "structured_data",
+    "message",
+];
+
+const FACILITIES: &[&str] = &[
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+const SEVERITIES: &[&str] = &[
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// Split one line into `FIELDS`, or `None` if it isn't a syslog message.
+/// The `<PRI>` is optional, as files written by syslog daemons usually
+/// leave it out. RFC 3164 timestamps carry no year or zone; they are taken
+/// to be in the year before `now` if they would otherwise lie more than a
+/// day ahead of it, and written without an offset.
+pub fn parse(line: &str, now: DateTime<Utc>) -> Option<Vec<String>> {
+    let (facility, severity, rest) = priority(line)?;
+    let mut fields = match rest.strip_prefix("1 ") {
+        Some(rest) => rfc5424(rest)?,
+        None => rfc3164(rest, now)?,
+    };
+    fields[2] = facility;
+    fields[3] = severity;
+    Some(fields)
+}
+
+/// `<PRI>` as facility and severity names, if present
+fn priority(line: &str) -> Option<(String, String, &str)> {
+    let Some(rest) = line.strip_prefix('<') else {
+        return Some((String::new(), String::new(), line));
+    };
+    let (pri, rest) = rest.split_once('>')?;
+    if pri.is_empty() || pri.len() > 3 || !pri.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let pri: usize = pri.parse().ok()?;
+    let facility = FACILITIES.get(pri / 8)?;
+    Some((facility.to_string(), SEVERITIES[pri % 8].to_string(), rest))
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]` after
+/// the version, `-` standing for an absent value
+fn rfc5424(rest: &str) -> Option<Vec<String>> {
+    let mut parts = rest.splitn(6, ' ');
+    let mut header = [""; 5];
+    for h in &mut header {
+        *h = parts.next()?;
+    }
+    let rest = parts.next().unwrap_or("");
+    let (structured_data, message) = structured_data(rest)?;
+    let nil = |s: &str| {
+        if s == "-" {
+            String::new()
+        } else {
+            s.to_string()
+        }
+    };
+    let timestamp = match header[0] {
+        "-" => String::new(),
+        ts => {
+            DateTime::parse_from_rfc3339(ts).ok()?;
+            ts.to_string()
+        }
+    };
+    // a UTF-8 message may start with a byte order mark
+    let message = message.strip_prefix('\u{feff}').unwrap_or(message);
+    Some(vec![
+        timestamp,
+        nil(header[1]),
+        String::new(),
+        String::new(),
+        nil(header[2]),
+        nil(header[3]),
+        nil(header[4]),
+        structured_data.to_string(),
+        message.to_string(),
+    ])
+}
+
+/// `-` or `[id k="v" ...]...`, then the message
+fn structured_data(s: &str) -> Option<(&str, &str)> {
+    if let Some(rest) = s.strip_prefix('-') {
+        return Some(("", rest.strip_prefix(' ').unwrap_or(rest)));
+    }
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while bytes.get(i) == Some(&b'[') {
+        let mut in_quotes = false;
+        loop {
+            i += 1;
+            match bytes.get(i)? {
+                b'\\' if in_quotes => i += 1,
+                b'"' => in_quotes = !in_quotes,
+                b']' if !in_quotes => break,
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    if i == 0 {
+        return None;
+    }
+    let rest = &s[i..];
+    Some((&s[..i], rest.strip_prefix(' ').unwrap_or(rest)))
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`, or with an RFC 3339
+/// timestamp as some daemons write
+fn rfc3164(rest: &str, now: DateTime<Utc>) -> Option<Vec<String>> {
+    let (timestamp, rest) = match rest.split_once(' ') {
+        Some((ts, rest)) if DateTime::parse_from_rfc3339(ts).is_ok() => (ts.to_string(), rest),
+        _ => {
+            let ts = rest.get(..15)?;
+            (bsd_timestamp(ts, now)?, &rest[15..])
+        }
+    };
+    let rest = rest.trim_start_matches(' ');
+    let (host, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    let (appname, procid, message) = tag(rest);
+    Some(vec![
+        timestamp,
+        host.to_string(),
+        String::new(),
+        String::new(),
+        appname.to_string(),
+        procid.to_string(),
+        String::new(),
+        String::new(),
+        message.to_string(),
+    ])
+}
+
+/// `Oct  1 22:14:15` in the latest year that isn't more than a day ahead
+/// of `now`
+fn bsd_timestamp(ts: &str, now: DateTime<Utc>) -> Option<String> {
+    let year = now.year();
+    let at = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", year, ts), "%Y %b %e %H:%M:%S").ok()
+    };
+    // Feb 29 only parses in a leap year
+    let mut dt = at(year).or_else(|| at(year - 1))?;
+    if dt > now.naive_utc() + TimeDelta::days(1) {
+        dt = at(year - 1)?;
+    }
+    Some(dt.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+/// `app[123]: message` or `app: message`; a message without a tag keeps
+/// the whole text
+fn tag(s: &str) -> (&str, &str, &str) {
+    let end = s
+        .find(|c: char| c == ':' || c == '[' || c.is_whitespace())
+        .unwrap_or(s.len());
+    let (app, rest) = s.split_at(end);
+    if app.is_empty() {
+        return ("", "", s);
+    }
+    let (procid, rest) = match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+        Some((pid, rest)) => (pid, rest),
+        None => ("", rest),
+    };
+    match rest.strip_prefix(':') {
+        Some(message) => (app, procid, message.trim_start_matches(' ')),
+        None => ("", "", s),
+    }
+}
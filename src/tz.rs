@@ -0,0 +1,499 @@
+//! Time zones for `--input-tz` and `--tz-column`: UTC, fixed offsets like
+//! +05:30, and IANA names like America/New_York read from the system's
+//! TZif files
+
+use std::env;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Utc};
+
+#[derive(Clone)]
+pub enum Zone {
+    /// Seconds east of UTC
+    Fixed(i64),
+    Rules {
+        /// UTC times at which `offsets[index]` takes over, ascending
+        transitions: Vec<(i64, usize)>,
+        offsets: Vec<i64>,
+        /// What follows the last transition
+        after: Option<PosixRule>,
+    },
+}
+
+/// The TZ string ending a TZif file, e.g. EST5EDT,M3.2.0,M11.1.0
+#[derive(Clone)]
+pub struct PosixRule {
+    std: i64,
+    /// Daylight saving offset and when it starts and ends, in local time
+    dst: Option<(i64, RuleDate, RuleDate)>,
+}
+
+/// Day of the year a rule switches on, and the local time it does so at
+#[derive(Clone, Copy)]
+struct RuleDate {
+    day: DaySpec,
+    secs: i64,
+}
+
+#[derive(Clone, Copy)]
+enum DaySpec {
+    /// Jn: day 1 to 365, February 29 never counted
+    Julian(u32),
+    /// n: day 0 to 365
+    Ordinal(u32),
+    /// Mm.w.d: weekday d (0 = Sunday) of week w (5 = last) of month m
+    Month { month: u32, week: u32, weekday: u32 },
+}
+
+impl Zone {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        if let Some(offset) = fixed_offset(name) {
+            return Ok(Zone::Fixed(offset));
+        }
+        if name.is_empty() || name.starts_with('/') || name.split('/').any(|p| p == "..") {
+            return Err(format!("unknown time zone {}", name));
+        }
+        let dir = env::var_os("TZDIR").map_or_else(|| "/usr/share/zoneinfo".into(), PathBuf::from);
+        let data =
+            std::fs::read(dir.join(name)).map_err(|_| format!("unknown time zone {}", name))?;
+        parse_tzif(&data).ok_or_else(|| format!("cannot read the time zone file of {}", name))
+    }
+
+    /// The instant a local date-time of this zone stands for; in a gap or
+    /// overlap of a clock change, one of the offsets around it is used
+    pub fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let l = local.and_utc().timestamp();
+        let guess = self.offset_at(l);
+        let offset = match self.offset_at(l - guess) {
+            o if self.offset_at(l - o) == o => o,
+            // no offset fits a time skipped by the change
+            _ => guess,
+        };
+        DateTime::from_timestamp(l - offset, local.and_utc().timestamp_subsec_nanos())
+            .unwrap_or_else(|| local.and_utc())
+    }
+
+    /// Offset at UTC time `t`
+    fn offset_at(&self, t: i64) -> i64 {
+        match self {
+            Zone::Fixed(offset) => *offset,
+            Zone::Rules {
+                transitions,
+                offsets,
+                after,
+            } => {
+                let n = transitions.partition_point(|&(at, _)| at <= t);
+                match (n, after) {
+                    (n, Some(rule)) if n == transitions.len() => rule.offset_at(t),
+                    (0, _) => offsets.first().copied().unwrap_or(0),
+                    (n, _) => offsets[transitions[n - 1].1],
+                }
+            }
+        }
+    }
+}
+
+impl PosixRule {
+    fn offset_at(&self, t: i64) -> i64 {
+        let Some((dst, start, end)) = self.dst else {
+            return self.std;
+        };
+        let Some(year) = DateTime::from_timestamp(t + self.std, 0).map(|d| d.year()) else {
+            return self.std;
+        };
+        let (Some(start), Some(end)) = (start.local(year), end.local(year)) else {
+            return self.std;
+        };
+        // the start is given in standard time and the end in daylight time
+        let (start, end) = (start - self.std, end - dst);
+        let in_dst = if start < end {
+            start <= t && t < end
+        } else {
+            // southern hemisphere: daylight time spans the new year
+            !(end <= t && t < start)
+        };
+        if in_dst { dst } else { self.std }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let mut rest = s;
+        zone_name(&mut rest)?;
+        // POSIX offsets count west of UTC
+        let std = -posix_time(&mut rest)?;
+        if rest.is_empty() {
+            return Some(Self { std, dst: None });
+        }
+        zone_name(&mut rest)?;
+        let dst = match rest.starts_with(',') {
+            true => std + 3600,
+            false => -posix_time(&mut rest)?,
+        };
+        let mut rules = rest.strip_prefix(',')?.split(',');
+        let start = RuleDate::parse(rules.next()?)?;
+        let end = RuleDate::parse(rules.next()?)?;
+        Some(Self {
+            std,
+            dst: Some((dst, start, end)),
+        })
+    }
+}
+
+impl RuleDate {
+    fn parse(s: &str) -> Option<Self> {
+        let (day, time) = s.split_once('/').unwrap_or((s, "2"));
+        let mut time = time;
+        let secs = posix_time(&mut time)?;
+        let day = if let Some(m) = day.strip_prefix('M') {
+            let mut parts = m.split('.').map(|p| p.parse::<u32>().ok());
+            let (month, week, weekday) = (parts.next()??, parts.next()??, parts.next()??);
+            DaySpec::Month {
+                month,
+                week,
+                weekday,
+            }
+        } else if let Some(n) = day.strip_prefix('J') {
+            DaySpec::Julian(n.parse().ok()?)
+        } else {
+            DaySpec::Ordinal(day.parse().ok()?)
+        };
+        Some(Self { day, secs })
+    }
+
+    /// Local time of the switch in `year`, in seconds as if it were UTC
+    fn local(self, year: i32) -> Option<i64> {
+        let date = match self.day {
+            DaySpec::Julian(n) => {
+                let date = NaiveDate::from_yo_opt(year, n)?;
+                // February 29 isn't counted
+                match date.leap_year() && n >= 60 {
+                    true => date.succ_opt()?,
+                    false => date,
+                }
+            }
+            DaySpec::Ordinal(n) => NaiveDate::from_yo_opt(year, n + 1)?,
+            DaySpec::Month {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+                let shift = (weekday + 7 - first.weekday().num_days_from_sunday()) % 7;
+                let mut date = first + chrono::Days::new((shift + 7 * (week - 1)) as u64);
+                while date.month() != month {
+                    date = date - chrono::Days::new(7);
+                }
+                date
+            }
+        };
+        Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp() + self.secs)
+    }
+}
+
+/// UTC, GMT, Z or an offset like +05:30, +0530 or -08, in seconds
+fn fixed_offset(s: &str) -> Option<i64> {
+    if ["UTC", "GMT", "Z"].contains(&s) {
+        return Some(0);
+    }
+    let sign = match s.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits: String = s[1..].chars().filter(|&c| c != ':').collect();
+    if !matches!(digits.len(), 2 | 4) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = match digits.len() {
+        2 => 0,
+        _ => digits[2..].parse().ok()?,
+    };
+    (hours <= 14 && minutes < 60).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Take an abbreviation like EST or <+0530> off the front of `s`
+fn zone_name<'a>(s: &mut &'a str) -> Option<&'a str> {
+    let (name, rest) = match s.strip_prefix('<') {
+        Some(quoted) => {
+            let (name, rest) = quoted.split_once('>')?;
+            (name, rest)
+        }
+        None => {
+            let end = s
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(s.len());
+            s.split_at(end)
+        }
+    };
+    *s = rest;
+    (!name.is_empty()).then_some(name)
+}
+
+/// Take a [+-]hh[:mm[:ss]] time off the front of `s`, in seconds
+fn posix_time(s: &mut &str) -> Option<i64> {
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'-' => (-1, &s[1..]),
+        b'+' => (1, &s[1..]),
+        _ => (1, *s),
+    };
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(rest.len());
+    let mut secs = 0;
+    for (part, scale) in rest[..end].split(':').zip([3600, 60, 1]) {
+        secs += part.parse::<i64>().ok()? * scale;
+    }
+    *s = &rest[end..];
+    Some(sign * secs)
+}
+
+/// Zone of a TZif file (RFC 8536), from its 64-bit data when it has them
+fn parse_tzif(data: &[u8]) -> Option<Zone> {
+    let header = |at: usize| -> Option<[usize; 6]> {
+        if data.get(at..at + 4)? != b"TZif" {
+            return None;
+        }
+        let mut counts = [0; 6];
+        for (i, count) in counts.iter_mut().enumerate() {
+            let b = data.get(at + 20 + 4 * i..at + 24 + 4 * i)?;
+            *count = u32::from_be_bytes(b.try_into().ok()?) as usize;
+        }
+        Some(counts)
+    };
+    // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+    let [isut, isstd, leap, time, types, chars] = header(0)?;
+    let v1_len = time * 5 + types * 6 + chars + leap * 8 + isstd + isut;
+    let (at, counts, time_size) = match data.get(4) {
+        Some(b'2'..) => (44 + v1_len, header(44 + v1_len)?, 8),
+        _ => (0, [isut, isstd, leap, time, types, chars], 4),
+    };
+    let [isut, isstd, leap, time, types, chars] = counts;
+    let mut at = at + 44;
+    let mut take = |n: usize| -> Option<&[u8]> {
+        let b = data.get(at..at + n)?;
+        at += n;
+        Some(b)
+    };
+    let times: Vec<i64> = take(time * time_size)?
+        .chunks(time_size)
+        .map(|b| match time_size {
+            8 => i64::from_be_bytes(b.try_into().unwrap_or_default()),
+            _ => i32::from_be_bytes(b.try_into().unwrap_or_default()) as i64,
+        })
+        .collect();
+    let indices = take(time)?.to_vec();
+    let offsets: Vec<i64> = take(types * 6)?
+        .chunks(6)
+        .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as i64)
+        .collect();
+    take(chars + leap * (time_size + 4) + isstd + isut)?;
+    let transitions = times
+        .into_iter()
+        .zip(indices.into_iter().map(usize::from))
+        .filter(|&(_, i)| i < offsets.len())
+        .collect();
+    let footer = match time_size {
+        8 => data.get(at..).and_then(|f| std::str::from_utf8(f).ok()),
+        _ => None,
+    };
+    let after = footer
+        .map(|f| f.trim_matches('\n'))
+        .filter(|f| !f.is_empty())
+        .and_then(PosixRule::parse);
+    Some(Zone::Rules {
+        transitions,
+        offsets,
+        after,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 2 TZif file with the same data in both blocks
+    fn tzif(transitions: &[(i64, u8)], offsets: &[i32], footer: &str) -> Vec<u8> {
+        let block = |size: usize| {
+            let mut out = b"TZif2".to_vec();
+            out.extend([0; 15]);
+            let chars = 4;
+            for count in [0, 0, 0, transitions.len(), offsets.len(), chars] {
+                out.extend((count as u32).to_be_bytes());
+            }
+            for &(at, _) in transitions {
+                match size {
+                    8 => out.extend(at.to_be_bytes()),
+                    _ => out.extend((at as i32).to_be_bytes()),
+                }
+            }
+            out.extend(transitions.iter().map(|&(_, i)| i));
+            for (i, &offset) in offsets.iter().enumerate() {
+                out.extend(offset.to_be_bytes());
+                out.extend([(i > 0) as u8, 0]);
+            }
+            out.extend(b"XXX\0");
+            out
+        };
+        [block(4), block(8), format!("\n{}\n", footer).into_bytes()].concat()
+    }
+
+    /// America/New_York with the 2024 changes written out, and the rule for
+    /// the years after
+    fn new_york() -> Zone {
+        let data = tzif(
+            &[(1710054000, 1), (1730613600, 0)],
+            &[-18000, -14400],
+            "EST5EDT,M3.2.0,M11.1.0",
+        );
+        parse_tzif(&data).unwrap()
+    }
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn uses_the_transitions_of_the_file() {
+        let zone = new_york();
+        assert_eq!(
+            zone.to_utc(local("2024-01-15 12:00:00")),
+            utc("2024-01-15T17:00:00Z")
+        );
+        assert_eq!(
+            zone.to_utc(local("2024-07-01 12:00:00")),
+            utc("2024-07-01T16:00:00Z")
+        );
+        // before the first transition, the first offset
+        assert_eq!(
+            zone.to_utc(local("1999-07-01 12:00:00")),
+            utc("1999-07-01T17:00:00Z")
+        );
+    }
+
+    #[test]
+    fn times_skipped_by_a_change_keep_the_offset_before_it() {
+        let zone = new_york();
+        // 02:30 doesn't exist on 2024-03-10: clocks go from 02:00 EST to 03:00 EDT
+        assert_eq!(
+            zone.to_utc(local("2024-03-10 02:30:00")),
+            utc("2024-03-10T07:30:00Z")
+        );
+        assert_eq!(
+            zone.to_utc(local("2024-03-10 01:59:59")),
+            utc("2024-03-10T06:59:59Z")
+        );
+        assert_eq!(
+            zone.to_utc(local("2024-03-10 03:00:00")),
+            utc("2024-03-10T07:00:00Z")
+        );
+    }
+
+    #[test]
+    fn repeated_times_take_the_first_of_them() {
+        let zone = new_york();
+        // 01:30 happens twice on 2024-11-03, in EDT and then in EST
+        assert_eq!(
+            zone.to_utc(local("2024-11-03 01:30:00")),
+            utc("2024-11-03T05:30:00Z")
+        );
+        assert_eq!(
+            zone.to_utc(local("2024-11-03 02:00:00")),
+            utc("2024-11-03T07:00:00Z")
+        );
+    }
+
+    #[test]
+    fn follows_the_rule_after_the_last_transition() {
+        let zone = new_york();
+        assert_eq!(
+            zone.to_utc(local("2040-07-01 12:00:00")),
+            utc("2040-07-01T16:00:00Z")
+        );
+        assert_eq!(
+            zone.to_utc(local("2040-12-01 12:00:00")),
+            utc("2040-12-01T17:00:00Z")
+        );
+        // second Sunday of March 2040 is the 11th, first of November the 4th
+        assert_eq!(
+            zone.to_utc(local("2040-03-11 01:59:00")),
+            utc("2040-03-11T06:59:00Z")
+        );
+        assert_eq!(
+            zone.to_utc(local("2040-03-11 03:00:00")),
+            utc("2040-03-11T07:00:00Z")
+        );
+        assert_eq!(
+            zone.to_utc(local("2040-11-04 00:59:00")),
+            utc("2040-11-04T04:59:00Z")
+        );
+        assert_eq!(
+            zone.to_utc(local("2040-11-04 02:00:00")),
+            utc("2040-11-04T07:00:00Z")
+        );
+    }
+
+    #[test]
+    fn rules_spanning_the_new_year() {
+        let data = tzif(&[], &[36000], "AEST-10AEDT,M10.1.0,M4.1.0/3");
+        let sydney = parse_tzif(&data).unwrap();
+        assert_eq!(
+            sydney.to_utc(local("2040-07-01 12:00:00")),
+            utc("2040-07-01T02:00:00Z")
+        );
+        assert_eq!(
+            sydney.to_utc(local("2041-01-01 12:00:00")),
+            utc("2041-01-01T01:00:00Z")
+        );
+    }
+
+    #[test]
+    fn julian_and_ordinal_rule_days() {
+        // J60 is March 1 in every year, day 59 February 29 in leap years
+        let julian = PosixRule::parse("AAA0BBB,J60,J300").unwrap();
+        let ordinal = PosixRule::parse("AAA0BBB,59,300").unwrap();
+        let t = utc("2040-02-29T12:00:00Z").timestamp();
+        assert_eq!(julian.offset_at(t), 0);
+        assert_eq!(ordinal.offset_at(t), 3600);
+        let t = utc("2040-03-01T12:00:00Z").timestamp();
+        assert_eq!(julian.offset_at(t), 3600);
+        assert_eq!(ordinal.offset_at(t), 3600);
+        let t = utc("2040-03-01T01:00:00Z").timestamp();
+        assert_eq!(julian.offset_at(t), 0);
+        assert_eq!(ordinal.offset_at(t), 3600);
+    }
+
+    #[test]
+    fn fixed_offsets_and_names() {
+        assert!(matches!(Zone::parse("UTC"), Ok(Zone::Fixed(0))));
+        assert!(matches!(Zone::parse("+05:30"), Ok(Zone::Fixed(19800))));
+        assert!(matches!(Zone::parse("-0800"), Ok(Zone::Fixed(-28800))));
+        assert!(matches!(Zone::parse("+03"), Ok(Zone::Fixed(10800))));
+        assert!(fixed_offset("+15").is_none());
+        assert!(fixed_offset("+05:60").is_none());
+        let zone = Zone::Fixed(19800);
+        assert_eq!(
+            zone.to_utc(local("2024-01-01 05:30:00")),
+            utc("2024-01-01T00:00:00Z")
+        );
+        for name in ["../etc/passwd", "/etc/localtime", "", "Nowhere/Atlantis"] {
+            assert_eq!(
+                Zone::parse(name).err(),
+                Some(format!("unknown time zone {}", name))
+            );
+        }
+    }
+
+    #[test]
+    fn posix_rules_with_quoted_names_and_explicit_offsets() {
+        let rule = PosixRule::parse("<+0330>-3:30").unwrap();
+        assert_eq!((rule.std, rule.dst.is_none()), (12600, true));
+        let rule = PosixRule::parse("IST-1GMT0,M10.5.0,M3.5.0/1").unwrap();
+        assert_eq!(rule.std, 3600);
+        assert_eq!(rule.dst.map(|(dst, _, _)| dst), Some(0));
+        assert!(PosixRule::parse("EST5EDT,M3.2.0").is_none());
+    }
+}
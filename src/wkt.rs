@@ -0,0 +1,276 @@
+//! WKT geometries such as `POLYGON((30 10, 40 40, 20 40, 30 10))`, turned
+//! into GeoJSON for `geo_shape` fields
+
+use std::fmt::Write;
+
+/// Geometry types, by WKT keyword and GeoJSON name
+const KINDS: &[(&str, &str)] = &[
+    ("POINT", "Point"),
+    ("LINESTRING", "LineString"),
+    ("POLYGON", "Polygon"),
+    ("MULTIPOINT", "MultiPoint"),
+    ("MULTILINESTRING", "MultiLineString"),
+    ("MULTIPOLYGON", "MultiPolygon"),
+    ("GEOMETRYCOLLECTION", "GeometryCollection"),
+];
+
+/// Whether `s` starts like a WKT geometry, a cheap test before parsing
+pub fn looks_like(s: &str) -> bool {
+    let s = strip_srid(s);
+    let word = s
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or("");
+    KINDS.iter().any(|(k, _)| k.eq_ignore_ascii_case(word))
+}
+
+/// GeoJSON geometry object of a WKT (or EWKT, `SRID=4326;...`) value;
+/// `None` if it isn't one. Z coordinates are kept and M values dropped.
+/// `EMPTY` geometries have no GeoJSON form and aren't taken.
+pub fn to_geojson(s: &str) -> Option<String> {
+    let mut p = Parser {
+        rest: strip_srid(s),
+    };
+    let mut out = String::new();
+    p.geometry(&mut out)?;
+    p.rest.trim().is_empty().then_some(out)
+}
+
+fn strip_srid(s: &str) -> &str {
+    let s = s.trim_start();
+    match s.split_once(';') {
+        Some((srid, rest))
+            if srid.len() > 5
+                && srid
+                    .get(..5)
+                    .is_some_and(|p| p.eq_ignore_ascii_case("SRID=")) =>
+        {
+            rest
+        }
+        _ => s,
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    /// Consume `c` if it comes next
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn word(&mut self) -> &str {
+        self.skip_space();
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        word
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        self.skip_space();
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+            .unwrap_or(self.rest.len());
+        let (num, rest) = self.rest.split_at(end);
+        let n = num.parse::<f64>().ok().filter(|n| n.is_finite())?;
+        self.rest = rest;
+        Some(n)
+    }
+
+    fn geometry(&mut self, out: &mut String) -> Option<()> {
+        let word = self.word().to_ascii_uppercase();
+        let &(kind, name) = KINDS.iter().find(|(k, _)| *k == word)?;
+        let before = self.rest;
+        let (z, m) = match self.word().to_ascii_uppercase().as_str() {
+            "Z" => (true, false),
+            "M" => (false, true),
+            "ZM" => (true, true),
+            _ => {
+                self.rest = before;
+                (false, false)
+            }
+        };
+        let dims = Dims { z, m };
+        if kind == "GEOMETRYCOLLECTION" {
+            out.push_str("{\"type\":\"GeometryCollection\",\"geometries\":");
+            self.list(out, |p, out| p.geometry(out))?;
+        } else {
+            out.push_str("{\"type\":\"");
+            out.push_str(name);
+            out.push_str("\",\"coordinates\":");
+            let point = |p: &mut Self, out: &mut String| p.point(out, dims);
+            let ring = |p: &mut Self, out: &mut String| p.list(out, point);
+            match kind {
+                "POINT" => {
+                    self.eat('(').then_some(())?;
+                    self.point(out, dims)?;
+                    self.eat(')').then_some(())?;
+                }
+                "LINESTRING" => self.list(out, point)?,
+                "POLYGON" | "MULTILINESTRING" => self.list(out, ring)?,
+                "MULTIPOLYGON" => self.list(out, |p, out| p.list(out, ring))?,
+                // points may or may not be in parentheses of their own
+                _ => self.list(out, |p, out| {
+                    let inner = p.eat('(');
+                    p.point(out, dims)?;
+                    (!inner || p.eat(')')).then_some(())
+                })?,
+            }
+        }
+        out.push('}');
+        Some(())
+    }
+
+    /// `(item, item, ...)` as a JSON array
+    fn list(
+        &mut self,
+        out: &mut String,
+        mut item: impl FnMut(&mut Self, &mut String) -> Option<()>,
+    ) -> Option<()> {
+        self.eat('(').then_some(())?;
+        out.push('[');
+        loop {
+            item(self, out)?;
+            if !self.eat(',') {
+                break;
+            }
+            out.push(',');
+        }
+        out.push(']');
+        self.eat(')').then_some(())
+    }
+
+    /// `x y`, `x y z`, `x y m` or `x y z m` as `[x,y]` or `[x,y,z]`
+    fn point(&mut self, out: &mut String, dims: Dims) -> Option<()> {
+        let mut coords = Vec::with_capacity(4);
+        while coords.len() < 4
+            && let Some(n) = self.number()
+        {
+            coords.push(n);
+        }
+        let wanted = 2 + usize::from(dims.z) + usize::from(dims.m);
+        if coords.len() < 2 || ((dims.z || dims.m) && coords.len() != wanted) {
+            return None;
+        }
+        // without markers a third value is z and a fourth m
+        coords.truncate(if dims.m && !dims.z { 2 } else { 3 });
+        out.push('[');
+        for (i, c) in coords.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{}", c);
+        }
+        out.push(']');
+        Some(())
+    }
+}
+
+/// Coordinate markers after the geometry keyword
+#[derive(Clone, Copy)]
+struct Dims {
+    z: bool,
+    m: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_each_geometry_type() {
+        assert_eq!(
+            to_geojson("POINT (30 10)").unwrap(),
+            r#"{"type":"Point","coordinates":[30,10]}"#
+        );
+        assert_eq!(
+            to_geojson("LINESTRING (30 10, 10 30, 40 40)").unwrap(),
+            r#"{"type":"LineString","coordinates":[[30,10],[10,30],[40,40]]}"#
+        );
+        assert_eq!(
+            to_geojson("POLYGON ((30 10, 40 40, 20 40, 30 10))").unwrap(),
+            r#"{"type":"Polygon","coordinates":[[[30,10],[40,40],[20,40],[30,10]]]}"#
+        );
+        assert_eq!(
+            to_geojson("MULTIPOLYGON (((30 20, 45 40, 10 40, 30 20)), ((15 5, 40 10, 15 5)))")
+                .unwrap(),
+            r#"{"type":"MultiPolygon","coordinates":[[[[30,20],[45,40],[10,40],[30,20]]],[[[15,5],[40,10],[15,5]]]]}"#
+        );
+        assert_eq!(
+            to_geojson("GEOMETRYCOLLECTION (POINT (40 10), LINESTRING (10 10, 20 20))").unwrap(),
+            r#"{"type":"GeometryCollection","geometries":[{"type":"Point","coordinates":[40,10]},{"type":"LineString","coordinates":[[10,10],[20,20]]}]}"#
+        );
+    }
+
+    #[test]
+    fn multipoints_take_points_with_and_without_parentheses() {
+        let geojson = r#"{"type":"MultiPoint","coordinates":[[10,40],[40,30]]}"#;
+        assert_eq!(
+            to_geojson("MULTIPOINT ((10 40), (40 30))").unwrap(),
+            geojson
+        );
+        assert_eq!(to_geojson("MULTIPOINT (10 40, 40 30)").unwrap(), geojson);
+    }
+
+    #[test]
+    fn keeps_z_and_drops_m() {
+        assert_eq!(
+            to_geojson("POINT Z (1 2 3)").unwrap(),
+            r#"{"type":"Point","coordinates":[1,2,3]}"#
+        );
+        assert_eq!(
+            to_geojson("POINT M (1 2 4)").unwrap(),
+            r#"{"type":"Point","coordinates":[1,2]}"#
+        );
+        assert_eq!(
+            to_geojson("POINT ZM (1 2 3 4)").unwrap(),
+            r#"{"type":"Point","coordinates":[1,2,3]}"#
+        );
+        assert_eq!(to_geojson("POINT Z (1 2)"), None);
+    }
+
+    #[test]
+    fn strips_an_ewkt_srid() {
+        assert!(looks_like("SRID=4326;POINT(1 2)"));
+        assert_eq!(
+            to_geojson("srid=4326;point(1 2)").unwrap(),
+            r#"{"type":"Point","coordinates":[1,2]}"#
+        );
+    }
+
+    #[test]
+    fn rejects_what_is_not_wkt() {
+        assert!(!looks_like("hello"));
+        assert!(!looks_like(""));
+        assert_eq!(to_geojson("POINT EMPTY"), None);
+        assert_eq!(to_geojson("POINT (1 2"), None);
+        assert_eq!(to_geojson("POINT (1 2) trailing"), None);
+        assert_eq!(to_geojson("POLYGON ((1 x))"), None);
+    }
+
+    #[test]
+    fn non_ascii_text_before_a_semicolon_is_not_an_srid() {
+        // the fifth byte falls inside 'ß'
+        assert!(!looks_like("Größe;x"));
+        assert_eq!(to_geojson("Größe;x"), None);
+        assert!(!looks_like("ÄÖÜ;POINT(1 2)"));
+    }
+}
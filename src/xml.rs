@@ -0,0 +1,503 @@
+//! Minimal streaming XML reader for `--format xml`
+
+/// Pulls the elements at one path out of an XML document, one record at a
+/// time, flattened to (field, value) pairs. Attributes of the record are
+/// fields of the same name; child elements are fields named by their path
+/// below the record (`address.city`), their attributes likewise
+/// (`price.currency`). Repeated names get `_2`, `_3`, ... suffixes.
+#[derive(Clone)]
+pub struct Records {
+    path: Vec<String>,
+    /// Names of the elements currently open
+    stack: Vec<String>,
+}
+
+/// (field, value) pairs of one record
+pub type Fields = Vec<(String, String)>;
+
+enum Token {
+    Open {
+        name: String,
+        attrs: Vec<(String, String)>,
+        /// `<name/>`
+        empty: bool,
+    },
+    Close(String),
+    Text(String),
+}
+
+impl Records {
+    /// `record_path` is absolute, e.g. `/export/records/record`; `*` matches
+    /// any element name
+    pub fn new(record_path: &str) -> Result<Self, String> {
+        let path: Vec<String> = record_path
+            .strip_prefix('/')
+            .ok_or_else(|| format!("--record-path must start with /: {}", record_path))?
+            .split('/')
+            .map(str::to_string)
+            .collect();
+        if path.iter().any(String::is_empty) {
+            return Err(format!("invalid --record-path: {}", record_path));
+        }
+        Ok(Self {
+            path,
+            stack: Vec::new(),
+        })
+    }
+
+    /// Next record at or after `*pos`, with the offset of its start tag
+    pub fn next(
+        &mut self,
+        input: &[u8],
+        pos: &mut usize,
+    ) -> Result<Option<(usize, Fields)>, String> {
+        loop {
+            let start = *pos;
+            match next_token(input, pos)? {
+                None if self.stack.is_empty() => return Ok(None),
+                None => return Err(format!("unexpected end of XML inside <{}>", self.stack[0])),
+                Some(Token::Open { name, attrs, empty }) => {
+                    self.stack.push(name);
+                    if self.at_record() {
+                        let mut fields = Vec::new();
+                        for (k, v) in attrs {
+                            push_field(&mut fields, k, v);
+                        }
+                        if !empty {
+                            let name = self.stack.last().cloned().unwrap_or_default();
+                            element(input, pos, &name, "", &mut fields)?;
+                        }
+                        self.stack.pop();
+                        return Ok(Some((start, fields)));
+                    }
+                    if empty {
+                        self.stack.pop();
+                    }
+                }
+                Some(Token::Close(name)) => self.close(&name)?,
+                Some(Token::Text(_)) => {}
+            }
+        }
+    }
+
+    fn at_record(&self) -> bool {
+        self.stack.len() == self.path.len()
+            && self
+                .path
+                .iter()
+                .zip(&self.stack)
+                .all(|(p, s)| p == "*" || p == s)
+    }
+
+    fn close(&mut self, name: &str) -> Result<(), String> {
+        match self.stack.pop() {
+            Some(open) if open == name => Ok(()),
+            Some(open) => Err(format!("XML </{}> doesn't close <{}>", name, open)),
+            None => Err(format!("XML </{}> without opening tag", name)),
+        }
+    }
+
+    /// Every field name the records of `input` use, in order of first
+    /// appearance
+    pub fn field_names(&self, input: &[u8]) -> Result<Vec<String>, String> {
+        let mut records = Records {
+            path: self.path.clone(),
+            stack: Vec::new(),
+        };
+        let mut pos = 0;
+        let mut names: Vec<String> = Vec::new();
+        while let Some((_, fields)) = records.next(input, &mut pos)? {
+            for (name, _) in fields {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Read the content of element `name` up to its closing tag. Text goes to
+/// field `prefix`, or to `name` itself for the record element.
+fn element(
+    input: &[u8],
+    pos: &mut usize,
+    name: &str,
+    prefix: &str,
+    fields: &mut Fields,
+) -> Result<(), String> {
+    let mut text = String::new();
+    let before = fields.len();
+    loop {
+        match next_token(input, pos)? {
+            None => return Err(format!("unexpected end of XML inside <{}>", name)),
+            Some(Token::Text(t)) => text.push_str(&t),
+            Some(Token::Close(close)) if close == name => break,
+            Some(Token::Close(close)) => {
+                return Err(format!("XML </{}> doesn't close <{}>", close, name));
+            }
+            Some(Token::Open {
+                name: child,
+                attrs,
+                empty,
+            }) => {
+                let field = if prefix.is_empty() {
+                    child.clone()
+                } else {
+                    format!("{}.{}", prefix, child)
+                };
+                for (k, v) in attrs {
+                    push_field(fields, format!("{}.{}", field, k), v);
+                }
+                if empty {
+                    push_field(fields, field, String::new());
+                } else {
+                    element(input, pos, &child, &field, fields)?;
+                }
+            }
+        }
+    }
+    let text = text.trim();
+    let has_children = fields.len() > before;
+    if !text.is_empty() || (!prefix.is_empty() && !has_children) {
+        let field = if prefix.is_empty() { name } else { prefix };
+        push_field(fields, field.to_string(), text.to_string());
+    }
+    Ok(())
+}
+
+fn push_field(fields: &mut Fields, name: String, value: String) {
+    let mut candidate = name.clone();
+    let mut n = 2;
+    while fields.iter().any(|(k, _)| *k == candidate) {
+        candidate = format!("{}_{}", name, n);
+        n += 1;
+    }
+    fields.push((candidate, value));
+}
+
+fn next_token(input: &[u8], pos: &mut usize) -> Result<Option<Token>, String> {
+    let rest = &input[*pos..];
+    if rest.is_empty() {
+        return Ok(None);
+    }
+    if rest[0] != b'<' {
+        let end = rest.iter().position(|&b| b == b'<').unwrap_or(rest.len());
+        *pos += end;
+        return Ok(Some(Token::Text(unescape(&String::from_utf8_lossy(
+            &rest[..end],
+        )))));
+    }
+    let skip_to = |pos: &mut usize, end: &[u8], what: &str| -> Result<(), String> {
+        let at = find(&input[*pos..], end).ok_or_else(|| format!("unterminated XML {}", what))?;
+        *pos += at + end.len();
+        Ok(())
+    };
+    if rest.starts_with(b"<?") {
+        skip_to(pos, b"?>", "processing instruction")?;
+        return next_token(input, pos);
+    }
+    if rest.starts_with(b"<!--") {
+        skip_to(pos, b"-->", "comment")?;
+        return next_token(input, pos);
+    }
+    if rest.starts_with(b"<![CDATA[") {
+        let body = &rest[9..];
+        let end = find(body, b"]]>").ok_or("unterminated XML CDATA section")?;
+        *pos += 9 + end + 3;
+        return Ok(Some(Token::Text(
+            String::from_utf8_lossy(&body[..end]).into_owned(),
+        )));
+    }
+    if rest.starts_with(b"<!") {
+        // DOCTYPE, possibly with an internal subset in brackets
+        let mut depth = 0;
+        for (i, &b) in rest.iter().enumerate() {
+            match b {
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                b'>' if depth == 0 => {
+                    *pos += i + 1;
+                    return next_token(input, pos);
+                }
+                _ => {}
+            }
+        }
+        return Err("unterminated XML declaration".into());
+    }
+
+    // start or end tag, up to the first '>' outside quotes
+    let mut quote = None;
+    let end = rest
+        .iter()
+        .position(|&b| match quote {
+            Some(q) if b == q => {
+                quote = None;
+                false
+            }
+            Some(_) => false,
+            None if b == b'"' || b == b'\'' => {
+                quote = Some(b);
+                false
+            }
+            None => b == b'>',
+        })
+        .ok_or("unterminated XML tag")?;
+    *pos += end + 1;
+    let tag = String::from_utf8_lossy(&rest[1..end]);
+    if let Some(name) = tag.strip_prefix('/') {
+        return Ok(Some(Token::Close(name.trim().to_string())));
+    }
+    let (tag, empty) = match tag.strip_suffix('/') {
+        Some(t) => (t, true),
+        None => (&*tag, false),
+    };
+    let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+    let name = tag[..name_end].to_string();
+    if name.is_empty() {
+        return Err("XML tag without a name".into());
+    }
+    Ok(Some(Token::Open {
+        attrs: attributes(&tag[name_end..], &name)?,
+        name,
+        empty,
+    }))
+}
+
+/// `a="1" b='2'`
+fn attributes(mut s: &str, tag: &str) -> Result<Vec<(String, String)>, String> {
+    let mut attrs = Vec::new();
+    loop {
+        s = s.trim_start();
+        if s.is_empty() {
+            return Ok(attrs);
+        }
+        let bad = || format!("malformed attribute in <{}>", tag);
+        let (name, rest) = s.split_once('=').ok_or_else(bad)?;
+        let rest = rest.trim_start();
+        let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'');
+        let quote = quote.ok_or_else(bad)?;
+        let (value, rest) = rest[1..].split_once(quote).ok_or_else(bad)?;
+        attrs.push((name.trim().to_string(), unescape(value)));
+        s = rest;
+    }
+}
+
+/// Decode the predefined and numeric character entities
+fn unescape(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').and_then(|semi| {
+            let entity = &rest[1..semi];
+            let ch = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|h| u32::from_str_radix(h, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            ch.map(|c| (c, semi))
+        });
+        match decoded {
+            Some((c, semi)) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every record at `path` in `xml`
+    fn records(path: &str, xml: &str) -> Result<Vec<Fields>, String> {
+        let mut records = Records::new(path)?;
+        let mut pos = 0;
+        let mut out = Vec::new();
+        while let Some((_, fields)) = records.next(xml.as_bytes(), &mut pos)? {
+            out.push(fields);
+        }
+        Ok(out)
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Fields {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn flattens_the_records_at_the_path() {
+        let xml = r#"<?xml version="1.0"?>
+            <export>
+              <meta><record>not at the path</record></meta>
+              <records>
+                <record id="1">
+                  <name>Ann</name>
+                  <address><city>Oslo</city></address>
+                  <price currency="EUR">9.5</price>
+                  <note/>
+                </record>
+                <record id="2"/>
+              </records>
+            </export>"#;
+        assert_eq!(
+            records("/export/records/record", xml).unwrap(),
+            [
+                fields(&[
+                    ("id", "1"),
+                    ("name", "Ann"),
+                    ("address.city", "Oslo"),
+                    ("price.currency", "EUR"),
+                    ("price", "9.5"),
+                    ("note", ""),
+                ]),
+                fields(&[("id", "2")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_of_a_record_is_named_after_it() {
+        assert_eq!(
+            records("/list/item", "<list><item>a</item><item>b</item></list>").unwrap(),
+            [fields(&[("item", "a")]), fields(&[("item", "b")])]
+        );
+    }
+
+    #[test]
+    fn a_star_matches_any_element_name() {
+        let xml = "<root><a><item>1</item></a><item>not deep enough</item>\
+                   <b><item>2</item><other/></b></root>";
+        assert_eq!(
+            records("/root/*/item", xml).unwrap(),
+            [fields(&[("item", "1")]), fields(&[("item", "2")])]
+        );
+        assert_eq!(
+            records("/*", "<x a='1'/>").unwrap(),
+            [fields(&[("a", "1")])]
+        );
+    }
+
+    #[test]
+    fn repeated_names_get_suffixes() {
+        let xml = r#"<r id="1"><id>2</id><tag>a</tag><tag>b</tag><tag>c</tag></r>"#;
+        assert_eq!(
+            records("/r", xml).unwrap(),
+            [fields(&[
+                ("id", "1"),
+                ("id_2", "2"),
+                ("tag", "a"),
+                ("tag_2", "b"),
+                ("tag_3", "c"),
+            ])]
+        );
+    }
+
+    #[test]
+    fn decodes_cdata_and_entities() {
+        let xml = r#"<!DOCTYPE r [<!ENTITY x "y">]>
+            <!-- <r>commented out</r> -->
+            <r>
+              <a><![CDATA[<b> & &amp; </b>]]></a>
+              <b>&lt;x&gt; &amp; &#65;&#x42; &bogus; &</b>
+              <c t="&quot;q&quot; &apos;s&apos;"/>
+            </r>"#;
+        assert_eq!(
+            records("/r", xml).unwrap(),
+            [fields(&[
+                ("a", "<b> & &amp; </b>"),
+                ("b", "<x> & AB &bogus; &"),
+                ("c.t", r#""q" 's'"#),
+                ("c", ""),
+            ])]
+        );
+    }
+
+    #[test]
+    fn reports_where_records_start_and_their_field_names() {
+        let xml = "<l>\n<r a='1'/>\n<r><b>2</b></r>\n</l>";
+        let mut records = Records::new("/l/r").unwrap();
+        let mut pos = 0;
+        let (start, _) = records.next(xml.as_bytes(), &mut pos).unwrap().unwrap();
+        assert_eq!(start, 4);
+        let (start, _) = records.next(xml.as_bytes(), &mut pos).unwrap().unwrap();
+        assert_eq!(start, 15);
+        assert!(records.next(xml.as_bytes(), &mut pos).unwrap().is_none());
+        assert_eq!(records.field_names(xml.as_bytes()).unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn rejects_malformed_xml() {
+        let err = |path: &str, xml: &str| records(path, xml).unwrap_err();
+        assert_eq!(
+            err("/root/r", "<root><r><a>1</b></r></root>"),
+            "XML </b> doesn't close <a>"
+        );
+        assert_eq!(
+            err("/root/r", "<root><x></y></root>"),
+            "XML </y> doesn't close <x>"
+        );
+        assert_eq!(err("/root/r", "</root>"), "XML </root> without opening tag");
+        assert_eq!(
+            err("/root/r", "<root><r><a>1</a>"),
+            "unexpected end of XML inside <r>"
+        );
+        assert_eq!(
+            err("/root/r", "<root>"),
+            "unexpected end of XML inside <root>"
+        );
+        assert_eq!(err("/root/r", "<root><r"), "unterminated XML tag");
+        assert_eq!(err("/root/r", "<root><!-- x"), "unterminated XML comment");
+        assert_eq!(
+            err("/root/r", "<root><r><![CDATA[x</r>"),
+            "unterminated XML CDATA section"
+        );
+        assert_eq!(
+            err("/root/r", "<root><r a=1/></root>"),
+            "malformed attribute in <r>"
+        );
+        assert_eq!(
+            err("/root/r", "<root>< /></root>"),
+            "XML tag without a name"
+        );
+    }
+
+    #[test]
+    fn record_paths_are_absolute() {
+        assert!(Records::new("/export/record").is_ok());
+        assert_eq!(
+            Records::new("export/record").err().unwrap(),
+            "--record-path must start with /: export/record"
+        );
+        assert_eq!(
+            Records::new("/export//record").err().unwrap(),
+            "invalid --record-path: /export//record"
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! The column statistics printed at the end of an import
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A file in a directory of its own under the system temp dir
+fn scratch(name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("elastic_importer-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn grouped_import_counts_the_input_columns() {
+    let input = scratch(
+        "orders.csv",
+        "order,item,qty\n1,apple,2\n1,pear,\n2,plum,x\n",
+    );
+    let output = input.with_extension("ndjson");
+    let run = Command::new(env!("CARGO_BIN_EXE_elastic_importer"))
+        .arg("import")
+        .arg(&input)
+        .args(["--index", "orders", "--group-by", "order"])
+        .args(["--nest", "items:item,qty", "--type", "qty=long"])
+        .arg("--output")
+        .arg(&output)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(run.status.success(), "{}", stderr);
+    let lines: Vec<&str> = stderr
+        .lines()
+        .skip_while(|l| !l.ends_with("Column statistics:"))
+        .skip(1)
+        .take(3)
+        .map(|l| l.split_once("] ").map_or(l, |(_, line)| line))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "  order: long, 0 empty, 1 to 2",
+            "  item: keyword, 0 empty",
+            "  qty: long, 1 empty, 1 not a valid long, 2 to 2",
+        ]
+    );
+    let docs = std::fs::read_to_string(&output).unwrap();
+    assert_eq!(docs.lines().count(), 4);
+    std::fs::remove_dir_all(input.parent().unwrap()).unwrap();
+}